@@ -0,0 +1,86 @@
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use crate::{compile::Backend, eval::Interpreter, lexer, parser::Parser};
+
+/// Timings of every run of a single phase.
+struct Phase {
+    name: &'static str,
+    samples: Vec<Duration>,
+}
+
+impl Phase {
+    fn new(name: &'static str) -> Phase {
+        Phase {
+            name,
+            samples: Vec::new(),
+        }
+    }
+
+    fn min(&self) -> Duration {
+        self.samples.iter().min().copied().unwrap_or_default()
+    }
+
+    fn mean(&self) -> Duration {
+        self.samples.iter().sum::<Duration>() / self.samples.len().max(1) as u32
+    }
+}
+
+/// Runs the script `iterations` times, timing each phase separately, and
+/// prints min/mean per phase. Program output is discarded.
+pub fn run(source: &str, iterations: usize, backend: Backend) -> i32 {
+    let mut tokenize = Phase::new("tokenize");
+    let mut parse = Phase::new("parse");
+    let mut evaluate = Phase::new("evaluate");
+
+    for _ in 0..iterations {
+        let start = Instant::now();
+        let (tokens, errors) = lexer::scan(source, false);
+        tokenize.samples.push(start.elapsed());
+        if !errors.is_empty() {
+            for error in errors {
+                eprintln!("{}", error);
+            }
+            return 65;
+        }
+
+        let start = Instant::now();
+        let stmts = Parser::new(tokens).parse2();
+        parse.samples.push(start.elapsed());
+        let stmts = match stmts {
+            Ok(stmts) => stmts,
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("{}", error);
+                }
+                return 65;
+            }
+        };
+
+        let mut interpreter = Interpreter::new()
+            .with_output(io::sink())
+            .with_backend(backend)
+            .with_warnings(false);
+        let start = Instant::now();
+        let result = interpreter.execute_program(&stmts);
+        evaluate.samples.push(start.elapsed());
+        if let Err(error) = result {
+            eprintln!("{}", error);
+            return 70;
+        }
+    }
+
+    println!("{} iterations, {:?} backend", iterations, backend);
+    println!("{:<10} {:>12} {:>12}", "phase", "min", "mean");
+    for phase in [&tokenize, &parse, &evaluate] {
+        println!(
+            "{:<10} {:>12} {:>12}",
+            phase.name,
+            format!("{:.3?}", phase.min()),
+            format!("{:.3?}", phase.mean())
+        );
+    }
+    0
+}
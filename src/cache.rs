@@ -0,0 +1,574 @@
+use std::{
+    cell::Cell,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{
+    expr::{Assign, Binary, Call, Expr, Get, Grouping, Literal, LiteralValue, Unary, Variable},
+    number::Number,
+    stmt::{Block, Expression, Func, If, Import, Print, Return, Stmt, Var, While},
+    symbol::Symbol,
+    token::{Span, Token, TokenValue},
+};
+
+/// Directory, next to the source file, holding its cached ASTs.
+const DIR: &str = ".lox-cache";
+
+/// Identifies the encoding below; bump it whenever the encoding or the AST
+/// changes shape.
+const MAGIC: &[u8] = b"LOXAST4\0";
+
+/// The parsed statements of `source`, read from `path`'s cache if it holds
+/// them for exactly this source, this interpreter version and this mode:
+/// with or without `extensions`, which change what parses.
+pub fn load(path: &Path, source: &str, extensions: bool) -> Option<Vec<Stmt>> {
+    let bytes = fs::read(entry(path, source, extensions)?).ok()?;
+    let mut decoder = Decoder { bytes: &bytes };
+    if decoder.take(MAGIC.len())? != MAGIC
+        || decoder.string()? != env!("CARGO_PKG_VERSION")
+        || decoder.usize()? != source.len()
+    {
+        return None;
+    }
+    decode(decoder.bytes)
+}
+
+/// Caches the parsed statements of `source` for `load`. Failing to write
+/// the cache is not an error; the file is just parsed again next time.
+pub fn store(path: &Path, source: &str, extensions: bool, stmts: &[Stmt]) {
+    let Some(entry) = entry(path, source, extensions) else {
+        return;
+    };
+    let mut encoder = Encoder::default();
+    encoder.bytes.extend_from_slice(MAGIC);
+    encoder.string(env!("CARGO_PKG_VERSION"));
+    encoder.usize(source.len());
+    encoder.bytes.extend(encode(stmts));
+    if let Some(dir) = entry.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    // Written under another name and renamed into place, so a concurrent
+    // run never reads half an entry.
+    let partial = entry.with_extension(format!("{}.tmp", std::process::id()));
+    if fs::write(&partial, &encoder.bytes).is_ok() {
+        let _ = fs::rename(&partial, &entry);
+    }
+}
+
+/// Encodes statements as `store` does, for `decode` to rebuild: unlike the
+/// statements, the bytes can be sent to another thread.
+pub fn encode(stmts: &[Stmt]) -> Vec<u8> {
+    let mut encoder = Encoder::default();
+    encoder.usize(stmts.len());
+    for stmt in stmts {
+        encoder.stmt(stmt);
+    }
+    encoder.bytes
+}
+
+/// The statements `encode` encoded.
+pub fn decode(bytes: &[u8]) -> Option<Vec<Stmt>> {
+    let mut decoder = Decoder { bytes };
+    let count = decoder.count()?;
+    (0..count).map(|_| decoder.stmt()).collect()
+}
+
+/// The cache file for `source`, named after its FNV-1a hash and marked when
+/// parsed with `extensions`.
+fn entry(path: &Path, source: &str, extensions: bool) -> Option<PathBuf> {
+    let hash = source.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    let dir = path.parent()?.join(DIR);
+    let mode = if extensions { ".ext" } else { "" };
+    Some(dir.join(format!("{:016x}{}.ast", hash, mode)))
+}
+
+/// Token values without a payload, encoded as their index here.
+const SIMPLE_TOKENS: [TokenValue; 44] = [
+    TokenValue::LeftParen,
+    TokenValue::RightParen,
+    TokenValue::LeftBrace,
+    TokenValue::RightBrace,
+    TokenValue::Colon,
+    TokenValue::Comma,
+    TokenValue::Dot,
+    TokenValue::Minus,
+    TokenValue::Plus,
+    TokenValue::Question,
+    TokenValue::Semicolon,
+    TokenValue::Slash,
+    TokenValue::Star,
+    TokenValue::Bang,
+    TokenValue::BangEqual,
+    TokenValue::Equal,
+    TokenValue::EqualEqual,
+    TokenValue::Greater,
+    TokenValue::GreaterEqual,
+    TokenValue::Less,
+    TokenValue::LessEqual,
+    TokenValue::And,
+    TokenValue::Break,
+    TokenValue::Class,
+    TokenValue::Else,
+    TokenValue::False,
+    TokenValue::Fun,
+    TokenValue::For,
+    TokenValue::If,
+    TokenValue::Import,
+    TokenValue::Nil,
+    TokenValue::Or,
+    TokenValue::Print,
+    TokenValue::Return,
+    TokenValue::Super,
+    TokenValue::This,
+    TokenValue::True,
+    TokenValue::Var,
+    TokenValue::While,
+    TokenValue::Eof,
+    TokenValue::PipeGreater,
+    TokenValue::QuestionDot,
+    TokenValue::QuestionQuestion,
+    TokenValue::In,
+];
+
+// Tags for the token values with a payload, past the end of `SIMPLE_TOKENS`.
+const IDENTIFIER: u8 = 100;
+const STRING: u8 = 101;
+const NUMBER: u8 = 102;
+
+#[derive(Default)]
+struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    /// LEB128, so the small numbers that dominate take a byte.
+    fn usize(&mut self, mut n: usize) {
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                self.bytes.push(byte);
+                return;
+            }
+            self.bytes.push(byte | 0x80);
+        }
+    }
+
+    fn string(&mut self, s: &str) {
+        self.usize(s.len());
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    fn f64(&mut self, n: f64) {
+        self.bytes.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn span(&mut self, span: Span) {
+        self.usize(span.start);
+        self.usize(span.end);
+        self.usize(span.line);
+    }
+
+    fn token(&mut self, token: &Token) {
+        match &token.value {
+            TokenValue::Identifier(_) => self.bytes.push(IDENTIFIER),
+            TokenValue::String(s) => {
+                self.bytes.push(STRING);
+                self.string(s);
+            }
+            TokenValue::Number(n) => {
+                self.bytes.push(NUMBER);
+                self.f64(n.0);
+            }
+            value => {
+                let index = SIMPLE_TOKENS.iter().position(|simple| simple == value);
+                self.bytes
+                    .push(index.expect("every simple token is listed") as u8);
+            }
+        }
+        self.string(&token.lexeme);
+        self.usize(token.line);
+        self.usize(token.start);
+    }
+
+    fn option<T>(&mut self, value: Option<&T>, encode: impl FnOnce(&mut Self, &T)) {
+        match value {
+            Some(value) => {
+                self.bytes.push(1);
+                encode(self, value);
+            }
+            None => self.bytes.push(0),
+        }
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        self.usize(stmts.len());
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Print(print) => {
+                self.bytes.push(0);
+                self.span(print.span);
+                self.expr(&print.expr);
+            }
+            Stmt::Expression(expression) => {
+                self.bytes.push(1);
+                self.span(expression.span);
+                self.expr(&expression.expr);
+            }
+            Stmt::Var(var) => {
+                self.bytes.push(2);
+                self.span(var.span);
+                self.token(&var.name);
+                self.option(var.initializer.as_deref(), Self::expr);
+            }
+            Stmt::Block(block) => {
+                self.bytes.push(3);
+                self.span(block.span);
+                self.stmts(&block.statements);
+            }
+            Stmt::If(if_stmt) => {
+                self.bytes.push(4);
+                self.span(if_stmt.span);
+                self.expr(&if_stmt.condition);
+                self.stmt(&if_stmt.then_branch);
+                self.option(if_stmt.else_branch.as_deref(), Self::stmt);
+            }
+            Stmt::While(while_stmt) => {
+                self.bytes.push(5);
+                self.span(while_stmt.span);
+                self.expr(&while_stmt.condition);
+                self.stmt(&while_stmt.body);
+            }
+            Stmt::Func(func) => {
+                self.bytes.push(6);
+                self.span(func.span);
+                self.token(&func.name);
+                self.usize(func.params.len());
+                for (param, ty) in func.params.iter().zip(&func.types) {
+                    self.token(param);
+                    self.option(ty.as_ref(), Self::token);
+                }
+                self.stmts(&func.body);
+            }
+            Stmt::Return(ret) => {
+                self.bytes.push(7);
+                self.span(ret.span);
+                self.option(ret.value.as_ref(), Self::expr);
+            }
+            Stmt::Import(import) => {
+                self.bytes.push(8);
+                self.span(import.span);
+                self.token(&import.keyword);
+                self.token(&import.path);
+                self.option(import.alias.as_ref(), Self::token);
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Literal(literal) => {
+                self.bytes.push(0);
+                self.span(literal.span);
+                match &literal.value {
+                    LiteralValue::String(s) => {
+                        self.bytes.push(0);
+                        self.string(s);
+                    }
+                    LiteralValue::Number(n) => {
+                        self.bytes.push(1);
+                        self.f64(n.0);
+                    }
+                    LiteralValue::Integer(n) => {
+                        self.bytes.push(2);
+                        self.bytes.extend_from_slice(&n.to_le_bytes());
+                    }
+                    LiteralValue::True => self.bytes.push(3),
+                    LiteralValue::False => self.bytes.push(4),
+                    LiteralValue::Nil => self.bytes.push(5),
+                }
+            }
+            Expr::Grouping(grouping) => {
+                self.bytes.push(1);
+                self.span(grouping.span);
+                self.expr(&grouping.expr);
+            }
+            Expr::Unary(unary) => {
+                self.bytes.push(2);
+                self.span(unary.span);
+                self.token(&unary.operator);
+                self.expr(&unary.right);
+            }
+            Expr::Binary(binary) => {
+                self.bytes.push(3);
+                self.span(binary.span);
+                self.expr(&binary.left);
+                self.token(&binary.operator);
+                self.expr(&binary.right);
+            }
+            Expr::Assign(assign) => {
+                self.bytes.push(4);
+                self.span(assign.span);
+                self.token(&assign.name);
+                self.expr(&assign.value);
+            }
+            Expr::Variable(variable) => {
+                self.bytes.push(5);
+                self.span(variable.span);
+                self.token(&variable.name);
+            }
+            Expr::Call(call) => {
+                self.bytes.push(6);
+                self.span(call.span);
+                self.expr(&call.callee);
+                self.usize(call.args.len());
+                for (arg, name) in call.args.iter().zip(&call.names) {
+                    self.option(name.as_ref(), Self::token);
+                    self.expr(arg);
+                }
+                self.token(&call.paren);
+            }
+            Expr::Get(get) => {
+                self.bytes.push(7);
+                self.span(get.span);
+                self.expr(&get.object);
+                self.token(&get.name);
+                self.bytes.push(get.optional as u8);
+            }
+        }
+    }
+}
+
+/// Reads what `Encoder` wrote. Every method returns `None` on malformed
+/// input, which `load` treats as a cache miss. Resolver slots are left
+/// empty, as the parser leaves them.
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if n > self.bytes.len() {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn usize(&mut self) -> Option<usize> {
+        let mut n = 0usize;
+        for shift in (0..usize::BITS).step_by(7) {
+            let byte = self.byte()?;
+            n |= usize::from(byte & 0x7f).checked_shl(shift)?;
+            if byte & 0x80 == 0 {
+                return Some(n);
+            }
+        }
+        None
+    }
+
+    /// The length of a list. Every item takes at least a byte, so a length
+    /// past the end of the input is corrupt, and fails here rather than
+    /// making a huge allocation.
+    fn count(&mut self) -> Option<usize> {
+        let count = self.usize()?;
+        (count <= self.bytes.len()).then_some(count)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.usize()?;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+
+    fn f64(&mut self) -> Option<f64> {
+        Some(f64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn span(&mut self) -> Option<Span> {
+        Some(Span {
+            start: self.usize()?,
+            end: self.usize()?,
+            line: self.usize()?,
+        })
+    }
+
+    fn token(&mut self) -> Option<Token> {
+        let tag = self.byte()?;
+        let payload = match tag {
+            STRING => Some(TokenValue::String(self.string()?)),
+            NUMBER => Some(TokenValue::Number(Number(self.f64()?))),
+            _ => None,
+        };
+        let lexeme = self.string()?;
+        let value = match (tag, payload) {
+            (_, Some(value)) => value,
+            (IDENTIFIER, None) => TokenValue::Identifier(Symbol::intern(&lexeme)),
+            (index, None) => SIMPLE_TOKENS.get(usize::from(index))?.clone(),
+        };
+        Some(Token::new(value, lexeme, self.usize()?, self.usize()?))
+    }
+
+    fn option<T>(&mut self, decode: impl FnOnce(&mut Self) -> Option<T>) -> Option<Option<T>> {
+        match self.byte()? {
+            0 => Some(None),
+            1 => Some(Some(decode(self)?)),
+            _ => None,
+        }
+    }
+
+    fn stmts(&mut self) -> Option<Vec<Stmt>> {
+        let count = self.count()?;
+        (0..count).map(|_| self.stmt()).collect()
+    }
+
+    fn stmt(&mut self) -> Option<Stmt> {
+        let tag = self.byte()?;
+        let span = self.span()?;
+        let stmt = match tag {
+            0 => Stmt::Print(Print {
+                span,
+                expr: Box::new(self.expr()?),
+            }),
+            1 => Stmt::Expression(Expression {
+                span,
+                expr: Box::new(self.expr()?),
+            }),
+            2 => Stmt::Var(Var {
+                span,
+                name: self.token()?,
+                initializer: self.option(|d| d.expr().map(Box::new))?,
+                slot: Cell::default(),
+            }),
+            3 => Stmt::Block(Block {
+                span,
+                statements: self.stmts()?,
+            }),
+            4 => Stmt::If(If {
+                span,
+                condition: Box::new(self.expr()?),
+                then_branch: Box::new(self.stmt()?),
+                else_branch: self.option(|d| d.stmt().map(Box::new))?,
+            }),
+            5 => Stmt::While(While {
+                span,
+                condition: Box::new(self.expr()?),
+                body: Box::new(self.stmt()?),
+            }),
+            6 => {
+                let name = self.token()?;
+                let count = self.count()?;
+                let mut params = Vec::with_capacity(count);
+                let mut types = Vec::with_capacity(count);
+                for _ in 0..count {
+                    params.push(self.token()?);
+                    types.push(self.option(Self::token)?);
+                }
+                Stmt::Func(Func {
+                    span,
+                    name,
+                    params,
+                    types,
+                    body: Rc::from(self.stmts()?),
+                    slot: Cell::default(),
+                })
+            }
+            7 => Stmt::Return(Return {
+                span,
+                value: self.option(Self::expr)?,
+            }),
+            8 => Stmt::Import(Import {
+                span,
+                keyword: self.token()?,
+                path: self.token()?,
+                alias: self.option(Self::token)?,
+                slot: Cell::default(),
+            }),
+            _ => return None,
+        };
+        Some(stmt)
+    }
+
+    fn expr(&mut self) -> Option<Expr> {
+        let tag = self.byte()?;
+        let span = self.span()?;
+        let expr = match tag {
+            0 => {
+                let value = match self.byte()? {
+                    0 => LiteralValue::String(Rc::new(self.string()?)),
+                    1 => LiteralValue::Number(Number(self.f64()?)),
+                    2 => LiteralValue::Integer(i64::from_le_bytes(self.take(8)?.try_into().ok()?)),
+                    3 => LiteralValue::True,
+                    4 => LiteralValue::False,
+                    5 => LiteralValue::Nil,
+                    _ => return None,
+                };
+                Expr::Literal(Literal { value, span })
+            }
+            1 => Expr::Grouping(Grouping {
+                expr: Box::new(self.expr()?),
+                span,
+            }),
+            2 => Expr::Unary(Unary {
+                operator: self.token()?,
+                right: Box::new(self.expr()?),
+                span,
+            }),
+            3 => Expr::Binary(Binary {
+                left: Box::new(self.expr()?),
+                operator: self.token()?,
+                right: Box::new(self.expr()?),
+                span,
+            }),
+            4 => Expr::Assign(Assign {
+                name: self.token()?,
+                value: Box::new(self.expr()?),
+                local: Cell::default(),
+                span,
+            }),
+            5 => Expr::Variable(Variable {
+                name: self.token()?,
+                local: Cell::default(),
+                span,
+            }),
+            6 => {
+                let callee = Box::new(self.expr()?);
+                let count = self.count()?;
+                let mut args = Vec::with_capacity(count);
+                let mut names = Vec::with_capacity(count);
+                for _ in 0..count {
+                    names.push(self.option(Self::token)?);
+                    args.push(self.expr()?);
+                }
+                Expr::Call(Call {
+                    callee,
+                    args,
+                    names,
+                    paren: self.token()?,
+                    span,
+                })
+            }
+            7 => Expr::Get(Get {
+                object: Box::new(self.expr()?),
+                name: self.token()?,
+                optional: self.byte()? == 1,
+                span,
+            }),
+            _ => return None,
+        };
+        Some(expr)
+    }
+}
@@ -0,0 +1,346 @@
+use std::{rc::Rc, str::FromStr};
+
+use anyhow::{Error, Result};
+
+use crate::{
+    eval::{chained, EvalError, Interpreter, Value},
+    expr::{Assign, Binary, Call, Expr, Get, Grouping, Literal, Unary, Variable},
+    stmt::{Block, Expression, If, Print, Return, Stmt, StmtVisitor, Var, While},
+    token::TokenValue,
+};
+
+/// How the interpreter executes statements.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Backend {
+    /// Walks the AST with the visitors.
+    #[default]
+    Tree,
+    /// Lowers the AST into closures once, then runs those; see `compile`.
+    Closures,
+}
+
+impl FromStr for Backend {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tree" => Ok(Backend::Tree),
+            "closures" => Ok(Backend::Closures),
+            _ => Err(()),
+        }
+    }
+}
+
+pub type CompiledExpr = Box<dyn Fn(&mut Interpreter) -> Result<Value, Error>>;
+pub type CompiledStmt = Box<dyn Fn(&mut Interpreter) -> Result<(), Error>>;
+/// A compiled function body along with the body itself, kept alive so its
+/// address can serve as a cache key.
+pub type CompiledBody = (Rc<[Stmt]>, Rc<[CompiledStmt]>);
+
+/// Lowers resolved statements into closures that do what the tree-walker's
+/// visitors would, without matching on the node type at every step. The
+/// semantics live in the interpreter's helper methods, shared by both
+/// backends; `interpreter` is only consulted for settings fixed at startup.
+pub fn compile(stmts: &[Stmt], interpreter: &Interpreter) -> Rc<[CompiledStmt]> {
+    stmts
+        .iter()
+        .map(|stmt| compile_stmt(stmt, interpreter))
+        .collect()
+}
+
+fn compile_stmt(stmt: &Stmt, interpreter: &Interpreter) -> CompiledStmt {
+    let run: CompiledStmt = match stmt {
+        Stmt::Print(Print { expr, .. }) => {
+            let expr = compile_expr(expr, interpreter);
+            Box::new(move |i| {
+                let value = expr(i)?;
+                i.print(&value)
+            })
+        }
+        Stmt::Expression(Expression { expr, .. }) => {
+            let expr = compile_expr(expr, interpreter);
+            Box::new(move |i| expr(i).map(drop))
+        }
+        Stmt::Var(Var {
+            name,
+            initializer,
+            slot,
+            ..
+        }) => {
+            let name = name.clone();
+            let slot = slot.get();
+            let initializer = initializer
+                .as_ref()
+                .map(|initializer| compile_expr(initializer, interpreter));
+            Box::new(move |i| {
+                let value = match &initializer {
+                    Some(initializer) => Some(initializer(i)?),
+                    None => None,
+                };
+                i.declare_var(&name, slot, value);
+                Ok(())
+            })
+        }
+        Stmt::Block(Block { statements, .. }) => {
+            let statements = compile(statements, interpreter);
+            Box::new(move |i| {
+                i.enter();
+                let result = statements.iter().try_for_each(|stmt| stmt(i));
+                i.exit();
+                result
+            })
+        }
+        Stmt::If(If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        }) => {
+            let condition = compile_expr(condition, interpreter);
+            let then_branch = compile_stmt(then_branch, interpreter);
+            let else_branch = else_branch
+                .as_ref()
+                .map(|else_branch| compile_stmt(else_branch, interpreter));
+            Box::new(move |i| {
+                if condition(i)?.is_truthy() {
+                    then_branch(i)
+                } else if let Some(else_branch) = &else_branch {
+                    else_branch(i)
+                } else {
+                    Ok(())
+                }
+            })
+        }
+        Stmt::While(While {
+            condition, body, ..
+        }) => {
+            let condition = compile_expr(condition, interpreter);
+            let body = compile_stmt(body, interpreter);
+            Box::new(move |i| {
+                while condition(i)?.is_truthy() {
+                    body(i)?;
+                }
+                Ok(())
+            })
+        }
+        Stmt::Return(Return { value, .. }) => compile_return(value.as_ref(), interpreter),
+        // Declaring a function or importing a module evaluates nothing, so
+        // the visitors handle those.
+        Stmt::Func(func) => {
+            let func = func.clone();
+            Box::new(move |i| i.visit_func(&func))
+        }
+        Stmt::Import(import) => {
+            let import = import.clone();
+            Box::new(move |i| i.visit_import(&import))
+        }
+    };
+    // Only the statement hooks need the statement itself, so a copy is kept
+    // only when there are any.
+    let line = stmt.line();
+    let hooked = interpreter.has_statement_hooks().then(|| stmt.clone());
+    Box::new(move |i| {
+        i.begin_stmt(line, hooked.as_ref())?;
+        run(i)
+    })
+}
+
+/// `return`, with a call as its value set up as a tail call.
+fn compile_return(value: Option<&Expr>, interpreter: &Interpreter) -> CompiledStmt {
+    match value {
+        Some(Expr::Call(Call {
+            callee,
+            args,
+            names,
+            paren,
+            ..
+        })) => {
+            let callee_line = callee.span().line;
+            let callee = compile_expr(callee, interpreter);
+            let args = compile_args(args, interpreter);
+            let names = names.clone();
+            let line = paren.line;
+            Box::new(move |i| {
+                i.step()?;
+                let callee = callee(i)?;
+                let args = args.iter().map(|arg| arg(i)).collect::<Result<_, _>>()?;
+                let args = i.arrange_args(&callee, args, &names, line)?;
+                i.return_call(callee, args, line, callee_line)?;
+                Err(EvalError::Return.into())
+            })
+        }
+        Some(value) => {
+            let value = compile_expr(value, interpreter);
+            Box::new(move |i| {
+                let value = value(i)?;
+                i.return_value(value);
+                Err(EvalError::Return.into())
+            })
+        }
+        None => Box::new(|i| {
+            i.return_value(Value::Nil);
+            Err(EvalError::Return.into())
+        }),
+    }
+}
+
+fn compile_args(args: &[Expr], interpreter: &Interpreter) -> Vec<CompiledExpr> {
+    args.iter()
+        .map(|arg| compile_expr(arg, interpreter))
+        .collect()
+}
+
+fn compile_expr(expr: &Expr, interpreter: &Interpreter) -> CompiledExpr {
+    match expr {
+        Expr::Literal(Literal { value, .. }) => {
+            let value = interpreter.literal(value);
+            Box::new(move |i| {
+                i.step()?;
+                Ok(value.clone())
+            })
+        }
+        Expr::Grouping(Grouping { expr, .. }) => {
+            let expr = compile_expr(expr, interpreter);
+            Box::new(move |i| {
+                i.step()?;
+                expr(i)
+            })
+        }
+        Expr::Unary(Unary {
+            operator, right, ..
+        }) => {
+            let operator = operator.value.clone();
+            let line = right.span().line;
+            let right = compile_expr(right, interpreter);
+            Box::new(move |i| {
+                i.step()?;
+                let right = right(i)?;
+                i.unary(&operator, right, line)
+            })
+        }
+        Expr::Binary(Binary {
+            left,
+            operator,
+            right,
+            ..
+        }) if operator.value == TokenValue::QuestionQuestion => {
+            let left = compile_expr(left, interpreter);
+            let right = compile_expr(right, interpreter);
+            Box::new(move |i| {
+                i.step()?;
+                match left(i)? {
+                    Value::Nil => right(i),
+                    left => Ok(left),
+                }
+            })
+        }
+        Expr::Binary(binary) if chained(binary).is_some() => {
+            compile_binary_chain(binary, interpreter)
+        }
+        Expr::Binary(Binary {
+            left,
+            operator,
+            right,
+            ..
+        }) => {
+            let operator = operator.value.clone();
+            let (left_line, right_line) = (left.span().line, right.span().line);
+            let left = compile_expr(left, interpreter);
+            let right = compile_expr(right, interpreter);
+            Box::new(move |i| {
+                i.step()?;
+                let right = right(i)?;
+                let left = left(i)?;
+                i.binary(&operator, left, right, left_line, right_line)
+            })
+        }
+        Expr::Assign(Assign {
+            name, value, local, ..
+        }) => {
+            let name = name.clone();
+            let local = local.get();
+            let value = compile_expr(value, interpreter);
+            Box::new(move |i| {
+                i.step()?;
+                let value = value(i)?;
+                i.assign_variable(&name, local, value)
+            })
+        }
+        Expr::Variable(Variable { name, local, .. }) => {
+            let name = name.clone();
+            let local = local.get();
+            Box::new(move |i| {
+                i.step()?;
+                i.variable(&name, local)
+            })
+        }
+        Expr::Call(Call {
+            callee,
+            args,
+            names,
+            paren,
+            ..
+        }) => {
+            let callee_line = callee.span().line;
+            let callee = compile_expr(callee, interpreter);
+            let args = compile_args(args, interpreter);
+            let names = names.clone();
+            let line = paren.line;
+            Box::new(move |i| {
+                i.step()?;
+                let callee = callee(i)?;
+                let args = args.iter().map(|arg| arg(i)).collect::<Result<_, _>>()?;
+                let args = i.arrange_args(&callee, args, &names, line)?;
+                i.call_value(callee, args, line, callee_line)
+            })
+        }
+        Expr::Get(Get {
+            object,
+            name,
+            optional,
+            ..
+        }) => {
+            let object = compile_expr(object, interpreter);
+            let name = name.clone();
+            let optional = *optional;
+            Box::new(move |i| {
+                i.step()?;
+                let object = object(i)?;
+                if optional {
+                    return i.optional_property(object, &name);
+                }
+                i.property(object, &name)
+            })
+        }
+    }
+}
+
+/// A chain of binary operators such as `1 + 2 + 3 + ...`, evaluated as
+/// `Interpreter::binary_chain` does, without recursing down the left operands
+/// either to compile or to run it.
+fn compile_binary_chain(binary: &Binary, interpreter: &Interpreter) -> CompiledExpr {
+    let mut chain = Vec::new();
+    let mut binary = binary;
+    loop {
+        let lines = (binary.left.span().line, binary.right.span().line);
+        let right = compile_expr(&binary.right, interpreter);
+        chain.push((binary.operator.value.clone(), lines, right));
+        let Some(left) = chained(binary) else {
+            break;
+        };
+        binary = left;
+    }
+    let leftmost = compile_expr(&binary.left, interpreter);
+    Box::new(move |i| {
+        let mut rights = Vec::with_capacity(chain.len());
+        for (_, _, right) in &chain {
+            i.step()?;
+            rights.push(right(i)?);
+        }
+        let mut value = leftmost(i)?;
+        for ((operator, (left_line, right_line), _), right) in chain.iter().zip(rights).rev() {
+            value = i.binary(operator, value, right, *left_line, *right_line)?;
+        }
+        Ok(value)
+    })
+}
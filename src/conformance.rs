@@ -0,0 +1,93 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::golden;
+
+/// Where `conformance` looks for the test corpus when given no directory.
+pub const DEFAULT_DIR: &str = "tests/cases";
+
+/// Lists, one path per line relative to the corpus, the tests known to fail
+/// because of a deliberate or not yet fixed difference from the reference
+/// implementation. `#` starts a comment.
+const KNOWN_FAILURES: &str = "known_failures.txt";
+
+/// `conformance [dir]`: runs the test corpus written for the reference Lox
+/// implementations (Crafting Interpreters' `// expect:` format) and reports
+/// how each suite, a top-level directory of the corpus, fares. Tests listed in
+/// `known_failures.txt` may fail; the run fails on any other failure, and on a
+/// known failure that now passes, so the list stays accurate.
+///
+/// Resolver warnings are turned off, as the reference implementations have
+/// none.
+pub fn run(dir: &Path, jobs: usize, timeout: Duration) -> i32 {
+    let started = Instant::now();
+    let files = golden::collect_tests(dir);
+    if files.is_empty() {
+        eprintln!("No tests found in {}.", dir.display());
+        return 1;
+    }
+    let known_failures = known_failures(dir);
+    let results = golden::run_tests(&files, &["--no-warnings"], jobs, timeout);
+
+    // Passed and total tests per suite.
+    let mut suites = BTreeMap::<String, (usize, usize)>::new();
+    let (mut failed, mut known, mut fixed) = (0, 0, Vec::new());
+    for (file, result) in files.iter().zip(&results) {
+        let relative = file.strip_prefix(dir).unwrap_or(file);
+        let name = relative.to_string_lossy().replace('\\', "/");
+        let suite = match name.split_once('/') {
+            Some((suite, _)) => suite.to_string(),
+            None => ".".to_string(),
+        };
+        let passed = matches!(result, Ok(failures) if failures.is_empty());
+        let counts = suites.entry(suite).or_default();
+        counts.1 += 1;
+        if passed {
+            counts.0 += 1;
+        }
+        match (passed, known_failures.contains(&name)) {
+            (true, false) => {}
+            (true, true) => fixed.push(name),
+            (false, true) => known += 1,
+            (false, false) => {
+                failed += 1;
+                golden::print_failure(file, result);
+            }
+        }
+    }
+    for name in &fixed {
+        println!("FIXED {} (remove it from {})", name, KNOWN_FAILURES);
+    }
+
+    println!("\n{:<20} {:>6} {:>6}", "suite", "passed", "total");
+    for (suite, (passed, total)) in &suites {
+        println!("{:<20} {:>6} {:>6}", suite, passed, total);
+    }
+    let passed = suites.values().map(|(passed, _)| passed).sum::<usize>();
+    println!(
+        "\n{} passed, {} failed, {} known failures, {} total in {:.2}s",
+        passed,
+        failed,
+        known,
+        files.len(),
+        started.elapsed().as_secs_f64()
+    );
+    if failed == 0 && fixed.is_empty() {
+        0
+    } else {
+        1
+    }
+}
+
+fn known_failures(dir: &Path) -> HashSet<String> {
+    let list = fs::read_to_string(dir.join(KNOWN_FAILURES)).unwrap_or_default();
+    list.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
@@ -0,0 +1,87 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::stmt::Stmt;
+
+/// Executed-line counts per source file.
+#[derive(Default)]
+pub struct Coverage {
+    files: BTreeMap<PathBuf, BTreeMap<usize, usize>>,
+}
+
+impl Coverage {
+    /// Registers every statement line of a program as executable, so lines
+    /// that never run show up with zero hits.
+    pub fn add_program(&mut self, path: &Path, stmts: &[Stmt]) {
+        let lines = self.files.entry(path.to_path_buf()).or_default();
+        for stmt in stmts {
+            add_lines(stmt, lines);
+        }
+    }
+
+    pub fn hit(&mut self, path: &Path, line: usize) {
+        *self
+            .files
+            .entry(path.to_path_buf())
+            .or_default()
+            .entry(line)
+            .or_default() += 1;
+    }
+
+    pub fn write_summary(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "{:<30} {:>8} {:>8} {:>9}",
+            "file", "lines", "covered", "coverage"
+        )?;
+        for (path, lines) in &self.files {
+            let covered = lines.values().filter(|hits| **hits > 0).count();
+            writeln!(
+                out,
+                "{:<30} {:>8} {:>8} {:>8.1}%",
+                path.display(),
+                lines.len(),
+                covered,
+                100.0 * covered as f64 / lines.len().max(1) as f64
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn write_lcov(&self, out: &mut impl Write) -> io::Result<()> {
+        for (path, lines) in &self.files {
+            writeln!(out, "TN:")?;
+            writeln!(out, "SF:{}", path.display())?;
+            for (line, hits) in lines {
+                writeln!(out, "DA:{},{}", line, hits)?;
+            }
+            writeln!(out, "LF:{}", lines.len())?;
+            writeln!(
+                out,
+                "LH:{}",
+                lines.values().filter(|hits| **hits > 0).count()
+            )?;
+            writeln!(out, "end_of_record")?;
+        }
+        Ok(())
+    }
+}
+
+fn add_lines(stmt: &Stmt, lines: &mut BTreeMap<usize, usize>) {
+    lines.entry(stmt.line()).or_default();
+    match stmt {
+        Stmt::Block(block) => block.statements.iter().for_each(|s| add_lines(s, lines)),
+        Stmt::If(if_stmt) => {
+            add_lines(&if_stmt.then_branch, lines);
+            if let Some(else_branch) = &if_stmt.else_branch {
+                add_lines(else_branch, lines);
+            }
+        }
+        Stmt::While(while_stmt) => add_lines(&while_stmt.body, lines),
+        Stmt::Func(func) => func.body.iter().for_each(|s| add_lines(s, lines)),
+        _ => {}
+    }
+}
@@ -0,0 +1,380 @@
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+/// How many lines of history are kept in memory, most recent last.
+const MAX_HISTORY: usize = 1000;
+
+/// Reads lines from a terminal with editing, history and history search, for
+/// the REPL:
+///
+/// - Left/Right, Home/End, Ctrl-A/E/B/F move the cursor; Backspace, Delete,
+///   Ctrl-K/U/W delete.
+/// - Up/Down and Ctrl-P/N step through the history.
+/// - Ctrl-R searches the history backwards for what is typed next; Ctrl-R
+///   again finds an older match, Enter runs it, Ctrl-G cancels the search
+///   and any other key keeps the match for editing.
+/// - Ctrl-C abandons the line; Ctrl-D on an empty line ends the input.
+///
+/// The history is read from `~/.lox_history` at start, and every line read
+/// is appended to it. The terminal is put in raw mode with `stty` while a
+/// line is read; where that fails, lines are read as typed, unedited.
+pub struct Editor {
+    history: Vec<String>,
+    file: Option<File>,
+}
+
+impl Editor {
+    /// An editor with the history saved so far.
+    pub fn open() -> Editor {
+        let path = env::var_os("HOME").map(|home| PathBuf::from(home).join(".lox_history"));
+        let mut history = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        history.drain(..history.len().saturating_sub(MAX_HISTORY));
+        let file =
+            path.and_then(|path| OpenOptions::new().create(true).append(true).open(path).ok());
+        Editor { history, file }
+    }
+
+    /// Prints `prompt` and reads a line, with its newline. `None` at the end
+    /// of input.
+    pub fn read_line(&mut self, prompt: &str) -> Option<String> {
+        let line = match RawMode::enter() {
+            Some(raw) => {
+                let line = self.edit(prompt);
+                drop(raw);
+                line?
+            }
+            None => read_plain(prompt)?,
+        };
+        self.remember(line.trim_end_matches('\n'));
+        Some(line)
+    }
+
+    /// Adds a line to the history, unless it is blank or repeats the last.
+    fn remember(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().is_some_and(|last| last == line) {
+            return;
+        }
+        if let Some(file) = &mut self.file {
+            // Failing to record history is not worth interrupting the session.
+            let _ = writeln!(file, "{}", line);
+        }
+        if self.history.len() == MAX_HISTORY {
+            self.history.remove(0);
+        }
+        self.history.push(line.to_string());
+    }
+
+    /// Reads a line in raw mode, echoing and editing it.
+    fn edit(&self, prompt: &str) -> Option<String> {
+        let mut line = Line {
+            prompt,
+            chars: Vec::new(),
+            cursor: 0,
+        };
+        // Which history entry is shown; `history.len()` for the new line,
+        // which is kept in `draft` while browsing.
+        let mut shown = self.history.len();
+        let mut draft = Vec::new();
+        line.render();
+        loop {
+            match read_key()? {
+                Key::Enter => {
+                    print!("\r\n");
+                    let _ = io::stdout().flush();
+                    return Some(line.chars.iter().collect::<String>() + "\n");
+                }
+                Key::Char(c) => line.insert(c),
+                Key::Ctrl('d') if line.chars.is_empty() => {
+                    print!("\r\n");
+                    return None;
+                }
+                Key::Ctrl('d') | Key::Delete => line.delete(),
+                Key::Backspace if line.cursor > 0 => {
+                    line.cursor -= 1;
+                    line.delete();
+                }
+                Key::Ctrl('c') => {
+                    print!("^C\r\n");
+                    return Some("\n".to_string());
+                }
+                Key::Left | Key::Ctrl('b') => line.cursor = line.cursor.saturating_sub(1),
+                Key::Right | Key::Ctrl('f') => {
+                    line.cursor = (line.cursor + 1).min(line.chars.len())
+                }
+                Key::Home | Key::Ctrl('a') => line.cursor = 0,
+                Key::End | Key::Ctrl('e') => line.cursor = line.chars.len(),
+                Key::Ctrl('k') => line.chars.truncate(line.cursor),
+                Key::Ctrl('u') => {
+                    line.chars.drain(..line.cursor);
+                    line.cursor = 0;
+                }
+                Key::Ctrl('w') => {
+                    let mut start = line.cursor;
+                    while start > 0 && line.chars[start - 1].is_whitespace() {
+                        start -= 1;
+                    }
+                    while start > 0 && !line.chars[start - 1].is_whitespace() {
+                        start -= 1;
+                    }
+                    line.chars.drain(start..line.cursor);
+                    line.cursor = start;
+                }
+                Key::Up | Key::Ctrl('p') if shown > 0 => {
+                    if shown == self.history.len() {
+                        draft = line.chars.clone();
+                    }
+                    shown -= 1;
+                    line.replace(self.history[shown].chars().collect());
+                }
+                Key::Down | Key::Ctrl('n') if shown < self.history.len() => {
+                    shown += 1;
+                    let chars = match self.history.get(shown) {
+                        Some(entry) => entry.chars().collect(),
+                        None => draft.clone(),
+                    };
+                    line.replace(chars);
+                }
+                Key::Ctrl('r') => {
+                    if let Some(found) = self.search(&mut line)? {
+                        return Some(found + "\n");
+                    }
+                }
+                _ => {}
+            }
+            line.render();
+        }
+    }
+
+    /// Ctrl-R: searches the history backwards as the query is typed. Returns
+    /// the match if Enter runs it; otherwise `line` holds what to edit next.
+    fn search(&self, line: &mut Line) -> Option<Option<String>> {
+        let original = line.chars.clone();
+        let mut query = String::new();
+        // The entry matched, searched back from on the next Ctrl-R.
+        let mut found = None;
+        loop {
+            let shown = found.map_or("", |i: usize| self.history[i].as_str());
+            print!("\r(reverse-i-search)`{}': {}\x1b[K", query, shown);
+            let _ = io::stdout().flush();
+            match read_key()? {
+                Key::Char(c) => {
+                    query.push(c);
+                    let before = found.map_or(self.history.len(), |i| i + 1);
+                    found = self.find(&query, before).or(found);
+                }
+                Key::Backspace => {
+                    query.pop();
+                    found = self.find(&query, self.history.len());
+                }
+                Key::Ctrl('r') => {
+                    found = self
+                        .find(&query, found.unwrap_or(self.history.len()))
+                        .or(found);
+                }
+                Key::Enter => {
+                    print!("\r\n");
+                    let _ = io::stdout().flush();
+                    return Some(Some(shown.to_string()));
+                }
+                Key::Ctrl('g') | Key::Ctrl('c') => {
+                    line.replace(original);
+                    return Some(None);
+                }
+                _ => {
+                    if let Some(i) = found {
+                        line.replace(self.history[i].chars().collect());
+                    }
+                    return Some(None);
+                }
+            }
+        }
+    }
+
+    /// The latest history entry before `before` containing `query`.
+    fn find(&self, query: &str, before: usize) -> Option<usize> {
+        if query.is_empty() {
+            return None;
+        }
+        self.history[..before]
+            .iter()
+            .rposition(|entry| entry.contains(query))
+    }
+}
+
+/// The line being edited.
+struct Line<'a> {
+    prompt: &'a str,
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl Line<'_> {
+    fn insert(&mut self, c: char) {
+        self.chars.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    /// Deletes the character under the cursor.
+    fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    fn replace(&mut self, chars: Vec<char>) {
+        self.cursor = chars.len();
+        self.chars = chars;
+    }
+
+    /// Redraws the line over the current one and puts the cursor in place.
+    fn render(&self) {
+        let text = self.chars.iter().collect::<String>();
+        print!("\r{}{}\x1b[K", self.prompt, text);
+        let back = self.chars.len() - self.cursor;
+        if back > 0 {
+            print!("\x1b[{}D", back);
+        }
+        let _ = io::stdout().flush();
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Key {
+    Char(char),
+    /// A letter pressed with Ctrl, in lowercase.
+    Ctrl(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Other,
+}
+
+/// Reads a key press from stdin in raw mode. `None` at the end of input.
+fn read_key() -> Option<Key> {
+    let byte = read_byte()?;
+    Some(match byte {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        0x1b => escape_sequence()?,
+        1..=26 => Key::Ctrl(char::from(b'a' + byte - 1)),
+        0..=0x1f => Key::Other,
+        _ => {
+            // The rest of a UTF-8 character, as many bytes as the first
+            // announces.
+            let len = byte.leading_ones().clamp(1, 4) as usize;
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                bytes.push(read_byte()?);
+            }
+            match std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|s| s.chars().next())
+            {
+                Some(c) => Key::Char(c),
+                None => Key::Other,
+            }
+        }
+    })
+}
+
+/// The key an escape sequence, its `ESC` already read, stands for.
+fn escape_sequence() -> Option<Key> {
+    let kind = read_byte()?;
+    if kind != b'[' && kind != b'O' {
+        return Some(Key::Other);
+    }
+    let mut params = Vec::new();
+    loop {
+        match read_byte()? {
+            b'A' => return Some(Key::Up),
+            b'B' => return Some(Key::Down),
+            b'C' => return Some(Key::Right),
+            b'D' => return Some(Key::Left),
+            b'H' => return Some(Key::Home),
+            b'F' => return Some(Key::End),
+            b'~' => {
+                return Some(match params.as_slice() {
+                    b"1" | b"7" => Key::Home,
+                    b"4" | b"8" => Key::End,
+                    b"3" => Key::Delete,
+                    _ => Key::Other,
+                })
+            }
+            byte @ (b'0'..=b'9' | b';') => params.push(byte),
+            _ => return Some(Key::Other),
+        }
+    }
+}
+
+fn read_byte() -> Option<u8> {
+    let mut byte = [0];
+    match io::stdin().read(&mut byte) {
+        Ok(1) => Some(byte[0]),
+        _ => None,
+    }
+}
+
+/// Prints `prompt` and reads a line of stdin as typed, `None` at the end of
+/// input.
+fn read_plain(prompt: &str) -> Option<String> {
+    print!("{}", prompt);
+    io::stdout().flush().ok()?;
+    let mut line = String::new();
+    match io::stdin().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line),
+    }
+}
+
+/// The terminal in raw mode, with its settings restored on drop.
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn enter() -> Option<RawMode> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        Some(RawMode {
+            saved: saved.trim().to_string(),
+        })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        stty(&[&self.saved]);
+    }
+}
+
+/// Runs `stty` on the terminal stdin is, returning its output if it
+/// succeeded.
+fn stty(args: &[&str]) -> Option<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -0,0 +1,125 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{eval::Value, symbol::Symbol};
+
+/// A single scope of variables, linked to the scope it is nested in.
+///
+/// Locals the resolver could place live in `slots`; `values` holds globals and
+/// anything defined dynamically, which are looked up by name.
+#[derive(Debug, Default, Clone)]
+pub struct Environment {
+    values: HashMap<Symbol, Value>,
+    slots: Vec<Value>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    pub fn new(enclosing: Rc<RefCell<Environment>>) -> Environment {
+        Environment {
+            values: HashMap::new(),
+            slots: Vec::new(),
+            enclosing: Some(enclosing),
+        }
+    }
+
+    pub fn enclosing(&self) -> Option<Rc<RefCell<Environment>>> {
+        self.enclosing.clone()
+    }
+
+    pub fn define(&mut self, name: Symbol, value: Value) {
+        self.values.insert(name, value);
+    }
+
+    pub fn define_at(&mut self, slot: usize, value: Value) {
+        if slot >= self.slots.len() {
+            self.slots.resize(slot + 1, Value::Nil);
+        }
+        self.slots[slot] = value;
+    }
+
+    /// Reads a resolved local. `None` if its declaration never ran, e.g. one
+    /// in an untaken `if` branch.
+    pub fn get_at(&self, depth: usize, slot: usize) -> Option<Value> {
+        match depth {
+            0 => self.slots.get(slot).cloned(),
+            _ => self.enclosing.as_ref()?.borrow().get_at(depth - 1, slot),
+        }
+    }
+
+    pub fn assign_at(&mut self, depth: usize, slot: usize, value: Value) -> bool {
+        match depth {
+            0 => match self.slots.get_mut(slot) {
+                Some(local) => {
+                    *local = value;
+                    true
+                }
+                None => false,
+            },
+            _ => match &self.enclosing {
+                Some(enclosing) => enclosing.borrow_mut().assign_at(depth - 1, slot, value),
+                None => false,
+            },
+        }
+    }
+
+    pub fn get(&self, name: Symbol) -> Option<Value> {
+        match self.values.get(&name) {
+            Some(value) => Some(value.clone()),
+            None => self.enclosing.as_ref()?.borrow().get(name),
+        }
+    }
+
+    /// Assigns to the innermost existing variable called `name`, returning
+    /// false if there is none.
+    pub fn assign(&mut self, name: Symbol, value: Value) -> bool {
+        match self.values.get_mut(&name) {
+            Some(slot) => {
+                *slot = value;
+                true
+            }
+            None => match &self.enclosing {
+                Some(enclosing) => enclosing.borrow_mut().assign(name, value),
+                None => false,
+            },
+        }
+    }
+
+    /// The variables declared directly in this scope.
+    pub fn values(&self) -> impl Iterator<Item = (&Symbol, &Value)> {
+        self.values.iter()
+    }
+
+    /// The locals placed in slots by the resolver, by slot.
+    pub fn slots(&self) -> &[Value] {
+        &self.slots
+    }
+
+    /// Every name a lookup by name can find from this scope, including those
+    /// of enclosing scopes. Slotted locals have no names at runtime.
+    pub fn names(&self) -> Vec<Symbol> {
+        let mut names = self.values.keys().copied().collect::<Vec<_>>();
+        if let Some(enclosing) = &self.enclosing {
+            names.extend(enclosing.borrow().names());
+        }
+        names
+    }
+
+    /// Every value held directly by this scope, named or slotted.
+    pub fn contents(&self) -> impl Iterator<Item = &Value> {
+        self.values.values().chain(&self.slots)
+    }
+
+    pub fn retain(&mut self, f: impl FnMut(&Symbol, &mut Value) -> bool) {
+        self.values.retain(f);
+    }
+}
+
+impl FromIterator<(Symbol, Value)> for Environment {
+    fn from_iter<T: IntoIterator<Item = (Symbol, Value)>>(iter: T) -> Self {
+        Environment {
+            values: iter.into_iter().collect(),
+            slots: Vec::new(),
+            enclosing: None,
+        }
+    }
+}
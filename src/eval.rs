@@ -1,12 +1,38 @@
-use std::{cell::RefCell, collections::HashMap, fmt::Display, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    env,
+    fmt::Display,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
 use anyhow::{Error, Result};
 use thiserror::Error;
 
 use crate::{
-    expr::{Assign, Binary, Expr, ExprVisitor, Grouping, Literal, Unary},
-    stmt::{Block, Expression, For, If, Print, Stmt, StmtVisitor, Var, While},
-    token::{Number, Token, TokenValue},
+    cache,
+    compile::{self, Backend, CompiledBody},
+    coverage::Coverage,
+    environment::Environment,
+    explain,
+    expr::{Assign, Binary, Call, Expr, ExprVisitor, Get, Grouping, Literal, LiteralValue, Unary},
+    gc::Heap,
+    interrupt, lexer,
+    memo::Memo,
+    memory::{self, Bytes},
+    module,
+    native::{Native, NATIVES},
+    number::Number,
+    parser::Parser,
+    random::Rng,
+    resolver::{Local, Resolver},
+    spawn::{self, Message},
+    stmt::{Block, Expression, Func, If, Import, Print, Stmt, StmtVisitor, Var, While},
+    symbol::Symbol,
+    token::{Token, TokenValue},
     Walkable,
 };
 
@@ -16,22 +42,259 @@ pub enum EvalError {
     Return,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
 pub enum Value {
     Nil,
     Boolean(bool),
     Number(Number),
-    String(String),
-    Function(LoxFunction),
-    RustFunction(String),
+    // `Rc<String>` rather than `Rc<str>` so the pointer is thin and `Value`
+    // stays at 16 bytes.
+    String(Rc<String>),
+    Function(Rc<LoxFunction>),
+    RustFunction(&'static Native),
+    Module(Rc<Module>),
+    Process(Rc<Process>),
+    /// A number written without a fraction, kept apart from other numbers
+    /// when integers are enabled.
+    Integer(i64),
+    /// Held by a variable declared without an initializer, in strict mode,
+    /// until it is first assigned. Reading it is a runtime error.
+    Uninitialized,
+}
+
+const _: () = assert!(std::mem::size_of::<Value>() <= 16);
+
+impl Value {
+    /// Lox `==`: values of different types are never equal, numbers compare
+    /// per IEEE 754 (so `NaN` is not equal to itself) whether or not they are
+    /// integers, strings by contents, and functions, modules and processes by
+    /// identity.
+    pub fn lox_eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Boolean(l), Value::Boolean(r)) => l == r,
+            (Value::Number(l), Value::Number(r)) => l == r,
+            (Value::Integer(l), Value::Integer(r)) => l == r,
+            (Value::Integer(l), Value::Number(r)) | (Value::Number(r), Value::Integer(l)) => {
+                Number(*l as f64) == *r
+            }
+            (Value::String(l), Value::String(r)) => l == r,
+            (Value::Function(l), Value::Function(r)) => Rc::ptr_eq(l, r),
+            (Value::RustFunction(l), Value::RustFunction(r)) => std::ptr::eq(*l, *r),
+            (Value::Module(l), Value::Module(r)) => Rc::ptr_eq(l, r),
+            (Value::Process(l), Value::Process(r)) => Rc::ptr_eq(l, r),
+            _ => false,
+        }
+    }
+}
+
+impl Value {
+    /// The name of the value's type, for error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Number(_) | Value::Integer(_) => "number",
+            Value::String(_) => "string",
+            Value::Function(_) | Value::RustFunction(_) => "function",
+            Value::Module(_) => "module",
+            Value::Process(_) => "process",
+            Value::Uninitialized => "nil",
+        }
+    }
+
+    /// Whether `if` and `while` take the value as true: anything but `nil`
+    /// and `false`.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Boolean(false))
+    }
+
+    /// Integers as plain numbers, for mixed and floating-point arithmetic.
+    fn into_float(self) -> Value {
+        match self {
+            Value::Integer(n) => Value::Number(Number(n as f64)),
+            value => value,
+        }
+    }
+}
+
+/// The left operand of `binary` when it is a binary operation evaluated
+/// along with it, as a chain: any operation but `??`, which may skip its
+/// right operand.
+pub fn chained(binary: &Binary) -> Option<&Binary> {
+    match &*binary.left {
+        Expr::Binary(left) if left.operator.value != TokenValue::QuestionQuestion => Some(left),
+        _ => None,
+    }
+}
+
+/// The error for calling `name` with the wrong number of arguments, reported
+/// at the call's closing parenthesis on `line`.
+fn arity_error(name: &str, expected: usize, got: usize, line: usize) -> Error {
+    explain::error(
+        "R0006",
+        format!(
+            "Expected {} arguments but got {} in call to '{}'.\n[line {}]",
+            expected, got, name, line
+        ),
+    )
+}
+
+/// The error for the first argument that doesn't have the type its parameter
+/// is annotated with, if any.
+fn annotation_error(
+    name: &Token,
+    params: &[Token],
+    types: &[Option<Token>],
+    args: &[Value],
+    line: usize,
+) -> Option<Error> {
+    let (param, ty, arg) = params
+        .iter()
+        .zip(types)
+        .zip(args)
+        .find_map(|((param, ty), arg)| {
+            let ty = ty.as_ref()?;
+            (ty.lexeme != arg.type_name()).then_some((param, ty, arg))
+        })?;
+    Some(explain::error(
+        "R0035",
+        format!(
+            "Expected {} for parameter '{}' of '{}' but got {}.\n[line {}]",
+            ty.lexeme,
+            param.lexeme,
+            name.lexeme,
+            arg.type_name(),
+            line
+        ),
+    ))
+}
+
+/// The variables declared directly in `env`, named ones sorted by name,
+/// then slotted ones.
+fn variables(env: &Environment) -> Vec<(String, Value)> {
+    let mut named = env
+        .values()
+        .map(|(name, value)| (name.as_str().to_string(), value.clone()))
+        .collect::<Vec<_>>();
+    named.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let slotted = env
+        .slots()
+        .iter()
+        .enumerate()
+        .map(|(slot, value)| (format!("#{}", slot), value.clone()));
+    named.extend(slotted);
+    named
+}
+
+/// The name among `names` closest to `name` by edit distance, if it is close
+/// enough to be a likely typo: within one edit per three characters of `name`.
+fn closest_name(name: &str, names: Vec<Symbol>) -> Option<&'static str> {
+    let limit = name.chars().count() / 3;
+    names
+        .into_iter()
+        .map(Symbol::as_str)
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// The Levenshtein distance between two strings, counted in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &b) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a != b);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The longest string, in bytes, that `*` builds, so a stray count fails
+/// rather than taking all the memory there is.
+const MAX_REPEAT: usize = 1 << 28;
+
+/// `s * times`, in extension mode: `s` repeated `times` times. The count,
+/// found on `line`, must be a whole number.
+fn repeat(s: &str, times: Number, line: usize) -> Result<Value, Error> {
+    if times.0 < 0.0 || times.0.fract() != 0.0 {
+        return Err(explain::error(
+            "R0044",
+            format!(
+                "Can only repeat a string a whole number of times, not {}.\n[line {}]",
+                Value::Number(times),
+                line
+            ),
+        ));
+    }
+    if times.0 * s.len() as f64 > MAX_REPEAT as f64 {
+        return Err(explain::error(
+            "R0045",
+            format!(
+                "Repeated string would be longer than {} bytes.\n[line {}]",
+                MAX_REPEAT, line
+            ),
+        ));
+    }
+    Ok(Value::String(Rc::new(s.repeat(times.0 as usize))))
+}
+
+/// Applies a binary operator to two integers, or returns `None` when the
+/// result is not an integer: on overflow and for division.
+fn integer_binary(operator: &TokenValue, l: i64, r: i64) -> Option<Value> {
+    let value = match operator {
+        TokenValue::Plus => Value::Integer(l.checked_add(r)?),
+        TokenValue::Minus => Value::Integer(l.checked_sub(r)?),
+        TokenValue::Star => Value::Integer(l.checked_mul(r)?),
+        TokenValue::Greater => Value::Boolean(l > r),
+        TokenValue::GreaterEqual => Value::Boolean(l >= r),
+        TokenValue::Less => Value::Boolean(l < r),
+        TokenValue::LessEqual => Value::Boolean(l <= r),
+        _ => return None,
+    };
+    Some(value)
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.lox_eq(other)
+    }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
 pub struct LoxFunction {
     pub name: Token,
     pub params: Vec<Token>,
-    pub body: Vec<Stmt>,
-    pub closure: Vec<Rc<RefCell<HashMap<String, Value>>>>,
+    pub types: Vec<Option<Token>>,
+    pub body: Rc<[Stmt]>,
+    pub closure: Scope,
+    /// The cached results of a function made by `memoize`.
+    pub memo: Option<RefCell<Memo>>,
+}
+
+/// A module imported with `import "path" as name;`, exposing its globals as
+/// properties.
+#[derive(Clone, Debug)]
+pub struct Module {
+    pub name: String,
+    pub globals: Scope,
+}
+
+/// A finished process, as `exec` and `execArgs` return it, exposing its
+/// output and exit status as the properties `stdout`, `stderr` and `code`.
+#[derive(Clone, Debug)]
+pub struct Process {
+    pub stdout: Rc<String>,
+    pub stderr: Rc<String>,
+    /// `None` when the process was killed by a signal.
+    pub code: Option<i32>,
 }
 
 impl Display for Value {
@@ -40,97 +303,1012 @@ impl Display for Value {
             Value::Nil => write!(f, "nil"),
             Value::Boolean(b) => write!(f, "{}", b),
             Value::Number(n) => write!(f, "{}", n),
+            Value::Integer(n) => write!(f, "{}", n),
             Value::String(s) => write!(f, "{}", s),
             Value::Function(func) => write!(f, "<fn {}>", func.name.lexeme),
-            Value::RustFunction(s) => write!(f, "fn {}>", s),
+            Value::RustFunction(_) => write!(f, "<native fn>"),
+            Value::Module(module) => write!(f, "<module {}>", module.name),
+            Value::Process(process) => match process.code {
+                Some(code) => write!(f, "<process exited with {}>", code),
+                None => write!(f, "<process killed>"),
+            },
+            Value::Uninitialized => write!(f, "nil"),
         }
     }
 }
 
+pub type Scope = Rc<RefCell<Environment>>;
+
+/// A call in tail position, deferred until its caller's frame is gone.
+struct TailCall {
+    func: Rc<LoxFunction>,
+    args: Vec<Value>,
+    line: usize,
+}
+
 pub struct Interpreter {
-    env: Vec<Rc<RefCell<HashMap<String, Value>>>>,
-    rets: HashMap<String, Value>,
+    env: Scope,
+    /// Value of the `return` statement currently unwinding its function.
+    returning: Option<Value>,
+    /// Set instead of `returning` by `return f(...)`, for the caller to run.
+    tail_call: Option<TailCall>,
+    /// The file currently being executed; imports resolve relative to it.
+    path: Option<PathBuf>,
+    /// Global scopes of every module loaded so far, keyed by canonical path.
+    modules: HashMap<PathBuf, Scope>,
+    /// Modules whose execution is in progress, used to detect import cycles.
+    loading: Vec<PathBuf>,
+    /// Where `print` writes to.
+    out: Box<dyn Write>,
+    /// Per-function call statistics, collected when profiling is enabled.
+    profile: Option<HashMap<String, CallStats>>,
+    coverage: Option<Coverage>,
+    /// Number of Lox function calls currently in progress.
+    depth: usize,
+    /// The most calls in progress at once so far.
+    peak_depth: usize,
+    max_depth: usize,
+    /// Statements and expressions evaluated so far.
+    steps: u64,
+    /// Statements executed so far; the other steps are expressions.
+    statements: u64,
+    max_steps: Option<u64>,
+    /// Heap the process may use, checked at every step.
+    max_memory: Option<Bytes>,
+    /// Line of the statement being executed, for errors raised outside of
+    /// any token.
+    line: usize,
+    /// Disables natives with ambient authority and `import`.
+    sandbox: bool,
+    /// Makes reading a variable before it is initialized an error, rather
+    /// than yielding `nil`.
+    strict: bool,
+    /// Enables behaviour beyond the Lox spec, such as comparing strings.
+    extensions: bool,
+    /// Keeps numbers written without a fraction as 64-bit integers.
+    integers: bool,
+    backend: Backend,
+    /// Prints the resolver's warnings to stderr.
+    warnings: bool,
+    /// Loads modules through the on-disk AST cache.
+    ast_cache: bool,
+    /// Function bodies compiled by the closure backend, keyed by address.
+    compiled: HashMap<*const Stmt, CompiledBody>,
+    /// Scope holding the natives and the prelude's definitions, when the
+    /// prelude is loaded. Global scopes enclose it.
+    prelude: Option<Scope>,
+    /// The next reading of the fake clock, when `clock` and `now` count up
+    /// from a fixed value instead of reading the system time.
+    fake_clock: Option<f64>,
+    /// Source of `random` and `randomInt`, reseeded by `seed`.
+    rng: Rng,
+    /// Environment variables set by `setenv`, over the process's own. They
+    /// are kept here rather than set on the process, which isn't sound while
+    /// other threads may be reading the environment.
+    env_vars: HashMap<String, String>,
+    statement_hooks: Vec<StatementHook>,
+    call_hooks: Vec<CallHook>,
+    heap: Heap,
+}
+
+/// Global variables and loaded modules saved by `Interpreter::snapshot`.
+/// Values are shared with the interpreter through their `Rc`s, not copied,
+/// so a snapshot costs a map entry per variable.
+pub struct StateSnapshot {
+    globals: Environment,
+    /// Each module's global scope, and its contents at the snapshot.
+    modules: HashMap<PathBuf, (Scope, Environment)>,
+}
+
+/// Called before each statement runs, with the line it starts on.
+pub type StatementHook = Box<dyn FnMut(&Stmt, usize)>;
+
+/// Called before each call of a function, with the function's name and the
+/// arguments.
+pub type CallHook = Box<dyn FnMut(&str, &[Value])>;
+
+/// Default limit on nested Lox function calls, well below what the native
+/// stack of the interpreter thread can hold.
+pub const DEFAULT_MAX_DEPTH: usize = 10_000;
+
+/// Name used in reports for a script executed without a path.
+const SCRIPT: &str = "<script>";
+
+const PRELUDE: &str = include_str!("prelude.lox");
+
+#[derive(Default)]
+pub struct CallStats {
+    pub calls: usize,
+    /// Inclusive wall time. Recursive calls are only timed at the outermost
+    /// level so they are not counted twice.
+    pub time: Duration,
+    active: usize,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
+    pub fn define(&self, name: Symbol, value: Value) {
+        self.env.borrow_mut().define(name, value);
+    }
+
+    /// Defines a declared variable in its resolved slot, or by name if it is
+    /// a global.
+    fn declare(&self, name: &Token, slot: Option<usize>, value: Value) {
+        match slot {
+            Some(slot) => self.env.borrow_mut().define_at(slot, value),
+            None => self.define(name.symbol(), value),
+        }
+    }
 
-   pub fn define(&self, name: String, value: Value) { self.env.last().unwrap().borrow_mut().insert(name, value); }
+    pub fn assign(&self, name: Symbol, value: Value) -> Result<(), Error> {
+        if !self.env.borrow_mut().assign(name, value) {
+            return Err(explain::error(
+                "R0001",
+                format!("Undefined variable '{}'.", name),
+            ));
+        }
+        Ok(())
+    }
 
-   pub fn assign(&self, name: String, value: Value) -> Result<(), Error> {
-       self.env.iter().rev().filter(|env| env.borrow().contains_key(&name)).next().and_then(|env| env.borrow_mut().insert(name.clone(), value)).ok_or(Error::msg(format!("Undefined variable '{}'.", name)))?;
-       Ok(())
-   }
+    pub fn get(&self, name: Symbol) -> Option<Value> {
+        self.env.borrow().get(name)
+    }
 
-   pub fn get(&self, name: &str) -> Option<Value> { self.env.iter().rev().filter(|env| env.borrow().contains_key(name)).next().and_then(|env| env.borrow().get(name).cloned()) }
+    pub fn return_value(&mut self, value: Value) {
+        self.returning = Some(value);
+    }
 
-   pub fn return_value(&mut self, value: Value) { self.define("return".into(), value); }
+    pub fn retrieve_return(&mut self) -> Value {
+        self.returning.take().unwrap_or(Value::Nil)
+    }
 
-   pub fn retrieve_return(&self) -> Value { self.get("return").unwrap_or(Value::Nil) }
+    pub fn enter(&mut self) {
+        self.env = self.new_scope(Environment::new(self.env.clone()));
+    }
 
-   pub fn enter(&mut self) {
-       self.env.push(Rc::new(RefCell::new(HashMap::new())));
-   }
+    /// Allocates a scope for a block or call, first collecting cyclic
+    /// garbage if enough scopes have piled up.
+    fn new_scope(&mut self, env: Environment) -> Scope {
+        if self.heap.should_collect() {
+            self.heap.collect();
+        }
+        self.heap.alloc(env)
+    }
 
-   pub fn exit(&mut self) { self.env.pop(); }
+    pub fn exit(&mut self) {
+        let enclosing = self.env.borrow().enclosing();
+        if let Some(enclosing) = enclosing {
+            self.env = enclosing;
+        }
+    }
 
     pub fn new() -> Interpreter {
-        let env = Rc::new(RefCell::new(HashMap::new()));
-        env.borrow_mut().insert("clock".into(), Value::RustFunction("clock".into()));
         Interpreter {
-            env: vec![env],
-            rets: HashMap::new(),
+            env: Self::native_globals(false),
+            returning: None,
+            tail_call: None,
+            path: None,
+            modules: HashMap::new(),
+            loading: Vec::new(),
+            out: Box::new(io::stdout()),
+            profile: None,
+            coverage: None,
+            depth: 0,
+            peak_depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            steps: 0,
+            statements: 0,
+            max_steps: None,
+            max_memory: None,
+            line: 0,
+            sandbox: false,
+            strict: false,
+            extensions: false,
+            integers: false,
+            backend: Backend::Tree,
+            warnings: true,
+            ast_cache: false,
+            compiled: HashMap::new(),
+            prelude: None,
+            fake_clock: None,
+            rng: Rng::from_time(),
+            env_vars: HashMap::new(),
+            statement_hooks: Vec::new(),
+            call_hooks: Vec::new(),
+            heap: Heap::default(),
         }
     }
 
-    pub fn interpret(&mut self, exprs: Vec<Expr>) -> Result<Vec<Value>, Vec<Error>> {
-        let mut values = Vec::new();
-        let mut errors = Vec::new();
-        for expr in exprs {
-            match expr.walk(self) {
-                Ok(value) => values.push(value),
-                Err(e) => errors.push(e),
+    /// Runs the prelude, the helpers in `prelude.lox`, into the scope of the
+    /// natives and gives the script a global scope of its own inside it, so
+    /// the script can shadow them. Call after `with_sandbox`.
+    pub fn with_prelude(mut self, enabled: bool) -> Interpreter {
+        if !enabled || self.prelude.is_some() {
+            return self;
+        }
+        let (tokens, _) = lexer::scan(PRELUDE, false);
+        let stmts = Parser::new(tokens)
+            .parse2()
+            .expect("the prelude should parse");
+        Resolver::new().resolve(&stmts);
+        self.execute(&stmts).expect("the prelude should run");
+
+        let prelude = self.env.clone();
+        self.env = Rc::new(RefCell::new(Environment::new(prelude.clone())));
+        self.prelude = Some(prelude);
+        self
+    }
+
+    /// Restricts the script to pure computation: natives that touch the
+    /// file system, environment or other processes are removed, and `import`
+    /// is rejected.
+    pub fn with_sandbox(mut self, sandbox: bool) -> Interpreter {
+        self.sandbox = sandbox;
+        if sandbox {
+            self.env
+                .borrow_mut()
+                .retain(|_, value| !matches!(value, Value::RustFunction(native) if native.ambient));
+        }
+        self
+    }
+
+    /// Makes reading a variable declared without an initializer a runtime
+    /// error until it is assigned.
+    pub fn with_strict(mut self, strict: bool) -> Interpreter {
+        self.strict = strict;
+        self
+    }
+
+    /// Enables the language extensions: relational operators also compare
+    /// strings, lexicographically, and `+` with one string operand converts
+    /// the other to a string as `print` would.
+    pub fn with_extensions(mut self, extensions: bool) -> Interpreter {
+        self.extensions = extensions;
+        self
+    }
+
+    /// Selects how statements are executed.
+    pub fn with_backend(mut self, backend: Backend) -> Interpreter {
+        self.backend = backend;
+        self
+    }
+
+    /// Whether to report the resolver's warnings, such as an assignment used
+    /// as a condition, on stderr before running each file.
+    pub fn with_warnings(mut self, warnings: bool) -> Interpreter {
+        self.warnings = warnings;
+        self
+    }
+
+    /// Evaluates number literals without a fraction to 64-bit integers.
+    /// Integer arithmetic stays integral; it turns into floating point on
+    /// overflow, on division and when mixed with other numbers.
+    pub fn with_integers(mut self, integers: bool) -> Interpreter {
+        self.integers = integers;
+        self
+    }
+
+    /// Aborts execution with a runtime error once more than `max_steps`
+    /// statements and expressions have been evaluated.
+    pub fn with_max_steps(mut self, max_steps: Option<u64>) -> Interpreter {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// Aborts execution with a runtime error once the heap in use, as
+    /// `memory::allocated` counts it, exceeds `max_memory`.
+    pub fn with_max_memory(mut self, max_memory: Option<Bytes>) -> Interpreter {
+        self.max_memory = max_memory;
+        self
+    }
+
+    /// Limits how deeply Lox function calls may nest before raising a
+    /// "Stack overflow." runtime error.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Interpreter {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Runs the scope collector before every allocation instead of only
+    /// once enough scopes have piled up.
+    pub fn with_gc_stress(mut self, enabled: bool) -> Interpreter {
+        self.heap.stress = enabled;
+        self
+    }
+
+    /// Reports every scope collection on stderr.
+    pub fn with_gc_log(mut self, enabled: bool) -> Interpreter {
+        self.heap.log = enabled;
+        self
+    }
+
+    /// Enables recording which statement lines get executed.
+    pub fn with_coverage(mut self, enabled: bool) -> Interpreter {
+        self.coverage = enabled.then(Coverage::default);
+        self
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Enables counting calls and timing them per function.
+    pub fn with_profiling(mut self, enabled: bool) -> Interpreter {
+        self.profile = enabled.then(HashMap::new);
+        self
+    }
+
+    /// Writes the collected call statistics as a table sorted by total time.
+    pub fn write_profile(&self, out: &mut impl Write) -> io::Result<()> {
+        let Some(profile) = &self.profile else {
+            return Ok(());
+        };
+        let mut rows = profile.iter().collect::<Vec<_>>();
+        rows.sort_by(|a, b| b.1.time.cmp(&a.1.time).then(a.0.cmp(b.0)));
+
+        writeln!(
+            out,
+            "{:<20} {:>10} {:>14} {:>14}",
+            "function", "calls", "total", "mean"
+        )?;
+        for (name, stats) in rows {
+            writeln!(
+                out,
+                "{:<20} {:>10} {:>14} {:>14}",
+                name,
+                stats.calls,
+                format!("{:.3?}", stats.time),
+                format!("{:.3?}", stats.time / stats.calls.max(1) as u32)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Redirects the output of `print` statements and the `println` and
+    /// `write` natives.
+    pub fn with_output(mut self, out: impl Write + 'static) -> Interpreter {
+        self.out = Box::new(out);
+        self
+    }
+
+    /// Sets the script being executed so `import` paths resolve relative to it.
+    pub fn with_path(mut self, path: impl Into<PathBuf>) -> Interpreter {
+        let path = path.into();
+        self.loading = path.canonicalize().into_iter().collect();
+        self.path = Some(path);
+        self
+    }
+
+    /// Every module file imported (directly or transitively) so far.
+    pub fn imported_files(&self) -> impl Iterator<Item = &Path> {
+        self.modules.keys().map(|path| path.as_path())
+    }
+
+    /// A fresh global scope for a module: inside the prelude when it is
+    /// loaded, otherwise holding the natives itself.
+    fn module_globals(&self) -> Scope {
+        match &self.prelude {
+            Some(prelude) => Rc::new(RefCell::new(Environment::new(prelude.clone()))),
+            None => Self::native_globals(self.sandbox),
+        }
+    }
+
+    /// A fresh global scope holding the natives available to scripts.
+    fn native_globals(sandbox: bool) -> Scope {
+        let globals = NATIVES
+            .iter()
+            .filter(|native| !(sandbox && native.ambient))
+            .map(|native| (Symbol::intern(native.name), Value::RustFunction(native)))
+            .collect::<Environment>();
+        Rc::new(RefCell::new(globals))
+    }
+
+    /// Executes the module at `path` in a fresh global scope, reusing the
+    /// cached scope when the module has already been loaded.
+    fn load_module(&mut self, path: PathBuf, line: usize) -> Result<Scope, Error> {
+        if let Some(globals) = self.modules.get(&path) {
+            return Ok(globals.clone());
+        }
+        if self.loading.contains(&path) {
+            let cycle = self
+                .loading
+                .iter()
+                .skip_while(|loading| **loading != path)
+                .chain([&path])
+                .map(|path| module::display_name(path))
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            return Err(explain::error(
+                "R0015",
+                format!("Import cycle detected: {}.\n[line {}]", cycle, line),
+            ));
+        }
+
+        let stmts = module::load(&path, self.ast_cache, self.extensions)
+            .map_err(|e| explain::reword(e, |message| format!("{}\n[line {}]", message, line)))?;
+        let globals = self.module_globals();
+        let old_env = std::mem::replace(&mut self.env, globals.clone());
+        let old_path = self.path.replace(path.clone());
+        self.loading.push(path.clone());
+
+        let result = self.execute_program(&stmts);
+
+        self.loading.pop();
+        self.path = old_path;
+        self.env = old_env;
+        result?;
+
+        self.modules.insert(path, globals.clone());
+        Ok(globals)
+    }
+
+    /// Starts `func` with `args` on a new thread, in an interpreter of its
+    /// own configured like this one, and returns the thread's handle. The
+    /// thread is handed the top-level function declarations of the program
+    /// `func` was declared in, as they are now, and runs only those, so
+    /// nothing is shared: `func` must be declared at the top level, and sees
+    /// the program's other top-level functions but none of its variables.
+    pub fn spawn(&self, func: &LoxFunction, args: &[Value]) -> Result<usize, Error> {
+        let top_level = match (&self.prelude, func.closure.borrow().enclosing()) {
+            (Some(prelude), Some(enclosing)) => Rc::ptr_eq(prelude, &enclosing),
+            (None, enclosing) => enclosing.is_none(),
+            (Some(_), None) => false,
+        };
+        if !top_level {
+            return Err(explain::error(
+                "R0028",
+                "Can only spawn functions declared at the top level of a file.",
+            ));
+        }
+        // The program's other functions first, so `func` is declared last
+        // and wins its name.
+        let globals = func.closure.borrow();
+        let mut functions = Vec::<&LoxFunction>::new();
+        for (_, value) in globals.values() {
+            if let Value::Function(other) = value {
+                if Rc::ptr_eq(&other.closure, &func.closure)
+                    && other.memo.is_none()
+                    && !std::ptr::eq(&**other, func)
+                    && !functions.iter().any(|f| std::ptr::eq(*f, &**other))
+                {
+                    functions.push(other);
+                }
             }
         }
-        if !errors.is_empty() {
-            return Err(std::mem::take(&mut errors));
+        functions.push(func);
+        let declarations = functions
+            .into_iter()
+            .map(|function| {
+                Stmt::Func(Func {
+                    span: function.name.span(),
+                    name: function.name.clone(),
+                    params: function.params.clone(),
+                    types: function.types.clone(),
+                    body: function.body.clone(),
+                    slot: Cell::default(),
+                })
+            })
+            .collect::<Vec<_>>();
+        // Statements hold `Rc`s, so they cross over encoded.
+        let declarations = cache::encode(&declarations);
+        drop(globals);
+        let path = self
+            .modules
+            .iter()
+            .find(|(_, globals)| Rc::ptr_eq(globals, &func.closure))
+            .map(|(path, _)| path.clone())
+            .or_else(|| self.path.clone());
+        let args = args
+            .iter()
+            .map(Message::from_value)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (max_depth, max_steps, max_memory) = (self.max_depth, self.max_steps, self.max_memory);
+        let (sandbox, prelude) = (self.sandbox, self.prelude.is_some());
+        let (strict, extensions, integers) = (self.strict, self.extensions, self.integers);
+        let backend = self.backend;
+        let env_vars = self.env_vars.clone();
+        spawn::start(move || {
+            let mut interpreter = Interpreter {
+                env_vars,
+                ..Interpreter::new()
+            };
+            if let Some(path) = path {
+                interpreter = interpreter.with_path(path);
+            }
+            interpreter
+                .with_max_depth(max_depth)
+                .with_max_steps(max_steps)
+                .with_max_memory(max_memory)
+                .with_sandbox(sandbox)
+                .with_prelude(prelude)
+                .with_strict(strict)
+                .with_extensions(extensions)
+                .with_integers(integers)
+                .with_backend(backend)
+                .with_warnings(false)
+                .run_spawned(&declarations, args)
+                .map_err(|e| e.to_string().lines().collect::<Vec<_>>().join(" "))
+        })
+    }
+
+    /// The body of a thread started by `spawn`: declares the functions
+    /// `spawn` encoded and calls the last one.
+    fn run_spawned(&mut self, declarations: &[u8], args: Vec<Message>) -> Result<Message, Error> {
+        let stmts = cache::decode(declarations).expect("spawn encodes declarations");
+        self.execute_program(&stmts)?;
+        let Some(Stmt::Func(func)) = stmts.last() else {
+            unreachable!("spawn declares the function to call last");
+        };
+        let callee = self.variable(&func.name, None)?;
+        let args = args.into_iter().map(Message::into_value).collect();
+        let value = self.call_value(callee, args, func.name.line, func.name.line)?;
+        Message::from_value(&value)
+    }
+
+    /// Evaluates a chain of binary operators such as `1 + 2 + 3 + ...`, whose
+    /// left operands nest as deep as the chain is long, without recursing
+    /// into them: the right operands are evaluated walking down the chain,
+    /// then the operators applied walking back up. Evaluation order is the
+    /// same as `visit_binary`'s.
+    fn binary_chain(&mut self, expr: &Binary) -> Result<Value, Error> {
+        let mut chain = Vec::new();
+        let mut binary = expr;
+        loop {
+            chain.push((binary, self.evaluate(&binary.right)?));
+            let Some(left) = chained(binary) else {
+                break;
+            };
+            self.step()?;
+            binary = left;
         }
-        Ok(values)
+        let mut value = self.evaluate(&binary.left)?;
+        for (binary, right) in chain.into_iter().rev() {
+            value = self.binary(
+                &binary.operator.value,
+                value,
+                right,
+                binary.left.span().line,
+                binary.right.span().line,
+            )?;
+        }
+        Ok(value)
     }
 
-    pub fn execute(&mut self, stmts: &Vec<Stmt>) -> Result<(), Error> {
-        for stmt in stmts {
-            stmt.walk(self)?;
+    /// The value of a literal.
+    pub fn literal(&self, value: &LiteralValue) -> Value {
+        match value {
+            LiteralValue::String(s) => Value::String(s.clone()),
+            LiteralValue::Number(n) => Value::Number(*n),
+            LiteralValue::Integer(n) if self.integers => Value::Integer(*n),
+            LiteralValue::Integer(n) => Value::Number(Number(*n as f64)),
+            LiteralValue::True => Value::Boolean(true),
+            LiteralValue::False => Value::Boolean(false),
+            LiteralValue::Nil => Value::Nil,
         }
+    }
+
+    /// Makes `clock` return `start`, `start + 1`, ... on successive calls
+    /// (and `now` the same in milliseconds), so output that includes times
+    /// is reproducible.
+    pub fn with_fake_clock(mut self, start: Option<f64>) -> Interpreter {
+        self.fake_clock = start;
+        self
+    }
+
+    /// The script's global scope: the outermost scope, or the one inside the
+    /// prelude's.
+    fn global_scope(&self) -> Scope {
+        let mut scope = self.env.clone();
+        loop {
+            let enclosing = scope.borrow().enclosing();
+            match enclosing {
+                Some(enclosing)
+                    if self
+                        .prelude
+                        .as_ref()
+                        .is_some_and(|p| Rc::ptr_eq(p, &enclosing)) =>
+                {
+                    return scope
+                }
+                Some(enclosing) => scope = enclosing,
+                None => return scope,
+            }
+        }
+    }
+
+    /// The global variables, sorted by name. The natives are included unless
+    /// the prelude holds them.
+    pub fn globals(&self) -> impl Iterator<Item = (String, Value)> {
+        variables(&self.global_scope().borrow()).into_iter()
+    }
+
+    /// The variables of every scope in effect, innermost first and ending
+    /// with the globals and the prelude. Locals the resolver placed in slots
+    /// have no names at runtime, so they are named by slot, as `#0`, `#1`...
+    pub fn scopes(&self) -> impl Iterator<Item = Vec<(String, Value)>> {
+        let mut scopes = Vec::new();
+        let mut scope = Some(self.env.clone());
+        while let Some(current) = scope {
+            scopes.push(variables(&current.borrow()));
+            scope = current.borrow().enclosing();
+        }
+        scopes.into_iter()
+    }
+
+    /// Registers `hook` to run before every statement, after any registered
+    /// before it. With the closures backend, only statements compiled after
+    /// the first hook is registered run the hooks.
+    pub fn on_statement(&mut self, hook: impl FnMut(&Stmt, usize) + 'static) {
+        self.statement_hooks.push(Box::new(hook));
+    }
+
+    pub fn has_statement_hooks(&self) -> bool {
+        !self.statement_hooks.is_empty()
+    }
+
+    /// Registers `hook` to run before every call of a Lox or native
+    /// function, after any registered before it. Tail calls are reported
+    /// too, though they reuse their caller's frame.
+    pub fn on_call(&mut self, hook: impl FnMut(&str, &[Value]) + 'static) {
+        self.call_hooks.push(Box::new(hook));
+    }
+
+    /// Saves the global variables and the loaded modules, for `restore`.
+    pub fn snapshot(&self) -> StateSnapshot {
+        StateSnapshot {
+            globals: self.global_scope().borrow().clone(),
+            modules: self
+                .modules
+                .iter()
+                .map(|(path, scope)| (path.clone(), (scope.clone(), scope.borrow().clone())))
+                .collect(),
+        }
+    }
+
+    /// Puts the globals and modules back as they were at `snapshot`:
+    /// variables defined since are gone and modules loaded since will be
+    /// loaded afresh. The scopes are restored in place, so functions defined
+    /// before the snapshot see the restored values. Variables captured in a
+    /// closure's own scope are not part of the snapshot.
+    pub fn restore(&mut self, snapshot: &StateSnapshot) {
+        *self.global_scope().borrow_mut() = snapshot.globals.clone();
+        self.modules = snapshot
+            .modules
+            .iter()
+            .map(|(path, (scope, contents))| {
+                *scope.borrow_mut() = contents.clone();
+                (path.clone(), scope.clone())
+            })
+            .collect();
+    }
+
+    /// Reads imported modules from the AST cache, see `cache`, parsing and
+    /// caching them only when they changed.
+    pub fn with_ast_cache(mut self, enabled: bool) -> Interpreter {
+        self.ast_cache = enabled;
+        self
+    }
+
+    /// Advances the fake clock, returning its reading in seconds, or `None`
+    /// when the real clock is in use.
+    pub fn tick(&mut self) -> Option<f64> {
+        let now = self.fake_clock?;
+        self.fake_clock = Some(now + 1.0);
+        Some(now)
+    }
+
+    pub fn rng(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+
+    /// The environment variable `name`, as set by `setenv` or else inherited
+    /// by the process.
+    pub fn env_var(&self, name: &str) -> Option<String> {
+        match self.env_vars.get(name) {
+            Some(value) => Some(value.clone()),
+            None => env::var(name).ok(),
+        }
+    }
+
+    /// The variables set by `setenv`, for the processes the script starts.
+    pub fn env_vars(&self) -> &HashMap<String, String> {
+        &self.env_vars
+    }
+
+    pub fn set_env_var(&mut self, name: &str, value: &str) {
+        self.env_vars.insert(name.to_string(), value.to_string());
+    }
+
+    /// Writes a value as the `print` statement does.
+    pub fn print(&mut self, value: &Value) -> Result<(), Error> {
+        writeln!(self.out, "{}", value)?;
         Ok(())
     }
-}
 
-impl ExprVisitor<Result<Value, Error>> for Interpreter {
-    fn visit_literal(&mut self, expr: &Literal) -> Result<Value, Error> {
-        match expr {
-            Literal::String(s) => Ok(Value::String(s.clone())),
-            Literal::Number(n) => Ok(Value::Number(n.clone())),
-            Literal::True => Ok(Value::Boolean(true)),
-            Literal::False => Ok(Value::Boolean(false)),
-            Literal::Nil => Ok(Value::Nil),
+    /// Writes a value as `print` does, without the newline.
+    pub fn write(&mut self, value: &Value) -> Result<(), Error> {
+        write!(self.out, "{}", value)?;
+        Ok(())
+    }
+
+    /// Declares a variable, given the value of its initializer if it has
+    /// one.
+    pub fn declare_var(&self, name: &Token, slot: Option<usize>, value: Option<Value>) {
+        let value = match value {
+            Some(value) => value,
+            None if self.strict => Value::Uninitialized,
+            None => Value::Nil,
+        };
+        self.declare(name, slot, value);
+    }
+
+    /// Reads a variable, from its resolved slot or by name.
+    pub fn variable(&self, name: &Token, local: Option<Local>) -> Result<Value, Error> {
+        let value = match local {
+            Some(Local { depth, slot }) => self.env.borrow().get_at(depth, slot),
+            None => self.get(name.symbol()),
+        };
+        match value {
+            Some(Value::Uninitialized) => Err(explain::error(
+                "R0010",
+                format!(
+                    "Variable '{}' used before initialization.\n[line {}]",
+                    name.lexeme, name.line
+                ),
+            )),
+            Some(value) => Ok(value),
+            None => Err(self.undefined_variable(name)),
         }
     }
 
-    fn visit_grouping(&mut self, expr: &Grouping) -> Result<Value, Error> {
-        expr.expr.walk(self)
+    /// Assigns to a variable, in its resolved slot or by name, and returns
+    /// the value assigned.
+    pub fn assign_variable(
+        &self,
+        name: &Token,
+        local: Option<Local>,
+        value: Value,
+    ) -> Result<Value, Error> {
+        let assigned = match local {
+            Some(Local { depth, slot }) => {
+                self.env.borrow_mut().assign_at(depth, slot, value.clone())
+            }
+            None => self.env.borrow_mut().assign(name.symbol(), value.clone()),
+        };
+        if !assigned {
+            return Err(self.undefined_variable(name));
+        }
+        Ok(value)
     }
 
-    fn visit_unary(&mut self, expr: &Unary) -> Result<Value, Error> {
-        let right = expr.right.walk(self)?;
-        match expr.operator.value {
+    /// The error for a variable that doesn't exist, suggesting a name in
+    /// scope that `name` looks like a typo of.
+    fn undefined_variable(&self, name: &Token) -> Error {
+        let names = self.env.borrow().names();
+        let hint = match closest_name(&name.lexeme, names) {
+            Some(suggestion) => format!(" Did you mean '{}'?", suggestion),
+            None => String::new(),
+        };
+        explain::error(
+            "R0001",
+            format!(
+                "Undefined variable '{}'.{}\n[line {}]",
+                name.lexeme, hint, name.line
+            ),
+        )
+    }
+
+    /// Reads `object?.name`: nil when the object is nil, else the property.
+    /// Only this link of a chain is guarded, so `a?.b.c` still fails on `.c`
+    /// when `a` is nil.
+    pub fn optional_property(&self, object: Value, name: &Token) -> Result<Value, Error> {
+        if matches!(object, Value::Nil) {
+            return Ok(Value::Nil);
+        }
+        self.property(object, name)
+    }
+
+    /// Reads the property `name` of an evaluated object.
+    pub fn property(&self, object: Value, name: &Token) -> Result<Value, Error> {
+        match object {
+            Value::Module(module) => match module.globals.borrow().get(name.symbol()) {
+                Some(Value::Uninitialized) => Err(explain::error("R0010", format!(
+                    "Variable '{}' used before initialization.\n[line {}]",
+                    name.lexeme, name.line
+                ))),
+                Some(value) => Ok(value),
+                None => Err(explain::error("R0009", format!(
+                    "Undefined property '{}'.\n[line {}]",
+                    name.lexeme, name.line
+                ))),
+            },
+            Value::Process(process) => match name.lexeme.as_str() {
+                "stdout" => Ok(Value::String(process.stdout.clone())),
+                "stderr" => Ok(Value::String(process.stderr.clone())),
+                "code" => Ok(process
+                    .code
+                    .map_or(Value::Nil, |code| Value::Number(Number(code as f64)))),
+                _ => Err(explain::error("R0049", format!(
+                    "A process has no property '{}', only 'stdout', 'stderr' and 'code'.\n[line {}]",
+                    name.lexeme, name.line
+                ))),
+            },
+            _ => Err(explain::error("R0008", format!(
+                "Only instances have properties.\n[line {}]",
+                name.line
+            ))),
+        }
+    }
+
+    /// Calls an evaluated callee, recording the call when profiling. `line`
+    /// is the line of the call's closing parenthesis, `callee_line` the line
+    /// the callee expression starts on.
+    pub fn call_value(
+        &mut self,
+        callee: Value,
+        args: Vec<Value>,
+        line: usize,
+        callee_line: usize,
+    ) -> Result<Value, Error> {
+        self.run_call_hooks(&callee, &args);
+        if self.profile.is_none() {
+            return self.call(callee, args, line, callee_line);
+        }
+        let name = match &callee {
+            Value::Function(func) => func.name.lexeme.clone(),
+            Value::RustFunction(native) => native.name.to_string(),
+            _ => return self.call(callee, args, line, callee_line),
+        };
+        if let Some(profile) = &mut self.profile {
+            let stats = profile.entry(name.clone()).or_default();
+            stats.calls += 1;
+            stats.active += 1;
+        }
+        let start = Instant::now();
+        let result = self.call(callee, args, line, callee_line);
+        if let Some(stats) = self.profile.as_mut().and_then(|p| p.get_mut(&name)) {
+            stats.active -= 1;
+            if stats.active == 0 {
+                stats.time += start.elapsed();
+            }
+        }
+        result
+    }
+
+    fn run_call_hooks(&mut self, callee: &Value, args: &[Value]) {
+        if self.call_hooks.is_empty() {
+            return;
+        }
+        let name = match callee {
+            Value::Function(func) => func.name.lexeme.as_str(),
+            Value::RustFunction(native) => native.name,
+            _ => return,
+        };
+        for hook in &mut self.call_hooks {
+            hook(name, args);
+        }
+    }
+
+    /// Moves the arguments passed by name, which follow the positional ones,
+    /// to the positions of the callee's parameters with those names, which
+    /// takes a Lox function to name parameters of.
+    pub fn arrange_args(
+        &self,
+        callee: &Value,
+        mut args: Vec<Value>,
+        names: &[Option<Token>],
+        line: usize,
+    ) -> Result<Vec<Value>, Error> {
+        let Some(first) = names.iter().position(Option::is_some) else {
+            return Ok(args);
+        };
+        let func = match callee {
+            Value::Function(func) => func,
+            Value::RustFunction(native) => {
+                return Err(explain::error(
+                    "R0037",
+                    format!(
+                        "'{}' doesn't take named arguments.\n[line {}]",
+                        native.name, line
+                    ),
+                ))
+            }
+            // Not callable, which `call_value` reports.
+            _ => return Ok(args),
+        };
+        let name = &func.name.lexeme;
+        if first > func.params.len() {
+            return Err(arity_error(name, func.params.len(), args.len(), line));
+        }
+        let named = args.split_off(first);
+        let mut slots = args.into_iter().map(Some).collect::<Vec<_>>();
+        slots.resize(func.params.len(), None);
+        for (param, value) in names[first..].iter().flatten().zip(named) {
+            let Some(i) = func
+                .params
+                .iter()
+                .position(|p| p.symbol() == param.symbol())
+            else {
+                return Err(explain::error(
+                    "R0038",
+                    format!(
+                        "No parameter '{}' in '{}'.\n[line {}]",
+                        param.lexeme, name, param.line
+                    ),
+                ));
+            };
+            if slots[i].is_some() {
+                return Err(explain::error(
+                    "R0039",
+                    format!(
+                        "Parameter '{}' of '{}' is given twice.\n[line {}]",
+                        param.lexeme, name, param.line
+                    ),
+                ));
+            }
+            slots[i] = Some(value);
+        }
+        slots
+            .into_iter()
+            .zip(&func.params)
+            .map(|(slot, param)| {
+                slot.ok_or_else(|| {
+                    explain::error(
+                        "R0040",
+                        format!(
+                            "Missing argument for parameter '{}' of '{}'.\n[line {}]",
+                            param.lexeme, name, line
+                        ),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the result of calling `callee` from the current function. A
+    /// Lox function is left as a pending tail call for `call_function` to
+    /// run once this frame is gone; profiling times every call, so it keeps
+    /// the frames.
+    pub fn return_call(
+        &mut self,
+        callee: Value,
+        args: Vec<Value>,
+        line: usize,
+        callee_line: usize,
+    ) -> Result<(), Error> {
+        match callee {
+            Value::Function(func) if self.depth > 0 && self.profile.is_none() => {
+                self.run_call_hooks(&Value::Function(func.clone()), &args);
+                self.tail_call = Some(TailCall { func, args, line });
+            }
+            callee => {
+                let value = self.call_value(callee, args, line, callee_line)?;
+                self.return_value(value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies a unary operator to its evaluated operand, found on `line`.
+    pub fn unary(&self, operator: &TokenValue, right: Value, line: usize) -> Result<Value, Error> {
+        match operator {
             TokenValue::Minus => {
-                if let Value::Number(n) = right {
+                if let Value::Integer(n) = right {
+                    Ok(n.checked_neg()
+                        .map_or(Value::Number(-Number(n as f64)), Value::Integer))
+                } else if let Value::Number(n) = right {
                     Ok(Value::Number(-n))
                 } else {
-                    Err(Error::msg(format!(
-                        "Operand must be a number.\n[line {}]",
-                        expr.operator.line
-                    )))
+                    Err(explain::error(
+                        "R0002",
+                        format!("Operand must be a number.\n[line {}]", line),
+                    ))
                 }
             }
             TokenValue::Bang => match right {
@@ -142,104 +1320,132 @@ impl ExprVisitor<Result<Value, Error>> for Interpreter {
         }
     }
 
-    fn visit_binary(&mut self, expr: &Binary) -> Result<Value, Error> {
-        let right = expr.right.walk(self)?;
-        let left = expr.left.walk(self)?;
-        match expr.operator.value {
+    /// Applies a binary operator to its evaluated operands, found on
+    /// `left_line` and `right_line`.
+    pub fn binary(
+        &self,
+        operator: &TokenValue,
+        left: Value,
+        right: Value,
+        left_line: usize,
+        right_line: usize,
+    ) -> Result<Value, Error> {
+        if let (Value::Integer(l), Value::Integer(r)) = (&left, &right) {
+            if let Some(value) = integer_binary(operator, *l, *r) {
+                return Ok(value);
+            }
+        }
+        let (left, right) = (left.into_float(), right.into_float());
+        // Type errors point at the operand at fault: the left one unless it
+        // has a type the operator accepts.
+        let left_ok = match operator {
+            TokenValue::Plus => matches!(left, Value::Number(_) | Value::String(_)),
+            TokenValue::Greater
+            | TokenValue::GreaterEqual
+            | TokenValue::Less
+            | TokenValue::LessEqual
+                if self.extensions =>
+            {
+                matches!(left, Value::Number(_) | Value::String(_))
+            }
+            TokenValue::In => matches!(left, Value::String(_)),
+            _ => matches!(left, Value::Number(_)),
+        };
+        let line = if left_ok { right_line } else { left_line };
+        match operator {
             TokenValue::Plus => match (left, right) {
                 (Value::Number(l), Value::Number(r)) => Ok(Value::Number(l + r)),
-                (Value::String(l), Value::String(r)) => Ok(Value::String(format!("{}{}", l, r))),
-                _ => Err(Error::msg(format!(
-                    "Operands must be two numbers or two strings.\n[line {}]",
-                    expr.operator.line
-                ))),
+                (Value::String(mut l), Value::String(r)) => {
+                    // A string nothing else holds, like the one the first
+                    // `+` in `a + b + c` makes, is appended to in place.
+                    if let Some(s) = Rc::get_mut(&mut l) {
+                        s.push_str(&r);
+                        return Ok(Value::String(l));
+                    }
+                    let mut s = String::with_capacity(l.len() + r.len());
+                    s.push_str(&l);
+                    s.push_str(&r);
+                    Ok(Value::String(Rc::new(s)))
+                }
+                (l @ Value::String(_), r) | (l, r @ Value::String(_)) if self.extensions => {
+                    Ok(Value::String(Rc::new(format!("{}{}", l, r))))
+                }
+                _ => Err(explain::error(
+                    "R0004",
+                    format!(
+                        "Operands must be two numbers or two strings.\n[line {}]",
+                        line
+                    ),
+                )),
             },
             TokenValue::Minus => {
                 if let (Value::Number(l), Value::Number(r)) = (left, right) {
                     Ok(Value::Number(l - r))
                 } else {
-                    Err(Error::msg(format!(
-                        "Operands must be numbers.\n[line {}]",
-                        expr.operator.line
-                    )))
+                    Err(explain::error(
+                        "R0003",
+                        format!("Operands must be numbers.\n[line {}]", line),
+                    ))
                 }
             }
-            TokenValue::Star => {
-                if let (Value::Number(l), Value::Number(r)) = (left, right) {
-                    Ok(Value::Number(l * r))
-                } else {
-                    Err(Error::msg(format!(
-                        "Operands must be numbers.\n[line {}]",
-                        expr.operator.line
-                    )))
+            TokenValue::Star => match (left, right) {
+                (Value::Number(l), Value::Number(r)) => Ok(Value::Number(l * r)),
+                (Value::String(s), Value::Number(n)) if self.extensions => {
+                    repeat(&s, n, right_line)
                 }
-            }
+                (Value::Number(n), Value::String(s)) if self.extensions => repeat(&s, n, left_line),
+                _ => Err(explain::error(
+                    "R0003",
+                    format!("Operands must be numbers.\n[line {}]", line),
+                )),
+            },
             TokenValue::Slash => {
                 if let (Value::Number(l), Value::Number(r)) = (left, right) {
                     Ok(Value::Number(l / r))
                 } else {
-                    Err(Error::msg(format!(
-                        "Operands must be numbers.\n[line {}]",
-                        expr.operator.line
-                    )))
+                    Err(explain::error(
+                        "R0003",
+                        format!("Operands must be numbers.\n[line {}]", line),
+                    ))
                 }
             }
-            TokenValue::Greater => {
-                if let (Value::Number(l), Value::Number(r)) = (left, right) {
+            TokenValue::Greater => match (left, right) {
+                (Value::Number(l), Value::Number(r)) => Ok(Value::Boolean(l > r)),
+                (Value::String(l), Value::String(r)) if self.extensions => {
                     Ok(Value::Boolean(l > r))
-                } else {
-                    Err(Error::msg(format!(
-                        "Operands must be numbers.\n[line {}]",
-                        expr.operator.line
-                    )))
                 }
-            }
-            TokenValue::GreaterEqual => {
-                if let (Value::Number(l), Value::Number(r)) = (left, right) {
+                _ => Err(self.comparison_error(line)),
+            },
+            TokenValue::GreaterEqual => match (left, right) {
+                (Value::Number(l), Value::Number(r)) => Ok(Value::Boolean(l >= r)),
+                (Value::String(l), Value::String(r)) if self.extensions => {
                     Ok(Value::Boolean(l >= r))
-                } else {
-                    Err(Error::msg(format!(
-                        "Operands must be numbers.\n[line {}]",
-                        expr.operator.line
-                    )))
                 }
-            }
-            TokenValue::Less => {
-                if let (Value::Number(l), Value::Number(r)) = (left, right) {
+                _ => Err(self.comparison_error(line)),
+            },
+            TokenValue::Less => match (left, right) {
+                (Value::Number(l), Value::Number(r)) => Ok(Value::Boolean(l < r)),
+                (Value::String(l), Value::String(r)) if self.extensions => {
                     Ok(Value::Boolean(l < r))
-                } else {
-                    Err(Error::msg(format!(
-                        "Operands must be numbers.\n[line {}]",
-                        expr.operator.line
-                    )))
                 }
-            }
-            TokenValue::LessEqual => {
-                if let (Value::Number(l), Value::Number(r)) = (left, right) {
+                _ => Err(self.comparison_error(line)),
+            },
+            TokenValue::LessEqual => match (left, right) {
+                (Value::Number(l), Value::Number(r)) => Ok(Value::Boolean(l <= r)),
+                (Value::String(l), Value::String(r)) if self.extensions => {
                     Ok(Value::Boolean(l <= r))
-                } else {
-                    Err(Error::msg(format!(
-                        "Operands must be numbers.\n[line {}]",
-                        expr.operator.line
-                    )))
                 }
-            }
-            TokenValue::EqualEqual => match (left, right) {
-                (Value::Number(l), Value::Number(r)) => Ok(Value::Boolean(l == r)),
-                (Value::String(l), Value::String(r)) => Ok(Value::Boolean(l == r)),
-                (Value::Boolean(l), Value::Boolean(r)) => Ok(Value::Boolean(l == r)),
-                (Value::Nil, Value::Nil) => Ok(Value::Boolean(true)),
-
-                _ => Ok(Value::Boolean(false)),
+                _ => Err(self.comparison_error(line)),
             },
-            TokenValue::BangEqual => match (left, right) {
-                (Value::Number(l), Value::Number(r)) => Ok(Value::Boolean(l != r)),
-                (Value::String(l), Value::String(r)) => Ok(Value::Boolean(l != r)),
-                (Value::Boolean(l), Value::Boolean(r)) => Ok(Value::Boolean(l != r)),
-                (Value::Nil, Value::Nil) => Ok(Value::Boolean(false)),
-
-                _ => Ok(Value::Boolean(true)),
+            TokenValue::In => match (left, right) {
+                (Value::String(l), Value::String(r)) => Ok(Value::Boolean(r.contains(l.as_str()))),
+                _ => Err(explain::error(
+                    "R0047",
+                    format!("Operands of 'in' must be two strings.\n[line {}]", line),
+                )),
             },
+            TokenValue::EqualEqual => Ok(Value::Boolean(left.lox_eq(&right))),
+            TokenValue::BangEqual => Ok(Value::Boolean(!left.lox_eq(&right))),
             TokenValue::Or => match (left, right) {
                 (Value::Boolean(l), Value::Boolean(r)) => Ok(Value::Boolean(l || r)),
                 (Value::Boolean(_), v) => Ok(v),
@@ -259,178 +1465,487 @@ impl ExprVisitor<Result<Value, Error>> for Interpreter {
         }
     }
 
-    fn visit_assign(&mut self, expr: &Assign) -> Result<Value, Error> {
-        let new_value = expr.value.walk(self)?;
-        self.assign(expr.name.lexeme.clone(), new_value.clone())?;
-        Ok(new_value)
+    /// The type error of a relational operator, saying what it accepts.
+    fn comparison_error(&self, line: usize) -> Error {
+        let (code, message) = if self.extensions {
+            ("R0004", "Operands must be two numbers or two strings.")
+        } else {
+            ("R0003", "Operands must be numbers.")
+        };
+        explain::error(code, format!("{}\n[line {}]", message, line))
     }
 
-    fn visit_variable(&mut self, expr: &crate::expr::Variable) -> Result<Value, Error> {
-        self
-            .get(&expr.name.lexeme)
-            .ok_or(Error::msg(format!(
-                "Undefined variable '{}'.\n[line {}]",
-                expr.name.lexeme, expr.name.line
-            )))
-    }
-
-    fn visit_call(&mut self, expr: &crate::expr::Call) -> Result<Value, Error> {
-        match expr.callee.walk(self)? {
-            Value::Function(value) => {
-                let LoxFunction {
-                    name,
-                    params,
-                    body,
-                    closure,
-                } = value;
-
-                if params.len() != expr.args.len() {
-                    return Err(Error::msg(format!(
-                        "Expected {} arguments but got {}.\n[line {}]",
-                        params.len(),
-                        expr.args.len(),
-                        expr.paren.line
-                    )));
+    fn call(
+        &mut self,
+        callee: Value,
+        args: Vec<Value>,
+        line: usize,
+        callee_line: usize,
+    ) -> Result<Value, Error> {
+        match callee {
+            Value::Function(func) => self.call_function(func, args, line),
+            Value::RustFunction(native) => {
+                if self.sandbox && native.ambient {
+                    return Err(explain::error(
+                        "R0018",
+                        format!(
+                            "'{}' is not available in sandbox mode.\n[line {}]",
+                            native.name, line
+                        ),
+                    ));
                 }
-                let mut new_env = HashMap::new();
-                let mut args = Vec::new();
-                expr.args
-                    .iter()
-                    .zip(params.iter())
-                    .for_each(|(arg, param)| {
-                        let arg = arg.walk(self).unwrap();
-                        args.push(arg.clone());
-                        new_env.insert(param.lexeme.clone(), arg);
-                    });
-
-                let args = args
-                    .iter()
-                    .map(|v| v.to_string())
-                    .collect::<Vec<String>>()
-                    .join(", ");
-                let func_key = format!("{}({})", &name.lexeme, args);
-                if let Some(ret) = self.rets.get(&func_key) {
-                    return Ok(ret.clone());
+                if native.variadic && args.len() < native.arity {
+                    return Err(explain::error(
+                        "R0007",
+                        format!(
+                            "Expected at least {} arguments but got {} in call to '{}'.\n[line {}]",
+                            native.arity,
+                            args.len(),
+                            native.name,
+                            line
+                        ),
+                    ));
                 }
+                if !native.variadic && native.arity != args.len() {
+                    return Err(arity_error(native.name, native.arity, args.len(), line));
+                }
+                (native.func)(self, &args).map_err(|e| {
+                    explain::reword(e, |message| format!("{}\n[line {}]", message, line))
+                })
+            }
+            callee => Err(explain::error(
+                "R0005",
+                format!(
+                    "Can only call functions and classes, got {}.\n[line {}]",
+                    callee.type_name(),
+                    callee_line
+                ),
+            )),
+        }
+    }
 
-                let old_env = self.env.clone();
-                self.env = closure.clone();
+    /// Calls a Lox function. Calls made by `return f(...)` in its body come
+    /// back here as a pending tail call and run in a loop, so they use no
+    /// native stack and don't count against the depth limit.
+    fn call_function(
+        &mut self,
+        mut func: Rc<LoxFunction>,
+        mut args: Vec<Value>,
+        mut line: usize,
+    ) -> Result<Value, Error> {
+        if self.depth >= self.max_depth {
+            return Err(explain::error(
+                "R0011",
+                format!("Stack overflow.\n[line {}]", line),
+            ));
+        }
+        let old_env = self.env.clone();
+        let old_line = self.line;
+        self.depth += 1;
+        self.peak_depth = self.peak_depth.max(self.depth);
+        // The memoized functions entered so far and their arguments, to cache
+        // the result under once it is known.
+        let mut memoized = Vec::new();
 
-                self.env.push(Rc::new(RefCell::new(new_env)));
+        let result = loop {
+            let LoxFunction {
+                name,
+                params,
+                types,
+                body,
+                closure,
+                memo,
+            } = &*func;
+            if params.len() != args.len() {
+                break Err(arity_error(&name.lexeme, params.len(), args.len(), line));
+            }
+            if self.extensions {
+                if let Some(e) = annotation_error(name, params, types, &args, line) {
+                    break Err(e);
+                }
+            }
 
-                for stmt in body.iter() {
-                    match stmt.walk(self) {
-                        Ok(_) => {}
-                        Err(e) if e.is::<EvalError>() => {break;}
-                        Err(e) => return Err(e),
-                    }
+            if let Some(memo) = memo {
+                if let Some(ret) = memo.borrow().get(&args) {
+                    break Ok(ret);
                 }
+                memoized.push((func.clone(), args.clone()));
+            }
 
-                let ret = self.retrieve_return();
+            let mut env = Environment::new(closure.clone());
+            for (slot, arg) in args.drain(..).enumerate() {
+                env.define_at(slot, arg);
+            }
+            self.env = self.new_scope(env);
 
-                if name.lexeme == "fib" {
-                    self.rets.insert(func_key, ret.clone());
+            let result = match self.execute_body(body) {
+                Err(e) if e.is::<EvalError>() => Ok(()),
+                result => result,
+            };
+            let tail_call = self.tail_call.take();
+            let ret = self.retrieve_return();
+            if let Err(e) = result {
+                break Err(e);
+            }
+            match tail_call {
+                Some(TailCall {
+                    func: next,
+                    args: next_args,
+                    line: next_line,
+                }) => {
+                    func = next;
+                    args = next_args;
+                    line = next_line;
                 }
+                None => break Ok(ret),
+            }
+        };
+
+        self.depth -= 1;
+        self.line = old_line;
+        self.env = old_env;
+        let ret = result?;
+        for (func, args) in memoized {
+            if let Some(memo) = &func.memo {
+                memo.borrow_mut().insert(&args, ret.clone());
+            }
+        }
+        Ok(ret)
+    }
+
+    pub fn interpret(&mut self, exprs: Vec<Expr>) -> Result<Vec<Value>, Vec<Error>> {
+        let mut values = Vec::new();
+        let mut errors = Vec::new();
+        for expr in exprs {
+            match self.evaluate(&expr) {
+                Ok(value) => values.push(value),
+                Err(e) => errors.push(e),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(std::mem::take(&mut errors));
+        }
+        Ok(values)
+    }
+
+    /// Executes a whole file's statements (the main script or a module).
+    pub fn execute_program(&mut self, stmts: &[Stmt]) -> Result<(), Error> {
+        self.resolve_program(stmts);
+        self.execute_resolved(stmts)
+    }
+
+    /// The first half of `execute_program`: resolves the program's variables
+    /// and reports the resolver's warnings.
+    pub fn resolve_program(&mut self, stmts: &[Stmt]) {
+        if let Some(coverage) = &mut self.coverage {
+            coverage.add_program(self.path.as_deref().unwrap_or(Path::new(SCRIPT)), stmts);
+        }
+        let mut resolver = Resolver::new();
+        resolver.resolve(stmts);
+        if self.warnings {
+            for warning in resolver.warnings() {
+                explain::report(warning);
+            }
+        }
+    }
+
+    /// The second half of `execute_program`: runs a resolved program.
+    pub fn execute_resolved(&mut self, stmts: &[Stmt]) -> Result<(), Error> {
+        match self.backend {
+            Backend::Tree => self.execute(stmts),
+            Backend::Closures => compile::compile(stmts, self)
+                .iter()
+                .try_for_each(|stmt| stmt(self)),
+        }
+    }
 
-                self.env = old_env;
+    /// Executes a function body with the selected backend, compiling it on
+    /// its first call.
+    fn execute_body(&mut self, body: &Rc<[Stmt]>) -> Result<(), Error> {
+        if self.backend == Backend::Tree {
+            return self.execute(body);
+        }
+        let compiled = match self.compiled.get(&body.as_ptr()) {
+            Some((_, compiled)) => compiled.clone(),
+            None => {
+                let compiled = compile::compile(body, self);
+                self.compiled
+                    .insert(body.as_ptr(), (body.clone(), compiled.clone()));
+                compiled
+            }
+        };
+        compiled.iter().try_for_each(|stmt| stmt(self))
+    }
 
-                return Ok(ret);
+    fn evaluate(&mut self, expr: &Expr) -> Result<Value, Error> {
+        self.step()?;
+        expr.walk(self)
+    }
+
+    fn execute_stmt(&mut self, stmt: &Stmt) -> Result<(), Error> {
+        self.begin_stmt(stmt.line(), Some(stmt))?;
+        stmt.walk(self)
+    }
+
+    /// Bookkeeping before executing the statement on `line`: steps, records
+    /// coverage and runs the statement hooks on `stmt`, which is only needed
+    /// when there are any; see `has_statement_hooks`.
+    pub fn begin_stmt(&mut self, line: usize, stmt: Option<&Stmt>) -> Result<(), Error> {
+        self.line = line;
+        self.statements += 1;
+        self.step()?;
+        if let Some(stmt) = stmt {
+            for hook in &mut self.statement_hooks {
+                hook(stmt, line);
             }
-            Value::RustFunction(s) if &s == "clock" => {
-                let now = std::time::SystemTime::now()
-                    .duration_since(std::time::SystemTime::UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs();
-                return Ok(Value::Number(now as f64));
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.hit(self.path.as_deref().unwrap_or(Path::new(SCRIPT)), line);
+        }
+        Ok(())
+    }
+
+    /// How many expressions have been evaluated so far.
+    pub fn expressions(&self) -> u64 {
+        self.steps - self.statements
+    }
+
+    /// The deepest Lox function calls have nested so far.
+    pub fn peak_depth(&self) -> usize {
+        self.peak_depth
+    }
+
+    /// Counts one unit of work against the step budget and checks for Ctrl-C
+    /// and the memory limit.
+    pub fn step(&mut self) -> Result<(), Error> {
+        if interrupt::requested() {
+            return Err(explain::error(
+                "R0013",
+                format!("Interrupted.\n[line {}]", self.line),
+            ));
+        }
+        self.steps += 1;
+        if self
+            .max_steps
+            .is_some_and(|max_steps| self.steps > max_steps)
+        {
+            return Err(explain::error(
+                "R0012",
+                format!("Execution limit exceeded.\n[line {}]", self.line),
+            ));
+        }
+        if let Some(max_memory) = self.max_memory {
+            if memory::allocated() > max_memory.0 {
+                return Err(explain::error(
+                    "R0034",
+                    format!(
+                        "Memory limit of {} exceeded.\n[line {}]",
+                        max_memory, self.line
+                    ),
+                ));
             }
-            _ => Err(Error::msg(format!(
-                "Can only call functions and classes.\n[line {}]",
-                expr.paren.line
-            ))),
         }
+        Ok(())
+    }
+
+    fn execute(&mut self, stmts: &[Stmt]) -> Result<(), Error> {
+        for stmt in stmts {
+            self.execute_stmt(stmt)?;
+        }
+        Ok(())
+    }
+}
+
+impl ExprVisitor<Result<Value, Error>> for Interpreter {
+    fn visit_literal(&mut self, expr: &Literal) -> Result<Value, Error> {
+        Ok(self.literal(&expr.value))
+    }
+
+    fn visit_grouping(&mut self, expr: &Grouping) -> Result<Value, Error> {
+        self.evaluate(&expr.expr)
+    }
+
+    fn visit_unary(&mut self, expr: &Unary) -> Result<Value, Error> {
+        let right = self.evaluate(&expr.right)?;
+        self.unary(&expr.operator.value, right, expr.right.span().line)
+    }
+
+    fn visit_binary(&mut self, expr: &Binary) -> Result<Value, Error> {
+        if expr.operator.value == TokenValue::QuestionQuestion {
+            let left = self.evaluate(&expr.left)?;
+            if !matches!(left, Value::Nil) {
+                return Ok(left);
+            }
+            return self.evaluate(&expr.right);
+        }
+        if chained(expr).is_some() {
+            return self.binary_chain(expr);
+        }
+        let right = self.evaluate(&expr.right)?;
+        let left = self.evaluate(&expr.left)?;
+        self.binary(
+            &expr.operator.value,
+            left,
+            right,
+            expr.left.span().line,
+            expr.right.span().line,
+        )
+    }
+
+    fn visit_assign(&mut self, expr: &Assign) -> Result<Value, Error> {
+        let value = self.evaluate(&expr.value)?;
+        self.assign_variable(&expr.name, expr.local.get(), value)
+    }
+
+    fn visit_variable(&mut self, expr: &crate::expr::Variable) -> Result<Value, Error> {
+        self.variable(&expr.name, expr.local.get())
+    }
+
+    fn visit_call(&mut self, expr: &Call) -> Result<Value, Error> {
+        let callee = self.evaluate(&expr.callee)?;
+        let args = expr
+            .args
+            .iter()
+            .map(|arg| self.evaluate(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+        let args = self.arrange_args(&callee, args, &expr.names, expr.paren.line)?;
+        self.call_value(callee, args, expr.paren.line, expr.callee.span().line)
+    }
+
+    fn visit_get(&mut self, expr: &Get) -> Result<Value, Error> {
+        let object = self.evaluate(&expr.object)?;
+        if expr.optional {
+            return self.optional_property(object, &expr.name);
+        }
+        self.property(object, &expr.name)
     }
 }
 
 impl StmtVisitor<Result<(), Error>> for Interpreter {
     fn visit_print(&mut self, stmt: &Print) -> Result<(), Error> {
-        let value = stmt.expr.walk(self)?;
-        println!("{}", value);
-        Ok(())
+        let value = self.evaluate(&stmt.expr)?;
+        self.print(&value)
     }
 
     fn visit_expression(&mut self, stmt: &Expression) -> Result<(), Error> {
-        stmt.expr.walk(self)?;
+        self.evaluate(&stmt.expr)?;
         Ok(())
     }
 
     fn visit_var(&mut self, stmt: &Var) -> Result<(), Error> {
-        let value = stmt.initializer.as_ref();
-        if let Some(value) = value {
-            let value = value.walk(self)?; 
-            self.define(stmt.name.lexeme.clone(), value);
-
-        } else {
-            self.define(stmt.name.lexeme.clone(), Value::Nil);
-        }
+        let value = match &stmt.initializer {
+            Some(value) => Some(self.evaluate(value)?),
+            None => None,
+        };
+        self.declare_var(&stmt.name, stmt.slot.get(), value);
         Ok(())
     }
 
     fn visit_block(&mut self, stmt: &Block) -> Result<(), Error> {
         self.enter();
-        self.execute(&stmt.statements)?;
+        let result = self.execute(&stmt.statements);
         self.exit();
-        Ok(())
+        result
     }
 
     fn visit_if(&mut self, stmt: &If) -> Result<(), Error> {
-        if Value::Boolean(false) != stmt.condition.walk(self)? {
-            stmt.then_branch.walk(self)?;
+        if self.evaluate(&stmt.condition)?.is_truthy() {
+            self.execute_stmt(&stmt.then_branch)?;
         } else if let Some(else_branch) = &stmt.else_branch {
-            else_branch.walk(self)?;
+            self.execute_stmt(else_branch)?;
         }
         Ok(())
     }
 
     fn visit_while(&mut self, stmt: &While) -> Result<(), Error> {
-        while Value::Boolean(false) != stmt.condition.walk(self)? {
-            stmt.body.walk(self)?;
-        }
-        Ok(())
-    }
-
-    fn visit_for(&mut self, stmt: &For) -> Result<(), Error> {
-        if let Some(init) = &stmt.init {
-            init.walk(self)?;
-        }
-        while Value::Boolean(false) != stmt.condition.walk(self)? {
-            stmt.body.walk(self)?;
-            if let Some(update) = &stmt.update {
-                update.walk(self)?;
-            }
+        while self.evaluate(&stmt.condition)?.is_truthy() {
+            self.execute_stmt(&stmt.body)?;
         }
         Ok(())
     }
 
     fn visit_func(&mut self, stmt: &crate::stmt::Func) -> Result<(), Error> {
         let closure = self.env.clone();
-        self.define(stmt.name.lexeme.clone(), Value::Function(LoxFunction {
+        self.declare(
+            &stmt.name,
+            stmt.slot.get(),
+            Value::Function(Rc::new(LoxFunction {
                 name: stmt.name.clone(),
                 params: stmt.params.clone(),
+                types: stmt.types.clone(),
                 body: stmt.body.clone(),
                 closure,
-            }));
+                memo: None,
+            })),
+        );
         Ok(())
     }
 
     fn visit_return(&mut self, stmt: &crate::stmt::Return) -> Result<(), Error> {
-        let value = &stmt.value;
-        if let Some(value) = value {
-            let value = value.walk(self)?;
-            self.return_value(value);
-        } else {
-            self.return_value(Value::Nil);
+        match &stmt.value {
+            Some(Expr::Call(call)) => {
+                self.step()?;
+                let callee = self.evaluate(&call.callee)?;
+                let args = call
+                    .args
+                    .iter()
+                    .map(|arg| self.evaluate(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let args = self.arrange_args(&callee, args, &call.names, call.paren.line)?;
+                self.return_call(callee, args, call.paren.line, call.callee.span().line)?;
+            }
+            Some(value) => {
+                let value = self.evaluate(value)?;
+                self.return_value(value);
+            }
+            None => self.return_value(Value::Nil),
+        }
+
+        Err(EvalError::Return.into())
+    }
+
+    fn visit_import(&mut self, stmt: &Import) -> Result<(), Error> {
+        let TokenValue::String(import) = &stmt.path.value else {
+            unreachable!()
+        };
+        if self.sandbox {
+            return Err(explain::error(
+                "R0017",
+                format!(
+                    "Imports are not available in sandbox mode.\n[line {}]",
+                    stmt.keyword.line
+                ),
+            ));
+        }
+        let path = module::resolve(self.path.as_deref(), import).ok_or_else(|| {
+            explain::error(
+                "R0014",
+                format!(
+                    "Could not find module '{}'.\n[line {}]",
+                    import, stmt.keyword.line
+                ),
+            )
+        })?;
+        let globals = self.load_module(path, stmt.keyword.line)?;
+
+        if let Some(alias) = &stmt.alias {
+            let module = Module {
+                name: alias.lexeme.clone(),
+                globals,
+            };
+            self.declare(alias, stmt.slot.get(), Value::Module(Rc::new(module)));
+            return Ok(());
         }
 
-        return Err(EvalError::Return.into());
+        let exports = globals
+            .borrow()
+            .values()
+            .filter(|(_, value)| matches!(value, Value::Function(_)))
+            .map(|(name, value)| (*name, value.clone()))
+            .collect::<Vec<_>>();
+        for (name, value) in exports {
+            self.define(name, value);
+        }
+        Ok(())
     }
 }
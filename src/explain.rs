@@ -0,0 +1,594 @@
+use std::{
+    fmt::{self, Display},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use anyhow::Error;
+
+/// A kind of diagnostic the interpreter reports, identified by a stable code:
+/// `L` for lexical errors, `P` for parse errors, `R` for runtime errors and
+/// `W` for warnings. Each diagnostic carries its code from where it is raised;
+/// the messages here are only for `explain`.
+pub struct Diagnostic {
+    pub code: &'static str,
+    /// The message as reported, with `{}` wherever it varies.
+    pub message: &'static str,
+    pub explanation: &'static str,
+    /// A program that produces the diagnostic.
+    pub example: &'static str,
+}
+
+pub static DIAGNOSTICS: &[Diagnostic] = &[
+    Diagnostic {
+        code: "L0001",
+        message: "Unterminated string.",
+        explanation: "A string literal was opened with '\"' but the file ended before the closing quote. Strings may span lines, so the missing quote can be far above where the file ends.",
+        example: "print \"hello;",
+    },
+    Diagnostic {
+        code: "L0002",
+        message: "Unexpected character: {}",
+        explanation: "The character is not part of any token in Lox, for example '@', '#' or '$' outside a string or comment. ':', '?' and '|' are only part of tokens with --extensions.",
+        example: "var price = $5;",
+    },
+    Diagnostic {
+        code: "P0001",
+        message: "Expect expression.",
+        explanation: "An expression was expected here but the next token can't start one, such as an operator with a missing operand.",
+        example: "print 1 + ;",
+    },
+    Diagnostic {
+        code: "P0002",
+        message: "Expect ';' after value.",
+        explanation: "A print or return statement must end with a semicolon.",
+        example: "print 1\nprint 2;",
+    },
+    Diagnostic {
+        code: "P0003",
+        message: "Expect ';' after expression.",
+        explanation: "An expression statement must end with a semicolon.",
+        example: "var a;\na = 1\na = 2;",
+    },
+    Diagnostic {
+        code: "P0004",
+        message: "Expect ';' after variable declaration.",
+        explanation: "A var declaration must end with a semicolon.",
+        example: "var a = 1\nprint a;",
+    },
+    Diagnostic {
+        code: "P0005",
+        message: "Expect variable name.",
+        explanation: "'var' must be followed by the name of the variable being declared.",
+        example: "var = 1;",
+    },
+    Diagnostic {
+        code: "P0006",
+        message: "Invalid assignment target.",
+        explanation: "Only variables can be assigned to. The left-hand side of '=' is some other expression.",
+        example: "1 + 2 = 3;",
+    },
+    Diagnostic {
+        code: "P0007",
+        message: "Expect '(' after 'if'.",
+        explanation: "The condition of an if statement must be in parentheses.",
+        example: "if true print 1;",
+    },
+    Diagnostic {
+        code: "P0008",
+        message: "Expect ')' after if condition.",
+        explanation: "The parenthesized condition of an if statement was not closed.",
+        example: "if (true print 1;",
+    },
+    Diagnostic {
+        code: "P0009",
+        message: "Expect '(' after 'while'.",
+        explanation: "The condition of a while loop must be in parentheses.",
+        example: "while true print 1;",
+    },
+    Diagnostic {
+        code: "P0010",
+        message: "Expect ')' after condition.",
+        explanation: "The parenthesized condition of a while loop was not closed.",
+        example: "while (true print 1;",
+    },
+    Diagnostic {
+        code: "P0011",
+        message: "Expect '(' after 'for'.",
+        explanation: "The clauses of a for loop must be in parentheses.",
+        example: "for var i = 0; i < 3; i = i + 1) print i;",
+    },
+    Diagnostic {
+        code: "P0012",
+        message: "Expect ')' after expression.",
+        explanation: "A parenthesized expression was not closed.",
+        example: "print (1 + 2;",
+    },
+    Diagnostic {
+        code: "P0013",
+        message: "Expect ';' after loop condition.",
+        explanation: "The condition clause of a for loop must be followed by ';', even when the increment clause is left out.",
+        example: "for (var i = 0; i < 3) print i;",
+    },
+    Diagnostic {
+        code: "P0014",
+        message: "Expect ')' after for clauses.",
+        explanation: "The clauses of a for loop were not closed with ')'.",
+        example: "for (var i = 0; i < 3; i = i + 1 print i;",
+    },
+    Diagnostic {
+        code: "P0015",
+        message: "Expect function name.",
+        explanation: "'fun' must be followed by the name of the function being declared.",
+        example: "fun (a) { return a; }",
+    },
+    Diagnostic {
+        code: "P0016",
+        message: "Expect '(' after function name.",
+        explanation: "A function declaration needs a parameter list, even an empty one.",
+        example: "fun greet { print \"hi\"; }",
+    },
+    Diagnostic {
+        code: "P0017",
+        message: "Expect ')' after paramters.",
+        explanation: "The parameter list of a function declaration was not closed, or a parameter is not a plain name.",
+        example: "fun add(a, b { return a + b; }",
+    },
+    Diagnostic {
+        code: "P0018",
+        message: "Expect '{' before function body.",
+        explanation: "A function's body must be a block.",
+        example: "fun add(a, b) return a + b;",
+    },
+    Diagnostic {
+        code: "P0019",
+        message: "Expect '}' .",
+        explanation: "A block or function body was opened with '{' but the file ended before the closing '}'.",
+        example: "fun f() {\n  print 1;",
+    },
+    Diagnostic {
+        code: "P0020",
+        message: "Expect ')' after arguments.",
+        explanation: "The argument list of a call was not closed.",
+        example: "print max(1, 2;",
+    },
+    Diagnostic {
+        code: "P0021",
+        message: "Can't have more than 255 arguments.",
+        explanation: "A call passes more than 255 arguments, the most a call can take.",
+        example: "// Any call with 256 or more arguments:\nf(a1, a2, a3, ..., a256);",
+    },
+    Diagnostic {
+        code: "P0022",
+        message: "Expect property name after '.'.",
+        explanation: "'.' must be followed by the name of the property to read.",
+        example: "import \"math.lox\" as math;\nprint math.;",
+    },
+    Diagnostic {
+        code: "P0023",
+        message: "Can't use '{}' outside of a class.",
+        explanation: "'this' and 'super' only have a meaning inside a class's methods, and classes are not supported yet.",
+        example: "print this;",
+    },
+    Diagnostic {
+        code: "P0024",
+        message: "Expect module path after 'import'.",
+        explanation: "'import' must be followed by the path of the module as a string.",
+        example: "import math;",
+    },
+    Diagnostic {
+        code: "P0025",
+        message: "Expect module name after 'as'.",
+        explanation: "'as' in an import must be followed by the name to bind the module to.",
+        example: "import \"math.lox\" as;",
+    },
+    Diagnostic {
+        code: "P0026",
+        message: "Expect ';' after module path.",
+        explanation: "An import must end with a semicolon.",
+        example: "import \"math.lox\"\nprint max(1, 2);",
+    },
+    Diagnostic {
+        code: "P0027",
+        message: "Expect end of expression.",
+        explanation: "parse-expr reads exactly one expression, but more tokens follow it.",
+        example: "1 + 2 3",
+    },
+    Diagnostic {
+        code: "P0028",
+        message: "Too much nesting.",
+        explanation: "Statements and expressions can nest at most 1000 deep, counting blocks, bodies, branches, groupings and operands, and each operator, call or property access in a chain such as `1 + 2 + 3`. Pull the inner parts out into variables or functions.",
+        example: "print ((((((((((1)))))))))); // ...but a thousand levels deep",
+    },
+    Diagnostic {
+        code: "P0029",
+        message: "Expect type after ':'.",
+        explanation: "A ':' after a parameter starts its type annotation, which must be a type name.",
+        example: "fun f(a: 1) {}",
+    },
+    Diagnostic {
+        code: "P0030",
+        message: "Unknown type '{}'.",
+        explanation: "Parameters can be annotated with boolean, number, string, function or module.",
+        example: "fun f(a: int) {}",
+    },
+    Diagnostic {
+        code: "P0031",
+        message: "Expect named argument after named ones.",
+        explanation: "Arguments passed by name, as in 'f(x: 1)', must come after all the positional ones.",
+        example: "fun f(a, b) {}\nf(a: 1, 2);",
+    },
+    Diagnostic {
+        code: "R0001",
+        message: "Undefined variable '{}'.{}",
+        explanation: "The variable was never declared, or its declaration is in a scope that doesn't enclose this use. A close name in scope is suggested when there is one.",
+        example: "var count = 1;\nprint cuont;",
+    },
+    Diagnostic {
+        code: "R0002",
+        message: "Operand must be a number.",
+        explanation: "Unary '-' only applies to numbers.",
+        example: "print -\"five\";",
+    },
+    Diagnostic {
+        code: "R0003",
+        message: "Operands must be numbers.",
+        explanation: "Arithmetic and comparison operators only apply to numbers ('+' also joins two strings). Convert the operands first.",
+        example: "print 1 < \"2\";",
+    },
+    Diagnostic {
+        code: "R0004",
+        message: "Operands must be two numbers or two strings.",
+        explanation: "'+' adds two numbers or joins two strings, and with --extensions the comparison operators also compare strings; mixing the two is an error.",
+        example: "print \"total: \" + 3;",
+    },
+    Diagnostic {
+        code: "R0005",
+        message: "Can only call functions and classes, got {}.",
+        explanation: "The expression before '(' evaluated to something that can't be called, such as a number, a string or nil.",
+        example: "var f = 1;\nf();",
+    },
+    Diagnostic {
+        code: "R0006",
+        message: "Expected {} arguments but got {} in call to '{}'.",
+        explanation: "A function was called with a different number of arguments than it has parameters.",
+        example: "fun add(a, b) { return a + b; }\nadd(1);",
+    },
+    Diagnostic {
+        code: "R0007",
+        message: "Expected at least {} arguments but got {} in call to '{}'.",
+        explanation: "A native that takes a variable number of arguments was called with fewer than it needs.",
+        example: "format();",
+    },
+    Diagnostic {
+        code: "R0008",
+        message: "Only instances have properties.",
+        explanation: "'.' was applied to a value without properties. Only modules (and the results of exec) have properties.",
+        example: "var n = 1;\nprint n.size;",
+    },
+    Diagnostic {
+        code: "R0009",
+        message: "Undefined property '{}'.",
+        explanation: "The module has no global by that name.",
+        example: "import \"math.lox\" as math;\nprint math.nope;",
+    },
+    Diagnostic {
+        code: "R0010",
+        message: "Variable '{}' used before initialization.",
+        explanation: "With --strict, a variable declared without an initializer must be assigned before it is read.",
+        example: "var a;\nprint a;",
+    },
+    Diagnostic {
+        code: "R0011",
+        message: "Stack overflow.",
+        explanation: "Function calls nested deeper than --max-depth allows, usually because of recursion without a base case. Calls in 'return f(...)' position don't count.",
+        example: "fun f(n) { return 1 + f(n + 1); }\nf(0);",
+    },
+    Diagnostic {
+        code: "R0012",
+        message: "Execution limit exceeded.",
+        explanation: "The program evaluated more statements and expressions than --max-steps allows.",
+        example: "while (true) {}",
+    },
+    Diagnostic {
+        code: "R0013",
+        message: "Interrupted.",
+        explanation: "Ctrl-C was pressed while the program was running.",
+        example: "sleep(60);",
+    },
+    Diagnostic {
+        code: "R0014",
+        message: "Could not find module '{}'.",
+        explanation: "No file exists at the import path, which is relative to the importing file.",
+        example: "import \"missing.lox\";",
+    },
+    Diagnostic {
+        code: "R0015",
+        message: "Import cycle detected: {}.",
+        explanation: "A module imports itself, directly or through other modules. The chain of imports is listed.",
+        example: "// a.lox\nimport \"a.lox\";",
+    },
+    Diagnostic {
+        code: "R0016",
+        message: "Could not read module '{}'.",
+        explanation: "The module file exists but could not be read.",
+        example: "import \"unreadable.lox\";",
+    },
+    Diagnostic {
+        code: "R0017",
+        message: "Imports are not available in sandbox mode.",
+        explanation: "--sandbox forbids imports, which read files.",
+        example: "import \"math.lox\";",
+    },
+    Diagnostic {
+        code: "R0018",
+        message: "'{}' is not available in sandbox mode.",
+        explanation: "--sandbox removes natives that reach outside the interpreter, such as getenv, exec and spawn.",
+        example: "// run with --sandbox\ngetenv(\"HOME\");",
+    },
+    Diagnostic {
+        code: "R0019",
+        message: "Argument must be {}.",
+        explanation: "A native was passed an argument of the wrong type.",
+        example: "sleep(\"1\");",
+    },
+    Diagnostic {
+        code: "R0020",
+        message: "Invalid environment variable.",
+        explanation: "setenv was given an empty name, or a name or value that contains '=' or a NUL character where not allowed.",
+        example: "setenv(\"A=B\", \"1\");",
+    },
+    Diagnostic {
+        code: "R0021",
+        message: "Unmatched '{}' in format string.",
+        explanation: "A format string has a '{' without a closing '}', or a lone '}'. Write '{{' and '}}' for literal braces.",
+        example: "format(\"{\");",
+    },
+    Diagnostic {
+        code: "R0022",
+        message: "Invalid placeholder '{}'.",
+        explanation: "A format placeholder must be '{}' or an argument index such as '{0}'.",
+        example: "format(\"{x}\", 1);",
+    },
+    Diagnostic {
+        code: "R0023",
+        message: "Placeholder refers to argument {} but only {} were given.",
+        explanation: "A format string has more placeholders, or a higher index, than there are arguments after it.",
+        example: "format(\"{} {}\", 1);",
+    },
+    Diagnostic {
+        code: "R0024",
+        message: "Lower bound must not exceed upper bound.",
+        explanation: "randomInt(lo, hi) needs lo <= hi.",
+        example: "randomInt(6, 1);",
+    },
+    Diagnostic {
+        code: "R0025",
+        message: "Unknown time directive '{}'.",
+        explanation: "formatTime supports %Y, %m, %d, %H, %M, %S and %%.",
+        example: "formatTime(now(), \"%A\");",
+    },
+    Diagnostic {
+        code: "R0026",
+        message: "Time format ends with '%'.",
+        explanation: "A '%' at the end of a formatTime format has no directive after it. Write '%%' for a literal '%'.",
+        example: "formatTime(now(), \"100%\");",
+    },
+    Diagnostic {
+        code: "R0027",
+        message: "Could not run process: {}.",
+        explanation: "exec or execArgs could not start the process, usually because the program doesn't exist.",
+        example: "execArgs(\"no-such-program\");",
+    },
+    Diagnostic {
+        code: "R0028",
+        message: "Can only spawn functions {}.",
+        explanation: "A spawned function runs in a fresh interpreter given only the top-level function declarations of the program it was declared in, so it must be one of them: declared at the top level of a script, module or REPL entry.",
+        example: "{\n  fun work() {}\n  spawn(work);\n}",
+    },
+    Diagnostic {
+        code: "R0029",
+        message: "Can't send a {} between threads.",
+        explanation: "Only nil, booleans, numbers and strings can be passed to spawned functions, returned from them or sent on channels.",
+        example: "fun f() {}\nsend(channel(), f);",
+    },
+    Diagnostic {
+        code: "R0030",
+        message: "Spawned thread failed: {}",
+        explanation: "join found that the spawned function raised an error; its message follows.",
+        example: "fun fail() { return -nil; }\njoin(spawn(fail));",
+    },
+    Diagnostic {
+        code: "R0031",
+        message: "Invalid channel.",
+        explanation: "send or recv was given a number that channel() never returned.",
+        example: "recv(12345);",
+    },
+    Diagnostic {
+        code: "R0032",
+        message: "Invalid thread handle.",
+        explanation: "join was given a number that spawn never returned.",
+        example: "join(12345);",
+    },
+    Diagnostic {
+        code: "R0033",
+        message: "Thread has already been joined.",
+        explanation: "Each spawned thread can be joined once.",
+        example: "fun f() {}\nvar t = spawn(f);\njoin(t);\njoin(t);",
+    },
+    Diagnostic {
+        code: "R0034",
+        message: "Memory limit of {} exceeded.",
+        explanation: "The interpreter's heap grew past what --max-memory allows. The count covers everything the interpreter allocates, the program's own code included.",
+        example: "var s = \"x\";\nwhile (true) s = s + s;",
+    },
+    Diagnostic {
+        code: "R0035",
+        message: "Expected {} for parameter '{}' of '{}' but got {}.",
+        explanation: "With --extensions, a call checks each argument against the type its parameter is annotated with. Without it, annotations don't parse.",
+        example: "fun greet(name: string) { print \"hi \" + name; }\ngreet(1);",
+    },
+    Diagnostic {
+        code: "R0037",
+        message: "'{}' doesn't take named arguments.",
+        explanation: "Native functions have no parameter names to pass arguments to; pass their arguments by position.",
+        example: "clock(now: true);",
+    },
+    Diagnostic {
+        code: "R0038",
+        message: "No parameter '{}' in '{}'.",
+        explanation: "An argument is passed by a name none of the function's parameters has.",
+        example: "fun area(width, height) {}\narea(width: 1, depth: 2);",
+    },
+    Diagnostic {
+        code: "R0039",
+        message: "Parameter '{}' of '{}' is given twice.",
+        explanation: "An argument is passed by the name of a parameter that a positional argument, or another named one, already gives.",
+        example: "fun area(width, height) {}\narea(1, width: 2);",
+    },
+    Diagnostic {
+        code: "R0040",
+        message: "Missing argument for parameter '{}' of '{}'.",
+        explanation: "With named arguments, every parameter must still get exactly one argument, by position or by name.",
+        example: "fun area(width, height) {}\narea(width: 1);",
+    },
+    Diagnostic {
+        code: "R0044",
+        message: "Can only repeat a string a whole number of times, not {}.",
+        explanation: "With --extensions, multiplying a string by a number repeats it, which takes a count of zero or more with no fraction.",
+        example: "print \"ab\" * 1.5;",
+    },
+    Diagnostic {
+        code: "R0045",
+        message: "Repeated string would be longer than {} bytes.",
+        explanation: "Repeating a string with '*' is capped at 256 MiB, so a mistaken count fails instead of exhausting memory.",
+        example: "print \"ab\" * 1000000000;",
+    },
+    Diagnostic {
+        code: "R0047",
+        message: "Operands of 'in' must be two strings.",
+        explanation: "'in' tests whether one string occurs in another; strings are the only values that contain others.",
+        example: "print 1 in \"123\";",
+    },
+    Diagnostic {
+        code: "R0048",
+        message: "Memo limit must be positive.",
+        explanation: "memoize(fn, limit) keeps at most limit results, so it needs room for at least one.",
+        example: "fun f(n) { return n; } memoize(f, 0);",
+    },
+    Diagnostic {
+        code: "R0049",
+        message: "A process has no property '{}', only 'stdout', 'stderr' and 'code'.",
+        explanation: "exec and execArgs return a finished process, which has its output as 'stdout' and 'stderr' and its exit status as 'code'.",
+        example: "print exec(\"true\").status;",
+    },
+    Diagnostic {
+        code: "W0001",
+        message: "Assignment used as a condition. Did you mean '=='? Wrap it in parentheses if not.",
+        explanation: "The condition of an if or while is an assignment, which is usually a typo for '=='. Wrap the assignment in another pair of parentheses if it is intended.",
+        example: "var x = 1;\nif (x = 2) print x;",
+    },
+    Diagnostic {
+        code: "W0002",
+        message: "Unreachable code.",
+        explanation: "The statement follows a return on every path through its block, so it never runs.",
+        example: "fun f() {\n  return 1;\n  print \"never\";\n}",
+    },
+    Diagnostic {
+        code: "W0003",
+        message: "Condition is always {}.",
+        explanation: "The condition of an if or while doesn't depend on anything that can change, so one branch never runs. 'while (true)' is allowed for loops left with return.",
+        example: "if (1 > 2) print \"never\";",
+    },
+];
+
+static SHOW_CODES: AtomicBool = AtomicBool::new(false);
+
+/// Makes `report` follow each diagnostic with its code.
+pub fn show_codes(enabled: bool) {
+    SHOW_CODES.store(enabled, Ordering::Relaxed);
+}
+
+/// A diagnostic as reported, which knows which kind in `DIAGNOSTICS` it is,
+/// if any.
+pub trait Coded: Display {
+    fn code(&self) -> Option<&'static str>;
+}
+
+impl<T: Coded> Coded for &T {
+    fn code(&self) -> Option<&'static str> {
+        (*self).code()
+    }
+}
+
+impl Coded for Error {
+    fn code(&self) -> Option<&'static str> {
+        self.downcast_ref::<CodedError>().map(|error| error.code)
+    }
+}
+
+/// An error of a kind in `DIAGNOSTICS`, raised as an `anyhow::Error` so it
+/// propagates like any other and still carries its code.
+#[derive(Debug)]
+pub struct CodedError {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl Display for CodedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for CodedError {}
+
+/// An error of the kind with the given code.
+pub fn error(code: &'static str, message: impl Into<String>) -> Error {
+    Error::new(CodedError {
+        code,
+        message: message.into(),
+    })
+}
+
+/// `error` with its message rewritten by `f`, such as to add the line it was
+/// raised on, keeping its code if it has one.
+pub fn reword(error: Error, f: impl FnOnce(&str) -> String) -> Error {
+    let message = f(&error.to_string());
+    match error.code() {
+        Some(code) => self::error(code, message),
+        None => Error::msg(message),
+    }
+}
+
+/// Prints a diagnostic to stderr as it was formatted, followed by a pointer
+/// to `explain` when codes are enabled and the diagnostic has one.
+pub fn report(diagnostic: impl Coded) {
+    eprintln!("{}", diagnostic);
+    if !SHOW_CODES.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Some(code) = diagnostic.code() {
+        eprintln!("[{}] Run 'explain {}' for details.", code, code);
+    }
+}
+
+/// `explain <code>`: prints what a diagnostic means and a program that
+/// produces it.
+pub fn run(code: &str) -> i32 {
+    let Some(diagnostic) = DIAGNOSTICS
+        .iter()
+        .find(|diagnostic| diagnostic.code.eq_ignore_ascii_case(code))
+    else {
+        eprintln!("Unknown error code: {}", code);
+        return 64;
+    };
+    println!("{}: {}", diagnostic.code, diagnostic.message);
+    println!();
+    println!("{}", diagnostic.explanation);
+    println!();
+    println!("Example:");
+    for line in diagnostic.example.lines() {
+        println!("    {}", line);
+    }
+    0
+}
@@ -0,0 +1,256 @@
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+    str::FromStr,
+};
+
+use crate::{
+    expr::Expr,
+    stmt::Stmt,
+    token::{Token, TokenValue, KEYWORDS},
+    walk::{walk_children, Node, Visitor},
+};
+
+/// Output formats for `tokenize --format=...`. `Text` is the codecrafters
+/// format; the others add positions for external tooling.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    /// One token per line: line, column, type, lexeme and literal, separated
+    /// by tabs.
+    Tsv,
+    /// A JSON array of token objects.
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "tsv" => Ok(Format::Tsv),
+            "json" => Ok(Format::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+pub fn write_tokens(
+    out: &mut impl Write,
+    source: &str,
+    tokens: &[Token],
+    format: Format,
+) -> io::Result<()> {
+    let positions = Positions::new(source);
+    match format {
+        Format::Text => {
+            for token in tokens {
+                writeln!(out, "{}", token)?;
+            }
+        }
+        Format::Tsv => {
+            writeln!(out, "line\tcolumn\ttype\tlexeme\tliteral")?;
+            for token in tokens {
+                let (line, column) = positions.of(token.start);
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}\t{}",
+                    line,
+                    column,
+                    token.value,
+                    tsv_escape(&token.lexeme),
+                    match &token.value {
+                        TokenValue::String(s) => tsv_escape(s),
+                        _ => literal(token),
+                    }
+                )?;
+            }
+        }
+        Format::Json => {
+            writeln!(out, "[")?;
+            for (i, token) in tokens.iter().enumerate() {
+                let literal = match &token.value {
+                    TokenValue::String(s) => json_string(s),
+                    TokenValue::Number(_) => literal(token),
+                    _ => "null".to_string(),
+                };
+                let (line, column) = positions.of(token.start);
+                writeln!(
+                    out,
+                    "  {{\"type\": {}, \"lexeme\": {}, \"literal\": {}, \"line\": {}, \"column\": {}}}{}",
+                    json_string(&token.value.to_string()),
+                    json_string(&token.lexeme),
+                    literal,
+                    line,
+                    column,
+                    if i + 1 < tokens.len() { "," } else { "" }
+                )?;
+            }
+            writeln!(out, "]")?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes a JSON array classifying the parts of a parsed program an editor
+/// would highlight, in source order: keywords, identifiers where they are
+/// defined (`identifier-def`) and used (`identifier-use`), parameter types,
+/// strings, numbers and comments. Operators and punctuation are left out.
+pub fn write_semantic_tokens(
+    out: &mut impl Write,
+    source: &str,
+    tokens: &[Token],
+    stmts: &[Stmt],
+) -> io::Result<()> {
+    let mut definitions = Definitions::default();
+    definitions.visit_all(stmts);
+
+    let mut spans = Vec::new();
+    let mut end = 0;
+    for token in tokens {
+        spans.extend(comments(source, end, token.start));
+        end = token.start + token.lexeme.len();
+        let kind = match &token.value {
+            TokenValue::Identifier(_) if definitions.starts.contains(&token.start) => {
+                "identifier-def"
+            }
+            TokenValue::Identifier(_) if definitions.types.contains(&token.start) => "type",
+            TokenValue::Identifier(_) if definitions.operators.contains(&token.start) => "keyword",
+            TokenValue::Identifier(_) => "identifier-use",
+            TokenValue::String(_) => "string",
+            TokenValue::Number(_) => "number",
+            value if KEYWORDS.iter().any(|(_, keyword)| keyword == value) => "keyword",
+            _ => continue,
+        };
+        spans.push((token.start, end, kind));
+    }
+
+    let positions = Positions::new(source);
+    writeln!(out, "[")?;
+    for (i, (start, end, kind)) in spans.iter().enumerate() {
+        let (line, column) = positions.of(*start);
+        writeln!(
+            out,
+            "  {{\"type\": {}, \"line\": {}, \"column\": {}, \"length\": {}, \"start\": {}, \"end\": {}}}{}",
+            json_string(kind),
+            line,
+            column,
+            source[*start..*end].chars().count(),
+            start,
+            end,
+            if i + 1 < spans.len() { "," } else { "" }
+        )?;
+    }
+    writeln!(out, "]")
+}
+
+/// The `//` comments in `source[start..end]`, the text between two tokens.
+fn comments(source: &str, start: usize, end: usize) -> Vec<(usize, usize, &'static str)> {
+    let mut comments = Vec::new();
+    let mut offset = start;
+    for line in source[start..end].split_inclusive('\n') {
+        if let Some(i) = line.find("//") {
+            let text = line[i..].trim_end_matches(['\n', '\r']);
+            comments.push((offset + i, offset + i + text.len(), "comment"));
+        }
+        offset += line.len();
+    }
+    comments
+}
+
+/// Byte offsets of the names declared by `var`, `fun` (with its parameters)
+/// and `import ... as`, of the types parameters are annotated with, and of
+/// the `in` operators and `import` keywords, which scan as identifiers.
+#[derive(Default)]
+struct Definitions {
+    starts: HashSet<usize>,
+    types: HashSet<usize>,
+    operators: HashSet<usize>,
+}
+
+impl Visitor for Definitions {
+    fn visit(&mut self, node: Node) {
+        match node {
+            Node::Stmt(Stmt::Var(var)) => {
+                self.starts.insert(var.name.start);
+            }
+            Node::Stmt(Stmt::Func(func)) => {
+                self.starts.insert(func.name.start);
+                self.starts
+                    .extend(func.params.iter().map(|param| param.start));
+                self.types
+                    .extend(func.types.iter().flatten().map(|ty| ty.start));
+            }
+            Node::Expr(Expr::Binary(binary)) if binary.operator.value == TokenValue::In => {
+                self.operators.insert(binary.operator.start);
+            }
+            Node::Stmt(Stmt::Import(import)) => {
+                self.operators.insert(import.keyword.start);
+                self.starts
+                    .extend(import.alias.iter().map(|alias| alias.start));
+            }
+            _ => {}
+        }
+        walk_children(self, node);
+    }
+}
+
+/// The literal as `tokenize` prints it: numbers always with a fraction.
+fn literal(token: &Token) -> String {
+    match &token.value {
+        TokenValue::Number(n) => n.literal(),
+        TokenValue::String(s) => s.clone(),
+        _ => "null".to_string(),
+    }
+}
+
+/// Maps byte offsets to 1-based lines and character columns. Tokens record
+/// the line they end on, which for a multi-line string is not where it starts.
+struct Positions<'a> {
+    source: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> Positions<'a> {
+    fn new(source: &'a str) -> Positions<'a> {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Positions {
+            source,
+            line_starts,
+        }
+    }
+
+    fn of(&self, offset: usize) -> (usize, usize) {
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let column = self.source[self.line_starts[line]..offset].chars().count() + 1;
+        (line + 1, column)
+    }
+}
+
+fn tsv_escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -1,4 +1,11 @@
-use crate::{token::Token, Walkable};
+use std::{cell::Cell, rc::Rc};
+
+use crate::{
+    number::Number,
+    resolver::Local,
+    token::{Span, Token},
+    Walkable,
+};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Expr {
@@ -9,12 +16,22 @@ pub enum Expr {
     Assign(Assign),
     Variable(Variable),
     Call(Call),
+    Get(Get),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Literal {
+    pub value: LiteralValue,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Clone)]
-pub enum Literal {
-    String(String),
-    Number(f64),
+pub enum LiteralValue {
+    String(Rc<String>),
+    Number(Number),
+    /// A number literal written without a fraction that fits in an `i64`.
+    /// It evaluates to a plain number unless integers are enabled.
+    Integer(i64),
     True,
     False,
     Nil,
@@ -23,12 +40,14 @@ pub enum Literal {
 #[derive(Debug, PartialEq, Clone)]
 pub struct Grouping {
     pub expr: Box<Expr>,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Unary {
     pub operator: Token,
     pub right: Box<Expr>,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -36,24 +55,60 @@ pub struct Binary {
     pub left: Box<Expr>,
     pub operator: Token,
     pub right: Box<Expr>,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Assign {
     pub name: Token,
     pub value: Box<Expr>,
+    /// Filled in by the resolver; `None` for globals.
+    pub local: Cell<Option<Local>>,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Variable {
     pub name: Token,
+    /// Filled in by the resolver; `None` for globals.
+    pub local: Cell<Option<Local>>,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Call {
     pub callee: Box<Expr>,
     pub args: Vec<Expr>,
+    /// The parameter each argument is passed to by name, as in `f(x: 1)`;
+    /// named arguments come after positional ones.
+    pub names: Vec<Option<Token>>,
     pub paren: Token,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Get {
+    pub object: Box<Expr>,
+    pub name: Token,
+    /// Written `?.`: nil rather than an error when the object is nil.
+    pub optional: bool,
+    pub span: Span,
+}
+
+impl Expr {
+    /// The source region the expression was parsed from.
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Literal(literal) => literal.span,
+            Expr::Grouping(grouping) => grouping.span,
+            Expr::Unary(unary) => unary.span,
+            Expr::Binary(binary) => binary.span,
+            Expr::Assign(assign) => assign.span,
+            Expr::Variable(variable) => variable.span,
+            Expr::Call(call) => call.span,
+            Expr::Get(get) => get.span,
+        }
+    }
 }
 
 impl<V: ExprVisitor<T>, T> Walkable<V, T> for Expr {
@@ -66,6 +121,7 @@ impl<V: ExprVisitor<T>, T> Walkable<V, T> for Expr {
             Expr::Assign(assign) => visitor.visit_assign(assign),
             Expr::Variable(variable) => visitor.visit_variable(variable),
             Expr::Call(function) => visitor.visit_call(function),
+            Expr::Get(get) => visitor.visit_get(get),
         }
     }
 }
@@ -84,45 +140,69 @@ pub trait ExprVisitor<T> {
     fn visit_variable(&mut self, expr: &Variable) -> T;
 
     fn visit_call(&mut self, expr: &Call) -> T;
+
+    fn visit_get(&mut self, expr: &Get) -> T;
+}
+
+impl std::fmt::Display for LiteralValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LiteralValue::Number(n) => f.write_str(&n.literal()),
+            LiteralValue::Integer(n) => f.write_str(&Number(*n as f64).literal()),
+            LiteralValue::String(s) => s.fmt(f),
+            LiteralValue::False => false.fmt(f),
+            LiteralValue::True => true.fmt(f),
+            LiteralValue::Nil => f.write_str("nil"),
+        }
+    }
 }
 
 impl std::fmt::Display for Expr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Expr::Literal(Literal::Number(n)) => {
-                if n % 1.0 == 0.0 {
-                    write!(f, "{:.1}", n)
-                } else {
-                    write!(f, "{}", n)
-                }
-            }
-            Expr::Literal(Literal::String(s)) => s.fmt(f),
-            Expr::Literal(Literal::False) => false.fmt(f),
-            Expr::Literal(Literal::True) => true.fmt(f),
-            Expr::Literal(Literal::Nil) => f.write_str("nil"),
-            Expr::Unary(Unary { operator, right }) => {
-                f.write_fmt(format_args!("({} {})", operator.lexeme, right))
-            }
+            Expr::Literal(literal) => literal.value.fmt(f),
+            Expr::Unary(Unary {
+                operator, right, ..
+            }) => f.write_fmt(format_args!("({} {})", operator.lexeme, right)),
             Expr::Binary(Binary {
                 left,
                 operator,
                 right,
+                ..
             }) => f.write_fmt(format_args!("({} {} {})", operator.lexeme, left, right)),
 
-            Expr::Grouping(Grouping { expr }) => f.write_fmt(format_args!("(group {})", expr)),
-            Expr::Assign(Assign { name, value }) => write!(f, "(= {} {})", name.lexeme, value),
-            Expr::Variable(Variable { name }) => name.lexeme.fmt(f),
-            Expr::Call(Call { callee, args, .. }) => {
+            Expr::Grouping(Grouping { expr, .. }) => f.write_fmt(format_args!("(group {})", expr)),
+            Expr::Assign(Assign { name, value, .. }) => write!(f, "(= {} {})", name.lexeme, value),
+            Expr::Variable(Variable { name, .. }) => name.lexeme.fmt(f),
+            Expr::Call(Call {
+                callee,
+                args,
+                names,
+                ..
+            }) => {
                 write!(
                     f,
                     "(fn {} {})",
-                    callee.to_string(),
+                    callee,
                     args.iter()
-                        .map(|a| a.to_string())
+                        .zip(names)
+                        .map(|(a, name)| match name {
+                            Some(name) => format!("{}:{}", name.lexeme, a),
+                            None => a.to_string(),
+                        })
                         .collect::<Vec<_>>()
                         .join(" ")
                 )
             }
+            Expr::Get(Get {
+                object,
+                name,
+                optional,
+                ..
+            }) => {
+                let dot = if *optional { "?." } else { "." };
+                write!(f, "({} {} {})", dot, object, name.lexeme)
+            }
         }
     }
 }
@@ -0,0 +1,200 @@
+use crate::{
+    expr::{Expr, LiteralValue},
+    stmt::Stmt,
+    token::TokenValue,
+};
+
+/// Prints `stmts` back as Lox source in a canonical layout: four-space
+/// indents, one statement per line, and operators spaced. Parsing the output
+/// gives back the same statements. Comments are not in the AST, so they are
+/// lost, and `for` loops come out as the `while` loops they desugar to.
+pub fn format(stmts: &[Stmt]) -> String {
+    let mut formatter = Formatter::default();
+    for stmt in stmts {
+        formatter.stmt(stmt);
+    }
+    formatter.out
+}
+
+#[derive(Default)]
+struct Formatter {
+    out: String,
+    indent: usize,
+}
+
+impl Formatter {
+    fn line(&mut self, text: &str) {
+        self.start_line();
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn start_line(&mut self) {
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Print(print) => self.line(&format!("print {};", expr(&print.expr))),
+            Stmt::Expression(expression) => self.line(&format!("{};", expr(&expression.expr))),
+            Stmt::Var(var) => match &var.initializer {
+                Some(initializer) => {
+                    self.line(&format!("var {} = {};", var.name.lexeme, expr(initializer)))
+                }
+                None => self.line(&format!("var {};", var.name.lexeme)),
+            },
+            Stmt::Block(block) => {
+                self.start_line();
+                self.block(&block.statements);
+                self.out.push('\n');
+            }
+            Stmt::If(_) => {
+                self.start_line();
+                self.if_stmt(stmt);
+            }
+            Stmt::While(while_stmt) => {
+                self.start_line();
+                self.out
+                    .push_str(&format!("while ({})", expr(&while_stmt.condition)));
+                self.body(&while_stmt.body);
+                self.out.push('\n');
+            }
+            Stmt::Func(func) => {
+                self.start_line();
+                let params = func
+                    .params
+                    .iter()
+                    .zip(&func.types)
+                    .map(|(param, ty)| match ty {
+                        Some(ty) => format!("{}: {}", param.lexeme, ty.lexeme),
+                        None => param.lexeme.clone(),
+                    })
+                    .collect::<Vec<_>>();
+                self.out
+                    .push_str(&format!("fun {}({}) ", func.name.lexeme, params.join(", ")));
+                self.block(&func.body);
+                self.out.push('\n');
+            }
+            Stmt::Return(ret) => match &ret.value {
+                Some(value) => self.line(&format!("return {};", expr(value))),
+                None => self.line("return;"),
+            },
+            Stmt::Import(import) => match &import.alias {
+                Some(alias) => self.line(&format!(
+                    "import {} as {};",
+                    import.path.lexeme, alias.lexeme
+                )),
+                None => self.line(&format!("import {};", import.path.lexeme)),
+            },
+        }
+    }
+
+    /// An `if` from where its line is started, without the final newline, so
+    /// `else if` chains stay on one line.
+    fn if_stmt(&mut self, stmt: &Stmt) {
+        let Stmt::If(if_stmt) = stmt else {
+            unreachable!()
+        };
+        self.out
+            .push_str(&format!("if ({})", expr(&if_stmt.condition)));
+        self.body(&if_stmt.then_branch);
+        let Some(else_branch) = &if_stmt.else_branch else {
+            self.out.push('\n');
+            return;
+        };
+        if matches!(*if_stmt.then_branch, Stmt::Block(_)) {
+            self.out.push_str(" else");
+        } else {
+            self.out.push('\n');
+            self.start_line();
+            self.out.push_str("else");
+        }
+        if let Stmt::If(_) = **else_branch {
+            self.out.push(' ');
+            self.if_stmt(else_branch);
+        } else {
+            self.body(else_branch);
+            self.out.push('\n');
+        }
+    }
+
+    /// The body of an `if`, `else` or `while`: a block on the same line, any
+    /// other statement indented on the next, without its final newline.
+    fn body(&mut self, body: &Stmt) {
+        if let Stmt::Block(block) = body {
+            self.out.push(' ');
+            self.block(&block.statements);
+            return;
+        }
+        self.out.push('\n');
+        self.indent += 1;
+        self.stmt(body);
+        self.indent -= 1;
+        self.out.pop();
+    }
+
+    /// Braces around `stmts`, from where the line is started, without the
+    /// final newline.
+    fn block(&mut self, stmts: &[Stmt]) {
+        if stmts.is_empty() {
+            self.out.push_str("{}");
+            return;
+        }
+        self.out.push_str("{\n");
+        self.indent += 1;
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+        self.indent -= 1;
+        self.start_line();
+        self.out.push('}');
+    }
+}
+
+/// An expression as source. Groupings are in the AST, so the parentheses
+/// that precedence needs are already there.
+fn expr(expr: &Expr) -> String {
+    match expr {
+        Expr::Literal(literal) => match &literal.value {
+            LiteralValue::String(s) => format!("\"{}\"", s),
+            LiteralValue::Number(n) => n.literal(),
+            LiteralValue::Integer(n) => n.to_string(),
+            LiteralValue::True => "true".to_string(),
+            LiteralValue::False => "false".to_string(),
+            LiteralValue::Nil => "nil".to_string(),
+        },
+        Expr::Grouping(grouping) => format!("({})", self::expr(&grouping.expr)),
+        Expr::Unary(unary) => format!("{}{}", unary.operator.lexeme, self::expr(&unary.right)),
+        Expr::Binary(binary) => format!(
+            "{} {} {}",
+            self::expr(&binary.left),
+            binary.operator.lexeme,
+            self::expr(&binary.right)
+        ),
+        Expr::Assign(assign) => format!("{} = {}", assign.name.lexeme, self::expr(&assign.value)),
+        Expr::Variable(variable) => variable.name.lexeme.clone(),
+        Expr::Call(call) if call.paren.value == TokenValue::PipeGreater => format!(
+            "{} |> {}",
+            self::expr(&call.args[0]),
+            self::expr(&call.callee)
+        ),
+        Expr::Call(call) => {
+            let args = call
+                .args
+                .iter()
+                .zip(&call.names)
+                .map(|(arg, name)| match name {
+                    Some(name) => format!("{}: {}", name.lexeme, self::expr(arg)),
+                    None => self::expr(arg),
+                })
+                .collect::<Vec<_>>();
+            format!("{}({})", self::expr(&call.callee), args.join(", "))
+        }
+        Expr::Get(get) => {
+            let dot = if get.optional { "?." } else { "." };
+            format!("{}{}{}", self::expr(&get.object), dot, get.name.lexeme)
+        }
+    }
+}
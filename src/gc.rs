@@ -0,0 +1,186 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::{Rc, Weak},
+};
+
+use crate::{
+    environment::Environment,
+    eval::{Scope, Value},
+};
+
+/// Collections are not attempted until this many scopes are tracked.
+const MIN_COLLECTION: usize = 4096;
+
+/// Tracks the scopes created for blocks and calls, and frees the ones kept
+/// alive only by reference cycles, such as a closure stored in the very
+/// scope it captured. Plain `Rc` counting never reclaims those.
+///
+/// Collection is by trial deletion, so it needs no knowledge of the roots:
+/// a scope with more strong references than the tracked scopes account for
+/// is referenced from elsewhere (the interpreter, the Rust stack, a module)
+/// and is live, along with everything it reaches. The rest are garbage.
+pub struct Heap {
+    scopes: Vec<Weak<RefCell<Environment>>>,
+    next_collection: usize,
+    /// Collect before every allocation, to shake out collector bugs.
+    pub stress: bool,
+    /// Report every collection on stderr.
+    pub log: bool,
+}
+
+/// What a collection found.
+#[derive(Debug, Default)]
+pub struct Collection {
+    pub live: usize,
+    pub freed: usize,
+}
+
+/// A function or module value found inside a tracked scope. Those sit
+/// between scopes as `Rc`s of their own, so they need counting too.
+struct Holder {
+    strong: usize,
+    seen: usize,
+    target: *const RefCell<Environment>,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap {
+            scopes: Vec::new(),
+            next_collection: MIN_COLLECTION,
+            stress: false,
+            log: false,
+        }
+    }
+}
+
+impl Heap {
+    pub fn alloc(&mut self, env: Environment) -> Scope {
+        let scope = Rc::new(RefCell::new(env));
+        self.scopes.push(Rc::downgrade(&scope));
+        scope
+    }
+
+    /// Whether enough scopes have been created since the last collection to
+    /// make another worthwhile. Only call `collect` while no scope is
+    /// mutably borrowed.
+    pub fn should_collect(&self) -> bool {
+        self.stress || self.scopes.len() >= self.next_collection
+    }
+
+    pub fn collect(&mut self) -> Collection {
+        self.scopes.retain(|scope| scope.strong_count() > 0);
+        let scopes = self
+            .scopes
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect::<Vec<_>>();
+        let index = scopes
+            .iter()
+            .enumerate()
+            .map(|(i, scope)| (Rc::as_ptr(scope), i))
+            .collect::<HashMap<_, _>>();
+
+        // References to each scope from other tracked scopes, and the
+        // function and module values they hold.
+        let mut internal = vec![0; scopes.len()];
+        let mut holders = HashMap::<*const (), Holder>::new();
+        for scope in &scopes {
+            let env = scope.borrow();
+            if let Some(i) = env
+                .enclosing()
+                .and_then(|e| index.get(&Rc::as_ptr(&e)).copied())
+            {
+                internal[i] += 1;
+            }
+            for value in env.contents() {
+                let Some((ptr, strong, target)) = holder(value) else {
+                    continue;
+                };
+                holders
+                    .entry(ptr)
+                    .or_insert(Holder {
+                        strong,
+                        seen: 0,
+                        target,
+                    })
+                    .seen += 1;
+            }
+        }
+        for holder in holders.values() {
+            if let Some(&i) = index.get(&holder.target) {
+                internal[i] += 1;
+            }
+        }
+
+        // Roots are scopes referenced from outside, directly or through a
+        // function or module value held outside. `scopes` itself holds one
+        // reference to each.
+        let mut worklist = (0..scopes.len())
+            .filter(|&i| Rc::strong_count(&scopes[i]) - 1 > internal[i])
+            .collect::<Vec<_>>();
+        worklist.extend(
+            holders
+                .values()
+                .filter(|holder| holder.strong > holder.seen)
+                .filter_map(|holder| index.get(&holder.target).copied()),
+        );
+
+        let mut live = vec![false; scopes.len()];
+        while let Some(i) = worklist.pop() {
+            if live[i] {
+                continue;
+            }
+            live[i] = true;
+            let env = scopes[i].borrow();
+            if let Some(enclosing) = env.enclosing() {
+                worklist.extend(index.get(&Rc::as_ptr(&enclosing)).copied());
+            }
+            worklist.extend(
+                env.contents()
+                    .filter_map(holder)
+                    .filter_map(|(_, _, target)| index.get(&target).copied()),
+            );
+        }
+
+        // Emptying the dead scopes breaks their cycles. The contents are
+        // dropped only once nothing here is borrowed any more.
+        let mut garbage = Vec::new();
+        for (scope, _) in scopes.iter().zip(&live).filter(|(_, live)| !**live) {
+            garbage.push(std::mem::take(&mut *scope.borrow_mut()));
+        }
+        let collection = Collection {
+            live: scopes.len() - garbage.len(),
+            freed: garbage.len(),
+        };
+        drop(scopes);
+        drop(garbage);
+
+        self.scopes.retain(|scope| scope.strong_count() > 0);
+        self.next_collection = MIN_COLLECTION.max(self.scopes.len() * 2);
+        if self.log {
+            eprintln!(
+                "[gc] {} live, {} freed, next at {}",
+                collection.live, collection.freed, self.next_collection
+            );
+        }
+        collection
+    }
+}
+
+fn holder(value: &Value) -> Option<(*const (), usize, *const RefCell<Environment>)> {
+    match value {
+        Value::Function(func) => Some((
+            Rc::as_ptr(func).cast(),
+            Rc::strong_count(func),
+            Rc::as_ptr(&func.closure),
+        )),
+        Value::Module(module) => Some((
+            Rc::as_ptr(module).cast(),
+            Rc::strong_count(module),
+            Rc::as_ptr(&module.globals),
+        )),
+        _ => None,
+    }
+}
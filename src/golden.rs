@@ -0,0 +1,260 @@
+use std::{
+    env, fs,
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Error, Result};
+
+/// What a test script expects from `run`, read from comments embedded in the
+/// script itself (Crafting Interpreters style):
+///
+/// - `// expect: <line>` — a line of stdout
+/// - `// expect runtime error: <message>` — a runtime error (exit code 70)
+/// - `// expect warning: <message>` — a resolver warning, on stderr before
+///   any output
+/// - `// expect code: <code>` — the pointer to `explain <code>` that
+///   `--error-codes` prints after the diagnostic expected before it
+/// - `// flags: <flags>` — extra flags for `run`, separated by spaces
+/// - `// command: <command>` — a command to run instead of `run`, such as
+///   `parse-stmt`, with any arguments it takes ahead of the script's path
+/// - `// Error at 'x': <message>` or `// [line N] Error...` — a compile error
+///   (exit code 65)
+#[derive(Debug, Default, PartialEq)]
+pub struct Expectations {
+    pub stdout: Vec<String>,
+    pub stderr: Vec<String>,
+    pub code: i32,
+    pub flags: Vec<String>,
+    pub command: Option<String>,
+}
+
+impl Expectations {
+    pub fn parse(source: &str) -> Expectations {
+        let mut expected = Expectations::default();
+        for (i, line) in source.lines().enumerate() {
+            let line_number = i + 1;
+            let Some((_, comment)) = line.split_once("// ") else {
+                continue;
+            };
+            if let Some(command) = comment.strip_prefix("command: ") {
+                expected.command = Some(command.trim().to_string());
+            } else if let Some(flags) = comment.strip_prefix("flags: ") {
+                expected
+                    .flags
+                    .extend(flags.split_whitespace().map(str::to_string));
+            } else if let Some(output) = comment.strip_prefix("expect: ") {
+                expected.stdout.push(output.to_string());
+            } else if let Some(message) = comment.strip_prefix("expect warning: ") {
+                expected
+                    .stderr
+                    .push(format!("[line {}] Warning: {}", line_number, message));
+            } else if let Some(code) = comment.strip_prefix("expect code: ") {
+                expected
+                    .stderr
+                    .push(format!("[{}] Run 'explain {}' for details.", code, code));
+            } else if let Some(message) = comment.strip_prefix("expect runtime error: ") {
+                expected.stderr.push(message.to_string());
+                expected.stderr.push(format!("[line {}]", line_number));
+                expected.code = 70;
+            } else if comment.starts_with("[line ") {
+                expected.stderr.push(comment.to_string());
+                expected.code = 65;
+            } else if comment.starts_with("Error") {
+                expected
+                    .stderr
+                    .push(format!("[line {}] {}", line_number, comment));
+                expected.code = 65;
+            }
+        }
+        expected
+    }
+}
+
+/// Runs a single test script in a child process, with `flags` ahead of its
+/// own, and returns a description of every mismatch (empty when the test
+/// passes). The child is killed if it runs longer than `timeout`.
+pub fn run_test(path: &Path, flags: &[&str], timeout: Duration) -> Result<Vec<String>, Error> {
+    let source = fs::read_to_string(path)?;
+    let expected = Expectations::parse(&source);
+
+    let mut child = Command::new(env::current_exe()?)
+        .args(
+            expected
+                .command
+                .as_deref()
+                .unwrap_or("run")
+                .split_whitespace(),
+        )
+        .args(flags)
+        .args(&expected.flags)
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Drained while the child runs, so it never blocks on a full pipe.
+    let stdout = read_to_end(child.stdout.take());
+    let stderr = read_to_end(child.stderr.take());
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Ok(vec![format!("timed out after {}s", timeout.as_secs_f64())]);
+        }
+        thread::sleep(Duration::from_millis(5));
+    };
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    let stdout = String::from_utf8_lossy(&stdout);
+    let stderr = String::from_utf8_lossy(&stderr);
+    let code = status.code().unwrap_or(-1);
+
+    let mut failures = Vec::new();
+    let stdout = stdout.lines().collect::<Vec<_>>();
+    if stdout != expected.stdout {
+        failures.push(format!(
+            "expected stdout:\n{}\ngot:\n{}",
+            expected.stdout.join("\n"),
+            stdout.join("\n")
+        ));
+    }
+    let stderr = stderr.lines().collect::<Vec<_>>();
+    if stderr != expected.stderr {
+        failures.push(format!(
+            "expected stderr:\n{}\ngot:\n{}",
+            expected.stderr.join("\n"),
+            stderr.join("\n")
+        ));
+    }
+    if code != expected.code {
+        failures.push(format!(
+            "expected exit code {} but got {}",
+            expected.code, code
+        ));
+    }
+    Ok(failures)
+}
+
+fn read_to_end(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        bytes
+    })
+}
+
+/// Every `.lox` file under `dir` (or just `dir` if it is a file), sorted.
+pub fn collect_tests(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    if dir.is_file() {
+        files.push(dir.to_path_buf());
+    } else {
+        collect(dir, &mut files);
+    }
+    files.sort();
+    files
+}
+
+/// Runs `files` as `run_test` does, up to `jobs` at a time, and returns
+/// their results in the same order.
+pub fn run_tests(
+    files: &[PathBuf],
+    flags: &[&str],
+    jobs: usize,
+    timeout: Duration,
+) -> Vec<Result<Vec<String>, String>> {
+    // Each worker takes the next file not yet claimed, so slow tests don't
+    // hold up a whole share of the files.
+    let next = AtomicUsize::new(0);
+    let results = files.iter().map(|_| Mutex::new(None)).collect::<Vec<_>>();
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, files.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(file) = files.get(i) else {
+                    break;
+                };
+                let result = run_test(file, flags, timeout).map_err(|e| e.to_string());
+                *results[i].lock().unwrap() = Some(result);
+            });
+        }
+    });
+    results
+        .into_iter()
+        .map(|result| result.into_inner().unwrap().expect("every test ran"))
+        .collect()
+}
+
+/// Prints why `file` failed, indented under a `FAIL` line.
+pub fn print_failure(file: &Path, result: &Result<Vec<String>, String>) {
+    println!("FAIL {}", file.display());
+    let failures = match result {
+        Ok(failures) => failures.clone(),
+        Err(e) => vec![e.clone()],
+    };
+    for failure in failures {
+        for line in failure.lines() {
+            println!("    {}", line);
+        }
+    }
+}
+
+/// Runs every `.lox` file under `dir` (or just `dir` if it is a file), up to
+/// `jobs` at a time, and prints a pass/fail summary in file order. Returns
+/// the process exit code: 0 when everything passed, 1 otherwise.
+pub fn run(dir: &Path, jobs: usize, timeout: Duration) -> i32 {
+    let started = Instant::now();
+    let files = collect_tests(dir);
+    let results = run_tests(&files, &[], jobs, timeout);
+
+    let mut failed = 0;
+    for (file, result) in files.iter().zip(&results) {
+        if matches!(result, Ok(failures) if failures.is_empty()) {
+            println!("PASS {}", file.display());
+        } else {
+            failed += 1;
+            print_failure(file, result);
+        }
+    }
+
+    println!(
+        "\n{} passed, {} failed, {} total in {:.2}s",
+        files.len() - failed,
+        failed,
+        files.len(),
+        started.elapsed().as_secs_f64()
+    );
+    if failed == 0 {
+        0
+    } else {
+        1
+    }
+}
+
+fn collect(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect(&path, files);
+        } else if path.extension().is_some_and(|ext| ext == "lox") {
+            files.push(path);
+        }
+    }
+}
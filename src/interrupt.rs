@@ -0,0 +1,43 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether Ctrl-C was pressed since the handler was installed or reset. The
+/// interpreter polls this between steps and unwinds with an "Interrupted."
+/// runtime error.
+pub fn requested() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Installs a SIGINT handler that requests an interrupt instead of killing
+/// the process. The default handler is restored once it fires, so a second
+/// Ctrl-C still terminates a script stuck outside the evaluation loop.
+#[cfg(unix)]
+pub fn install() {
+    extern "C" {
+        fn signal(signum: i32, handler: usize) -> usize;
+    }
+    const SIGINT: i32 = 2;
+    const SIG_DFL: usize = 0;
+
+    extern "C" fn on_sigint(_: i32) {
+        INTERRUPTED.store(true, Ordering::Relaxed);
+        unsafe {
+            signal(SIGINT, SIG_DFL);
+        }
+    }
+
+    unsafe {
+        signal(SIGINT, on_sigint as extern "C" fn(i32) as usize);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install() {}
+
+/// Forgets an interrupt that has been handled and installs the handler again,
+/// for the REPL and `run --watch`, which carry on after one.
+pub fn reset() {
+    INTERRUPTED.store(false, Ordering::Relaxed);
+    install();
+}
@@ -1,171 +1,354 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt, io::BufRead};
 
-use crate::token::{Token, TokenValue, KEYWORDS};
+use crate::{
+    explain::Coded,
+    symbol::Symbol,
+    token::{Token, TokenValue, KEYWORDS},
+};
 
-pub fn scan(source: String) -> (Vec<Token>, i32) {
+/// A character or string the scanner could not turn into a token. Scanning
+/// carries on past it, so one pass finds every such error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    /// See `explain::DIAGNOSTICS`; `None` when the input couldn't be read.
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub line: usize,
+    /// Byte offset of the offending text.
+    pub start: usize,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] Error: {}", self.line, self.message)
+    }
+}
+
+impl Coded for LexError {
+    fn code(&self) -> Option<&'static str> {
+        self.code
+    }
+}
+
+/// Scans `source` by byte offset. Every token of interest is ASCII, so the
+/// only place UTF-8 matters is stepping over an unexpected character; lexemes
+/// are sliced straight out of `source`.
+///
+/// Returns every token that could be scanned along with the errors, both in
+/// source order.
+///
+/// The tokens only extensions use (`:`, `??`, `?.` and `|>`) are scanned only
+/// with `extensions`; otherwise their characters are unexpected, as in the
+/// reference implementations.
+pub fn scan(source: &str, extensions: bool) -> (Vec<Token>, Vec<LexError>) {
     let keywords = HashMap::from(KEYWORDS);
     let mut tokens = Vec::new();
+    let mut errors = Vec::new();
     let mut line = 1;
-    let mut code = 0;
-    let chars = source.chars().collect::<Vec<_>>();
     let mut i = 0;
-    while let Some(char) = chars.get(i) {
-        match char {
-            '(' => tokens.push(Token::new(TokenValue::LeftParen, char.to_string(), line)),
-            ')' => tokens.push(Token::new(TokenValue::RightParen, char.to_string(), line)),
-            '{' => tokens.push(Token::new(TokenValue::LeftBrace, char.to_string(), line)),
-            '}' => tokens.push(Token::new(TokenValue::RightBrace, char.to_string(), line)),
-            ',' => tokens.push(Token::new(TokenValue::Comma, char.to_string(), line)),
-            '.' => tokens.push(Token::new(TokenValue::Dot, char.to_string(), line)),
-            '-' => tokens.push(Token::new(TokenValue::Minus, char.to_string(), line)),
-            '+' => tokens.push(Token::new(TokenValue::Plus, char.to_string(), line)),
-            ';' => tokens.push(Token::new(TokenValue::Semicolon, char.to_string(), line)),
-            '*' => tokens.push(Token::new(TokenValue::Star, char.to_string(), line)),
-            '/' => {
-                if let Some('/') = chars.get(i + 1) {
-                    while chars.get(i + 1).is_some_and(|c| *c != '\n') {
-                        i += 1;
-                    }
-                } else {
-                    tokens.push(Token::new(TokenValue::Slash, char.to_string(), line))
+    while let Some(result) = scan_token(source, &mut i, &mut line, &keywords, extensions) {
+        match result {
+            Ok(token) => tokens.push(token),
+            Err(error) => errors.push(error),
+        }
+    }
+    tokens.push(Token::new(
+        TokenValue::Eof,
+        "".to_string(),
+        line,
+        source.len(),
+    ));
+    (tokens, errors)
+}
+
+/// Scans the next token of `source` from byte `i` on, skipping whitespace and
+/// comments, and advances `i` and `line` past it. `None` at the end of
+/// `source`.
+fn scan_token(
+    source: &str,
+    i: &mut usize,
+    line: &mut usize,
+    keywords: &HashMap<&str, TokenValue>,
+    extensions: bool,
+) -> Option<Result<Token, LexError>> {
+    let bytes = source.as_bytes();
+    loop {
+        let &byte = bytes.get(*i)?;
+        let start = *i;
+        *i += 1;
+        let value = match byte {
+            b'(' => TokenValue::LeftParen,
+            b')' => TokenValue::RightParen,
+            b'{' => TokenValue::LeftBrace,
+            b'}' => TokenValue::RightBrace,
+            b',' => TokenValue::Comma,
+            b':' if extensions => TokenValue::Colon,
+            b'.' => TokenValue::Dot,
+            b'-' => TokenValue::Minus,
+            b'+' => TokenValue::Plus,
+            b';' => TokenValue::Semicolon,
+            b'*' => TokenValue::Star,
+            b'/' if bytes.get(*i) == Some(&b'/') => {
+                while bytes.get(*i).is_some_and(|b| *b != b'\n') {
+                    *i += 1;
                 }
+                continue;
             }
-            '=' => {
-                if let Some('=') = chars.get(i + 1) {
-                    tokens.push(Token::new(TokenValue::EqualEqual, "==".to_string(), line));
-                    i += 1;
-                } else {
-                    tokens.push(Token::new(TokenValue::Equal, char.to_string(), line))
-                }
+            b'/' => TokenValue::Slash,
+            b'=' if bytes.get(*i) == Some(&b'=') => {
+                *i += 1;
+                TokenValue::EqualEqual
             }
-            '!' => {
-                if let Some('=') = chars.get(i + 1) {
-                    tokens.push(Token::new(TokenValue::BangEqual, "!=".to_string(), line));
-                    i += 1;
-                } else {
-                    tokens.push(Token::new(TokenValue::Bang, char.to_string(), line))
-                }
+            b'=' => TokenValue::Equal,
+            b'!' if bytes.get(*i) == Some(&b'=') => {
+                *i += 1;
+                TokenValue::BangEqual
             }
-            '<' => {
-                if let Some('=') = chars.get(i + 1) {
-                    tokens.push(Token::new(TokenValue::LessEqual, "<=".to_string(), line));
-                    i += 1;
-                } else {
-                    tokens.push(Token::new(TokenValue::Less, char.to_string(), line))
-                }
+            b'!' => TokenValue::Bang,
+            b'<' if bytes.get(*i) == Some(&b'=') => {
+                *i += 1;
+                TokenValue::LessEqual
             }
-            '>' => {
-                if let Some('=') = chars.get(i + 1) {
-                    tokens.push(Token::new(TokenValue::GreaterEqual, ">=".to_string(), line));
-                    i += 1;
-                } else {
-                    tokens.push(Token::new(TokenValue::Greater, char.to_string(), line))
-                }
+            b'<' => TokenValue::Less,
+            b'?' if extensions && bytes.get(*i) == Some(&b'?') => {
+                *i += 1;
+                TokenValue::QuestionQuestion
+            }
+            b'?' if extensions && bytes.get(*i) == Some(&b'.') => {
+                *i += 1;
+                TokenValue::QuestionDot
+            }
+            b'|' if extensions && bytes.get(*i) == Some(&b'>') => {
+                *i += 1;
+                TokenValue::PipeGreater
             }
+            b'>' if bytes.get(*i) == Some(&b'=') => {
+                *i += 1;
+                TokenValue::GreaterEqual
+            }
+            b'>' => TokenValue::Greater,
 
-            '"' => {
-                let mut lexeme = String::new();
-                lexeme.push(*char);
-                i += 1;
-                loop {
-                    let char = chars.get(i);
-                    match char {
-                        Some('"') => {
-                            lexeme.push('"');
-                            let literal: String =
-                                lexeme.clone().drain(1..lexeme.len() - 1).collect();
-                            tokens.push(Token::new(TokenValue::String(literal), lexeme, line));
-                            break;
-                        }
-                        Some('\n') => {
-                            line += 1;
-                            lexeme.push('\n');
-                            i += 1;
-                        }
-                        Some(char) => {
-                            lexeme.push(*char);
-                            i += 1;
-                        }
-                        None => {
-                            eprintln!("[line {line}] Error: Unterminated string.");
-                            code = 65;
-                            break;
-                        }
-                    }
-                }
+            b'"' => {
+                let rest = &source[*i..];
+                let Some(len) = rest.find('"') else {
+                    // Nothing after an unterminated string can be scanned.
+                    *line += rest.matches('\n').count();
+                    *i = source.len();
+                    return Some(Err(LexError {
+                        code: Some(UNTERMINATED_STRING),
+                        message: "Unterminated string.".to_string(),
+                        line: *line,
+                        start,
+                    }));
+                };
+                let literal = &rest[..len];
+                *line += literal.matches('\n').count();
+                *i += len + 1;
+                TokenValue::String(literal.to_string())
             }
 
-            '0'..='9' => {
-                let mut lexeme = String::new();
-                let mut has_digit = false;
-                while let Some(char) = chars.get(i) {
-                    match char {
-                        '0'..='9' => {
-                            lexeme.push(*char);
-                            i += 1;
-                        }
-                        '.' if !has_digit => {
-                            has_digit = true;
-                            lexeme.push(*char);
-                            i += 1;
-                        }
-                        _ => {
-                            i -= 1;
-                            break;
-                        }
+            b'0'..=b'9' => {
+                while bytes.get(*i).is_some_and(u8::is_ascii_digit) {
+                    *i += 1;
+                }
+                if bytes.get(*i) == Some(&b'.') && bytes.get(*i + 1).is_some_and(u8::is_ascii_digit)
+                {
+                    *i += 1;
+                    while bytes.get(*i).is_some_and(u8::is_ascii_digit) {
+                        *i += 1;
                     }
                 }
-                if lexeme.ends_with(".") {
-                    i -= 2;
-                    lexeme.pop();
+                TokenValue::Number(source[start..*i].parse().unwrap())
+            }
+
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
+                while bytes
+                    .get(*i)
+                    .is_some_and(|b| b.is_ascii_alphanumeric() || *b == b'_')
+                {
+                    *i += 1;
                 }
-                if lexeme.parse::<f64>().is_ok() {
-                    tokens.push(Token::new(
-                        TokenValue::Number(lexeme.parse().unwrap()),
-                        lexeme.to_string(),
-                        line,
-                    ));
-                } else {
-                    eprintln!("[line {line}] Error: Unexpected character: {lexeme}");
-                    code = 65;
+                let lexeme = &source[start..*i];
+                match keywords.get(lexeme) {
+                    Some(keyword) => keyword.clone(),
+                    None => TokenValue::Identifier(Symbol::intern(lexeme)),
                 }
             }
 
-            'a'..='z' | 'A'..='Z' | '_' => {
-                let mut lexeme = String::new();
-                while let Some(char) = chars.get(i) {
-                    match char {
-                        'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => {
-                            lexeme.push(*char);
-                            i += 1;
-                        }
-                        _ => {
-                            i -= 1;
-                            break;
-                        }
+            b' ' | b'\r' | b'\t' => continue,
+
+            b'\n' => {
+                *line += 1;
+                continue;
+            }
+            _ => {
+                let c = source[start..].chars().next().unwrap();
+                *i = start + c.len_utf8();
+                return Some(Err(LexError {
+                    code: Some("L0002"),
+                    message: format!("Unexpected character: {c}"),
+                    line: *line,
+                    start,
+                }));
+            }
+        };
+        let lexeme = source[start..*i].to_string();
+        return Some(Ok(Token::new(value, lexeme, *line, start)));
+    }
+}
+
+/// The code of the error for a string still open at the end of the input.
+pub const UNTERMINATED_STRING: &str = "L0001";
+
+/// Scans a source read a line at a time, for inputs too large to hold in
+/// memory at once. Yields the same tokens and errors as `scan`, interleaved
+/// in source order, and ends with the end-of-file token.
+///
+/// Only string literals can span lines, so a line is scanned as soon as it
+/// is read, unless it ends inside a string; then lines are added until the
+/// string is closed or the input runs out, each searched for the closing
+/// quote only once.
+pub struct Lexer<R> {
+    reader: R,
+    keywords: HashMap<&'static str, TokenValue>,
+    /// Whether the extensions' tokens are scanned, as in `scan`.
+    extensions: bool,
+    /// The lines read but not yet scanned past.
+    buffer: String,
+    /// Byte offset of `buffer` in the whole source.
+    offset: usize,
+    /// How far into `buffer` scanning has got.
+    i: usize,
+    line: usize,
+    /// The string literal being read, while its closing quote is on a line
+    /// not read yet.
+    open_string: Option<OpenString>,
+    /// Whether `reader` has run out.
+    exhausted: bool,
+    /// Whether the end-of-file token was yielded.
+    done: bool,
+}
+
+impl<R: BufRead> Lexer<R> {
+    pub fn new(reader: R) -> Lexer<R> {
+        Lexer {
+            reader,
+            keywords: HashMap::from(KEYWORDS),
+            extensions: false,
+            buffer: String::new(),
+            offset: 0,
+            i: 0,
+            line: 1,
+            open_string: None,
+            exhausted: false,
+            done: false,
+        }
+    }
+
+    pub fn with_extensions(mut self, extensions: bool) -> Lexer<R> {
+        self.extensions = extensions;
+        self
+    }
+
+    /// Appends the next line of input to `buffer`, returning whether there
+    /// was one.
+    fn read_line(&mut self) -> Result<bool, LexError> {
+        if self.exhausted {
+            return Ok(false);
+        }
+        match self.reader.read_line(&mut self.buffer) {
+            Ok(0) => {
+                self.exhausted = true;
+                Ok(false)
+            }
+            Ok(_) => Ok(true),
+            Err(e) => {
+                self.exhausted = true;
+                Err(LexError {
+                    code: None,
+                    message: format!("Could not read input: {e}"),
+                    line: self.line,
+                    start: self.offset + self.buffer.len(),
+                })
+            }
+        }
+    }
+}
+
+/// A string literal whose end hasn't been read yet.
+struct OpenString {
+    /// Offset of the opening quote in `buffer`.
+    start: usize,
+    /// The line the string starts on.
+    line: usize,
+    /// How much of `buffer` is known to hold no closing quote.
+    searched: usize,
+}
+
+impl<R: BufRead> Iterator for Lexer<R> {
+    type Item = Result<Token, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        loop {
+            if let Some(mut open) = self.open_string.take() {
+                let read = self.read_line();
+                let closed = self.buffer[open.searched..].contains('"');
+                open.searched = self.buffer.len();
+                if !closed && !self.exhausted {
+                    self.open_string = Some(open);
+                    continue;
+                }
+                // Scanned once more from the opening quote, now that the
+                // string is closed or can't be.
+                (self.i, self.line) = (open.start, open.line);
+                if let Err(error) = read {
+                    return Some(Err(error));
+                }
+            } else if self.i == self.buffer.len() {
+                self.offset += self.buffer.len();
+                self.buffer.clear();
+                self.i = 0;
+                match self.read_line() {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        self.done = true;
+                        let eof =
+                            Token::new(TokenValue::Eof, "".to_string(), self.line, self.offset);
+                        return Some(Ok(eof));
                     }
+                    Err(error) => return Some(Err(error)),
                 }
-                if keywords.contains_key(lexeme.as_str()) {
-                    tokens.push(Token::new(
-                        keywords.get(lexeme.as_str()).unwrap().clone(),
-                        lexeme,
+            }
+            let (i, line) = (self.i, self.line);
+            let result = scan_token(
+                &self.buffer,
+                &mut self.i,
+                &mut self.line,
+                &self.keywords,
+                self.extensions,
+            );
+            match result {
+                // The string may close on a line not read yet.
+                Some(Err(error)) if error.code == Some(UNTERMINATED_STRING) && !self.exhausted => {
+                    self.open_string = Some(OpenString {
+                        start: i,
                         line,
-                    ));
-                } else {
-                    tokens.push(Token::new(TokenValue::Identifier, lexeme, line));
+                        searched: self.buffer.len(),
+                    });
                 }
-            }
-
-            ' ' | '\r' | '\t' => {}
-
-            '\n' => line += 1,
-            c => {
-                eprintln!("[line {line}] Error: Unexpected character: {c}");
-                code = 65;
+                Some(Ok(mut token)) => {
+                    token.start += self.offset;
+                    return Some(Ok(token));
+                }
+                Some(Err(mut error)) => {
+                    error.start += self.offset;
+                    return Some(Err(error));
+                }
+                None => {}
             }
         }
-        i += 1;
     }
-    tokens.push(Token::new(TokenValue::Eof, "".to_string(), line));
-    (tokens, code)
 }
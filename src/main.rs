@@ -1,155 +1,857 @@
 use std::env;
 use std::fs;
-use std::io::{self, Write};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use eval::Interpreter;
+use eval::{Interpreter, Value};
+use export::Format;
 use parser::Parser;
+use stmt::Stmt;
+use token::{Token, TokenValue};
+pub mod bench;
+pub mod cache;
+pub mod compile;
+pub mod conformance;
+pub mod coverage;
+pub mod editor;
+pub mod environment;
 pub mod eval;
+pub mod explain;
+pub mod export;
 pub mod expr;
+pub mod format;
+pub mod gc;
+pub mod golden;
+pub mod interrupt;
 pub mod lexer;
+pub mod memo;
+pub mod memory;
+pub mod module;
+pub mod native;
+pub mod number;
 pub mod parser;
+pub mod random;
+pub mod repl;
+pub mod resolver;
+pub mod roundtrip;
+pub mod spawn;
+pub mod stats;
 pub mod stmt;
+pub mod symbol;
 pub mod token;
+pub mod typecheck;
+pub mod walk;
+
+/// Flags that consume the following argument as their value (`--flag value`).
+const VALUE_FLAGS: [&str; 10] = [
+    "backend",
+    "cases",
+    "format",
+    "iterations",
+    "jobs",
+    "max-depth",
+    "max-memory",
+    "max-steps",
+    "seed",
+    "timeout",
+];
+
+pub struct Args {
+    pub command: String,
+    pub filename: String,
+    pub flags: Vec<(String, Option<String>)>,
+}
+
+impl Args {
+    pub fn parse(args: &[String]) -> Option<Args> {
+        let command = args.get(1)?.clone();
+        let mut filename = None;
+        let mut flags = Vec::new();
+        let mut rest = args[2..].iter();
+        while let Some(arg) = rest.next() {
+            if let Some(flag) = arg.strip_prefix("--") {
+                match flag.split_once('=') {
+                    Some((name, value)) => flags.push((name.to_string(), Some(value.to_string()))),
+                    None if VALUE_FLAGS.contains(&flag) => {
+                        flags.push((flag.to_string(), rest.next().cloned()))
+                    }
+                    None => flags.push((flag.to_string(), None)),
+                }
+            } else if filename.is_none() {
+                filename = Some(arg.clone());
+            }
+        }
+        // Commands that need no file, or have a default for it.
+        if matches!(command.as_str(), "repl" | "roundtrip" | "conformance") {
+            filename.get_or_insert_with(String::new);
+        }
+        Some(Args {
+            command,
+            filename: filename?,
+            flags,
+        })
+    }
+
+    pub fn has_flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|(flag, _)| flag == name)
+    }
+
+    pub fn flag_value(&self, name: &str) -> Option<&str> {
+        self.flags
+            .iter()
+            .find(|(flag, _)| flag == name)
+            .and_then(|(_, value)| value.as_deref())
+    }
+
+    /// Parses the value of a flag, reporting malformed values as a usage
+    /// error (exit code 64).
+    pub fn parse_flag<T: FromStr>(&self, name: &str) -> Result<Option<T>, i32> {
+        match self.flag_value(name).map(str::parse) {
+            None => Ok(None),
+            Some(Ok(value)) => Ok(Some(value)),
+            Some(Err(_)) => {
+                eprintln!("Invalid value for --{}", name);
+                Err(64)
+            }
+        }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: memory::CountingAllocator = memory::CountingAllocator;
+
+/// Stack size of the thread running the interpreter. The tree-walker recurses
+/// on the native stack, so this bounds how deep Lox programs can nest.
+const STACK_SIZE: usize = 512 * 1024 * 1024;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() < 3 {
-        writeln!(io::stderr(), "Usage: {} tokenize <filename>", args[0]).unwrap();
+    let Some(args) = Args::parse(&args) else {
+        eprintln!("Usage: {} tokenize <filename>", args[0]);
         return;
+    };
+
+    let code = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || dispatch(&args))
+        .unwrap()
+        .join()
+        .unwrap_or(70);
+    if code != 0 {
+        exit(code);
     }
+}
 
-    let command = &args[1];
-    let filename = &args[2];
+fn dispatch(args: &Args) -> i32 {
+    explain::show_codes(args.has_flag("error-codes"));
+    match args.command.as_str() {
+        "tokenize" => tokenize(args),
+        "parse" => parse(args),
+        "parse-expr" => parse_expr(args),
+        "parse-stmt" => parse_stmt(args),
+        "semantic-tokens" => semantic_tokens(args),
+        "fmt" => fmt(args),
+        "evaluate" => evaluate(args),
+        "run" if args.has_flag("watch") => watch(args),
+        "run" => run(args),
+        "test" => test(args),
+        "conformance" => conformance(args),
+        "bench" => bench(args),
+        "explain" => explain::run(&args.filename),
+        "stats" => stats::run(&read_file(&args.filename), args.has_flag("extensions")),
+        "typecheck" => typecheck(args),
+        "roundtrip" => roundtrip(args),
+        "repl" => repl::run(args),
+        command => {
+            eprintln!("Unknown command: {}", command);
+            0
+        }
+    }
+}
 
-    match command.as_str() {
-        "tokenize" => {
-            let file_contents = fs::read_to_string(filename).unwrap_or_else(|_| {
-                writeln!(io::stderr(), "Failed to read file {}", filename).unwrap();
-                String::new()
-            });
+fn read_file(filename: &str) -> String {
+    fs::read_to_string(filename).unwrap_or_else(|_| {
+        eprintln!("Failed to read file {}", filename);
+        String::new()
+    })
+}
 
-            if !file_contents.is_empty() {
-                let (tokens, code) = lexer::scan(file_contents);
-                for token in tokens {
-                    println!("{}", token);
-                }
-                if code != 0 {
-                    exit(code);
-                }
-            } else {
-                println!("EOF  null");
-            }
-        }
-        "parse" => {
-            let file_contents = fs::read_to_string(filename).unwrap_or_else(|_| {
-                writeln!(io::stderr(), "Failed to read file {}", filename).unwrap();
-                String::new()
-            });
-            if !file_contents.is_empty() {
-                let (tokens, code) = lexer::scan(file_contents);
-                if code != 0 {
-                    exit(code);
-                }
-                let mut parser = Parser::new(tokens);
-                let exprs = parser.parse();
-
-                match exprs {
-                    Ok(exprs) => {
-                        for expr in exprs {
-                            println!("{}", expr);
-                        }
-                    }
-                    Err(errors) => {
-                        for error in errors {
-                            writeln!(io::stderr(), "{}", error).unwrap();
-                        }
-                        exit(65);
-                    }
-                }
+fn tokenize(args: &Args) -> i32 {
+    let format = match args.parse_flag("format") {
+        Ok(format) => format.unwrap_or(Format::Text),
+        Err(code) => return code,
+    };
+    let stream = args.has_flag("stream") || args.filename == "-" || is_large(&args.filename);
+    if format == Format::Text && stream {
+        return tokenize_stream(&args.filename, args.has_flag("extensions"));
+    }
+    let file_contents = read_file(&args.filename);
+    if file_contents.is_empty() && format == Format::Text {
+        println!("EOF  null");
+        return 0;
+    }
+
+    let (tokens, errors) = lexer::scan(&file_contents, args.has_flag("extensions"));
+    if format == Format::Text {
+        // Interleaved by position, so each error shows up where it occurred.
+        let mut errors = errors.iter().peekable();
+        for token in &tokens {
+            while let Some(error) = errors.next_if(|error| error.start < token.start) {
+                explain::report(error);
             }
+            println!("{}", token);
         }
-        "evaluate" => {
-            let file_contents = fs::read_to_string(filename).unwrap_or_else(|_| {
-                writeln!(io::stderr(), "Failed to read file {}", filename).unwrap();
-                String::new()
-            });
-            if !file_contents.is_empty() {
-                let (tokens, code) = lexer::scan(file_contents);
-                if code != 0 {
-                    exit(code);
-                }
-                let mut parser = Parser::new(tokens);
-                let exprs = parser.parse();
-
-                match exprs {
-                    Ok(exprs) => {
-                        let mut interpreter = Interpreter::new();
-                        let values = interpreter.interpret(exprs);
-                        match values {
-                            Ok(values) => {
-                                for value in values {
-                                    println!("{}", value);
-                                }
-                            }
-                            Err(errors) => {
-                                for error in errors {
-                                    writeln!(io::stderr(), "{}", error).unwrap();
-                                }
-                                exit(70);
-                            }
-                        }
-                    }
-                    Err(errors) => {
-                        for error in errors {
-                            writeln!(io::stderr(), "{}", error).unwrap();
-                        }
-                        exit(65);
-                    }
-                }
+    } else {
+        export::write_tokens(&mut io::stdout(), &file_contents, &tokens, format).unwrap();
+        for error in &errors {
+            explain::report(error);
+        }
+    }
+    if errors.is_empty() {
+        0
+    } else {
+        65
+    }
+}
+
+/// Files from this size on are tokenized as they are read rather than read
+/// into memory first.
+const LARGE_INPUT: u64 = 64 * 1024 * 1024;
+
+fn is_large(filename: &str) -> bool {
+    fs::metadata(filename).is_ok_and(|metadata| metadata.len() >= LARGE_INPUT)
+}
+
+/// `tokenize` for stdin (`-`), large files and `--stream`: prints tokens and
+/// errors as the lexer streams them.
+fn tokenize_stream(filename: &str, extensions: bool) -> i32 {
+    let reader: Box<dyn BufRead> = if filename == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        match fs::File::open(filename) {
+            Ok(file) => Box::new(io::BufReader::new(file)),
+            Err(_) => {
+                eprintln!("Failed to read file {}", filename);
+                return 0;
             }
         }
-        "run" => {
-            let file_contents = fs::read_to_string(filename).unwrap_or_else(|_| {
-                writeln!(io::stderr(), "Failed to read file {}", filename).unwrap();
-                String::new()
-            });
-            if !file_contents.is_empty() {
-                let (tokens, code) = lexer::scan(file_contents);
-                if code != 0 {
-                    exit(code);
-                }
-                let mut parser = Parser::new(tokens);
-                let stmts = parser.parse2();
-
-                match stmts {
-                    Ok(stmts) => {
-                        let mut interpreter = Interpreter::new();
-                        let result = interpreter.execute(&stmts);
-                        match result {
-                            Ok(_) => (),
-                            Err(error) => {
-                                writeln!(io::stderr(), "{}", error).unwrap();
-                                exit(70);
-                            }
-                        }
-                    }
-                    Err(errors) => {
-                        for error in errors {
-                            writeln!(io::stderr(), "{}", error).unwrap();
-                        }
-                        exit(65);
-                    }
-                }
+    };
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    let mut code = 0;
+    for result in lexer::Lexer::new(reader).with_extensions(extensions) {
+        match result {
+            Ok(token) => writeln!(stdout, "{}", token).unwrap(),
+            Err(error) => {
+                // Flushed first so errors still appear where they occurred.
+                stdout.flush().unwrap();
+                explain::report(error);
+                code = 65;
+            }
+        }
+    }
+    stdout.flush().unwrap();
+    code
+}
+
+/// Scans `source`, with the extensions' tokens if `args` enable them,
+/// reporting lexical errors. `Err` holds the exit code.
+fn scan(args: &Args, source: &str) -> Result<Vec<Token>, i32> {
+    let (tokens, errors) = lexer::scan(source, args.has_flag("extensions"));
+    if errors.is_empty() {
+        return Ok(tokens);
+    }
+    for error in errors {
+        explain::report(error);
+    }
+    Err(65)
+}
+
+/// A parser for `tokens`, with the extensions' syntax if `args` enable it.
+fn parser(args: &Args, tokens: Vec<Token>) -> Parser {
+    Parser::new(tokens).with_extensions(args.has_flag("extensions"))
+}
+
+/// `parse`: prints each expression in the file as an S-expression, or each
+/// statement when the file holds a program.
+fn parse(args: &Args) -> i32 {
+    let file_contents = read_file(&args.filename);
+    if file_contents.is_empty() {
+        return 0;
+    }
+
+    let tokens = match scan(args, &file_contents) {
+        Ok(tokens) => tokens,
+        Err(code) => return code,
+    };
+    if is_program(&tokens) {
+        return print_stmts(args, tokens);
+    }
+    let mut parser = parser(args, tokens);
+    match parser.parse() {
+        Ok(exprs) => {
+            for expr in exprs {
+                println!("{}", expr);
             }
+            0
         }
-        _ => {
-            writeln!(io::stderr(), "Unknown command: {}", command).unwrap();
-            return;
+        Err(errors) => {
+            for error in errors {
+                explain::report(error);
+            }
+            65
         }
     }
 }
 
+/// `fmt`: prints the program in canonical layout, see `format::format`.
+fn fmt(args: &Args) -> i32 {
+    let file_contents = read_file(&args.filename);
+    let tokens = match scan(args, &file_contents) {
+        Ok(tokens) => tokens,
+        Err(code) => return code,
+    };
+    match parser(args, tokens).parse2() {
+        Ok(stmts) => {
+            print!("{}", format::format(&stmts));
+            0
+        }
+        Err(errors) => {
+            for error in errors {
+                explain::report(error);
+            }
+            65
+        }
+    }
+}
+
+/// `typecheck`: reports likely type errors without running the program.
+fn typecheck(args: &Args) -> i32 {
+    let file_contents = read_file(&args.filename);
+    let tokens = match scan(args, &file_contents) {
+        Ok(tokens) => tokens,
+        Err(code) => return code,
+    };
+    let stmts = match parser(args, tokens).parse2() {
+        Ok(stmts) => stmts,
+        Err(errors) => {
+            for error in errors {
+                explain::report(error);
+            }
+            return 65;
+        }
+    };
+    let errors = typecheck::check(&stmts, args.has_flag("extensions"));
+    if errors.is_empty() {
+        return 0;
+    }
+    for error in errors {
+        explain::report(error);
+    }
+    65
+}
+
+/// `semantic-tokens`: prints the program's highlighting classes as JSON.
+fn semantic_tokens(args: &Args) -> i32 {
+    let file_contents = read_file(&args.filename);
+    let tokens = match scan(args, &file_contents) {
+        Ok(tokens) => tokens,
+        Err(code) => return code,
+    };
+    let stmts = match parser(args, tokens.clone()).parse2() {
+        Ok(stmts) => stmts,
+        Err(errors) => {
+            for error in errors {
+                explain::report(error);
+            }
+            return 65;
+        }
+    };
+    export::write_semantic_tokens(&mut io::stdout(), &file_contents, &tokens, &stmts).unwrap();
+    0
+}
+
+/// `parse-stmt`: prints every statement in the file as an S-expression.
+fn parse_stmt(args: &Args) -> i32 {
+    let file_contents = read_file(&args.filename);
+    match scan(args, &file_contents) {
+        Ok(tokens) => print_stmts(args, tokens),
+        Err(code) => code,
+    }
+}
+
+fn print_stmts(args: &Args, tokens: Vec<Token>) -> i32 {
+    match parser(args, tokens).parse2() {
+        Ok(stmts) => {
+            for stmt in stmts {
+                println!("{}", stmt);
+            }
+            0
+        }
+        Err(errors) => {
+            for error in errors {
+                explain::report(error);
+            }
+            65
+        }
+    }
+}
+
+/// `parse-expr`: prints the single expression the file holds.
+fn parse_expr(args: &Args) -> i32 {
+    let file_contents = read_file(&args.filename);
+    let tokens = match scan(args, &file_contents) {
+        Ok(tokens) => tokens,
+        Err(code) => return code,
+    };
+    match parser(args, tokens).parse_expression() {
+        Ok(expr) => {
+            println!("{}", expr);
+            0
+        }
+        Err(error) => {
+            explain::report(error);
+            65
+        }
+    }
+}
+
+fn evaluate(args: &Args) -> i32 {
+    let file_contents = read_file(&args.filename);
+    if file_contents.is_empty() {
+        return 0;
+    }
+
+    let tokens = match scan(args, &file_contents) {
+        Ok(tokens) => tokens,
+        Err(code) => return code,
+    };
+    let mut interpreter = Interpreter::new().with_extensions(args.has_flag("extensions"));
+    if is_program(&tokens) {
+        return match evaluate_program(args, &mut interpreter, tokens) {
+            Ok(_) => 0,
+            Err(code) => code,
+        };
+    }
+    let mut parser = parser(args, tokens);
+    let exprs = match parser.parse() {
+        Ok(exprs) => exprs,
+        Err(errors) => {
+            for error in errors {
+                explain::report(error);
+            }
+            return 65;
+        }
+    };
+
+    match interpreter.interpret(exprs) {
+        Ok(values) => {
+            for value in values {
+                println!("{}", value);
+            }
+            0
+        }
+        Err(errors) => {
+            for error in errors {
+                explain::report(error);
+            }
+            70
+        }
+    }
+}
+
+/// Whether `evaluate` or `parse` was given statements rather than bare expressions:
+/// anything with a `;` or starting with a statement keyword or block.
+fn is_program(tokens: &[Token]) -> bool {
+    let starts_statement = tokens.first().is_some_and(|token| {
+        matches!(
+            token.value,
+            TokenValue::Var
+                | TokenValue::Print
+                | TokenValue::Fun
+                | TokenValue::If
+                | TokenValue::While
+                | TokenValue::For
+                | TokenValue::Return
+                | TokenValue::LeftBrace
+        )
+    });
+    starts_statement
+        || tokens
+            .iter()
+            .any(|token| token.value == TokenValue::Semicolon)
+}
+
+/// Runs a program for `evaluate` and the REPL. If the last statement is an
+/// expression its value is printed, as a bare expression's would be, and
+/// returned. Fails with the exit code.
+fn evaluate_program(
+    args: &Args,
+    interpreter: &mut Interpreter,
+    mut tokens: Vec<Token>,
+) -> Result<Option<Value>, i32> {
+    // The final expression may leave off its semicolon.
+    let eof = tokens.len() - 1;
+    if eof > 0
+        && !matches!(
+            tokens[eof - 1].value,
+            TokenValue::Semicolon | TokenValue::RightBrace
+        )
+    {
+        let eof_token = &tokens[eof];
+        let semicolon = Token::new(
+            TokenValue::Semicolon,
+            String::new(),
+            eof_token.line,
+            eof_token.start,
+        );
+        tokens.insert(eof, semicolon);
+    }
+    let mut stmts = match parser(args, tokens).parse2() {
+        Ok(stmts) => stmts,
+        Err(errors) => {
+            for error in errors {
+                explain::report(error);
+            }
+            return Err(65);
+        }
+    };
+    let last = match stmts.pop() {
+        Some(Stmt::Expression(last)) => Some(*last.expr),
+        other => {
+            stmts.extend(other);
+            None
+        }
+    };
+
+    if let Err(e) = interpreter.execute_program(&stmts) {
+        explain::report(e);
+        return Err(70);
+    }
+    let Some(last) = last else {
+        return Ok(None);
+    };
+    match interpreter.interpret(vec![last]) {
+        Ok(mut values) => {
+            for value in &values {
+                println!("{}", value);
+            }
+            Ok(values.pop())
+        }
+        Err(errors) => {
+            for error in errors {
+                explain::report(error);
+            }
+            Err(70)
+        }
+    }
+}
+
+fn run(args: &Args) -> i32 {
+    interrupt::install();
+    let mut interpreter = match interpreter(args) {
+        Ok(interpreter) => interpreter,
+        Err(code) => return code,
+    };
+    let started = Instant::now();
+    let code = run_script(args, &mut interpreter);
+    report(args, &interpreter, started.elapsed());
+    code
+}
+
+/// Builds an interpreter configured by the `run` flags.
+fn interpreter(args: &Args) -> Result<Interpreter, i32> {
+    let max_depth = args
+        .parse_flag("max-depth")?
+        .unwrap_or(eval::DEFAULT_MAX_DEPTH);
+    // `--fake-clock` starts at 0, `--fake-clock=N` at N.
+    let fake_clock = args
+        .has_flag("fake-clock")
+        .then(|| args.parse_flag("fake-clock"))
+        .transpose()?
+        .map(|start| start.unwrap_or(0.0));
+    let mut interpreter = Interpreter::new()
+        .with_path(&args.filename)
+        .with_max_depth(max_depth)
+        .with_max_steps(args.parse_flag("max-steps")?)
+        .with_max_memory(args.parse_flag("max-memory")?)
+        .with_sandbox(args.has_flag("sandbox"))
+        .with_prelude(!args.has_flag("no-prelude"))
+        .with_strict(args.has_flag("strict"))
+        .with_extensions(args.has_flag("extensions"))
+        .with_integers(args.has_flag("integers"))
+        .with_backend(args.parse_flag("backend")?.unwrap_or_default())
+        .with_warnings(!args.has_flag("no-warnings"))
+        .with_fake_clock(fake_clock)
+        .with_ast_cache(args.has_flag("ast-cache"))
+        .with_gc_stress(args.has_flag("gc-stress"))
+        .with_gc_log(args.has_flag("gc-log"))
+        .with_profiling(args.has_flag("profile"))
+        .with_coverage(args.has_flag("coverage") || args.has_flag("lcov"));
+    if args.has_flag("trace") {
+        trace(&mut interpreter);
+    }
+    Ok(interpreter)
+}
+
+/// `--trace`: logs every statement and call to stderr as it runs.
+fn trace(interpreter: &mut Interpreter) {
+    interpreter.on_statement(|stmt, line| eprintln!("[line {}] {}", line, stmt));
+    interpreter.on_call(|name, args| {
+        let args = args.iter().map(Value::to_string).collect::<Vec<_>>();
+        eprintln!("call {}({})", name, args.join(", "));
+    });
+}
+
+/// Prints the reports requested by the `run` flags once the script finished,
+/// `elapsed` after it started.
+fn report(args: &Args, interpreter: &Interpreter, elapsed: Duration) {
+    if args.has_flag("stats") {
+        let expressions = interpreter.expressions();
+        let rate = expressions as f64 / elapsed.as_secs_f64().max(1e-9);
+        eprintln!("{:<18} {}", "expressions", expressions);
+        eprintln!("{:<18} {:.0}", "expressions/s", rate);
+        eprintln!("{:<18} {}", "peak call depth", interpreter.peak_depth());
+        eprintln!("{:<18} {} KiB", "peak memory", memory::peak() / 1024);
+    }
+    interpreter.write_profile(&mut io::stderr()).unwrap();
+    if let Some(coverage) = interpreter.coverage() {
+        coverage.write_summary(&mut io::stderr()).unwrap();
+        if let Some(path) = args.flag_value("lcov") {
+            let written =
+                fs::File::create(path).and_then(|mut file| coverage.write_lcov(&mut file));
+            if let Err(e) = written {
+                eprintln!("Failed to write {}: {}", path, e);
+            }
+        }
+    }
+}
+
+fn run_script(args: &Args, interpreter: &mut Interpreter) -> i32 {
+    let mut phases = Phases::default();
+    let code = run_phases(args, interpreter, &mut phases);
+    if args.has_flag("time") {
+        phases.report();
+    }
+    code
+}
+
+/// Wall-clock time spent in each phase of running a script, in the order
+/// they ran, for `--time`.
+#[derive(Default)]
+struct Phases(Vec<(&'static str, Duration)>);
+
+impl Phases {
+    fn time<T>(&mut self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let result = f();
+        self.0.push((phase, started.elapsed()));
+        result
+    }
+
+    /// Prints the phases to stderr, keeping stdout the script's.
+    fn report(&self) {
+        for (phase, duration) in &self.0 {
+            eprintln!("{:<18} {:.3} ms", phase, duration.as_secs_f64() * 1e3);
+        }
+        let total = self
+            .0
+            .iter()
+            .map(|(_, duration)| *duration)
+            .sum::<Duration>();
+        eprintln!("{:<18} {:.3} ms", "total", total.as_secs_f64() * 1e3);
+    }
+}
+
+fn run_phases(args: &Args, interpreter: &mut Interpreter, phases: &mut Phases) -> i32 {
+    let file_contents = read_file(&args.filename);
+    if file_contents.is_empty() {
+        return 0;
+    }
+
+    // A cached script has no tokens to dump, so `--dump-tokens` skips the
+    // cache.
+    let extensions = args.has_flag("extensions");
+    let cache_path = (args.has_flag("ast-cache") && !args.has_flag("dump-tokens"))
+        .then(|| Path::new(&args.filename));
+    let cached = cache_path.and_then(|path| {
+        phases.time("load cache", || {
+            cache::load(path, &file_contents, extensions)
+        })
+    });
+    let stmts = match cached {
+        Some(stmts) => stmts,
+        None => {
+            let stmts = match parse_script(args, &file_contents, phases) {
+                Ok(stmts) => stmts,
+                Err(code) => return code,
+            };
+            if let Some(path) = cache_path {
+                phases.time("store cache", || {
+                    cache::store(path, &file_contents, extensions, &stmts)
+                });
+            }
+            stmts
+        }
+    };
+    if args.has_flag("dump-ast") {
+        for stmt in &stmts {
+            eprintln!("{}", stmt);
+        }
+    }
+
+    phases.time("resolve", || interpreter.resolve_program(&stmts));
+    match phases.time("execute", || interpreter.execute_resolved(&stmts)) {
+        Ok(_) => 0,
+        Err(error) => {
+            explain::report(error);
+            70
+        }
+    }
+}
+
+/// Scans and parses the script, reporting errors with exit code 65.
+fn parse_script(args: &Args, source: &str, phases: &mut Phases) -> Result<Vec<Stmt>, i32> {
+    let tokens = phases.time("lex", || scan(args, source))?;
+    if args.has_flag("dump-tokens") {
+        for token in &tokens {
+            eprintln!("{}", token);
+        }
+    }
+    phases
+        .time("parse", || parser(args, tokens).parse2())
+        .map_err(|errors| {
+            for error in errors {
+                explain::report(error);
+            }
+            65
+        })
+}
+
+/// `test <dir>`: runs the golden tests on `--jobs` threads (one per core by
+/// default), failing any that take longer than `--timeout` seconds.
+fn test(args: &Args) -> i32 {
+    match test_options(args) {
+        Ok((jobs, timeout)) => golden::run(Path::new(&args.filename), jobs, timeout),
+        Err(code) => code,
+    }
+}
+
+/// `conformance [dir]`: runs the reference test corpus, `tests/cases` by
+/// default, with the same options as `test`.
+fn conformance(args: &Args) -> i32 {
+    let dir = match args.filename.as_str() {
+        "" => conformance::DEFAULT_DIR,
+        dir => dir,
+    };
+    match test_options(args) {
+        Ok((jobs, timeout)) => conformance::run(Path::new(dir), jobs, timeout),
+        Err(code) => code,
+    }
+}
+
+/// The `--jobs` and `--timeout` flags of the test runners.
+fn test_options(args: &Args) -> Result<(usize, Duration), i32> {
+    let jobs = args
+        .parse_flag("jobs")?
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, usize::from));
+    let timeout = Duration::try_from_secs_f64(args.parse_flag("timeout")?.unwrap_or(10.0))
+        .map_err(|_| {
+            eprintln!("Invalid value for --timeout");
+            64
+        })?;
+    Ok((jobs, timeout))
+}
+
+/// `roundtrip`: checks `--cases` random programs (1000 by default) survive
+/// formatting and parsing back. `--seed` picks the programs; without it they
+/// differ every run, and a failure prints the seed that reproduces it.
+fn roundtrip(args: &Args) -> i32 {
+    let cases = match args.parse_flag("cases") {
+        Ok(cases) => cases.unwrap_or(1000),
+        Err(code) => return code,
+    };
+    let seed = match args.parse_flag("seed") {
+        Ok(seed) => seed.unwrap_or_else(|| random::Rng::from_time().next_u64()),
+        Err(code) => return code,
+    };
+    roundtrip::run(cases, seed)
+}
+
+fn bench(args: &Args) -> i32 {
+    let iterations = match args.parse_flag("iterations") {
+        Ok(iterations) => iterations.unwrap_or(10).max(1),
+        Err(code) => return code,
+    };
+    let backend = match args.parse_flag("backend") {
+        Ok(backend) => backend.unwrap_or_default(),
+        Err(code) => return code,
+    };
+    bench::run(&read_file(&args.filename), iterations, backend)
+}
+
+/// Re-runs the script every time it (or any module it imports) is modified,
+/// starting from a fresh interpreter each time. Ctrl-C stops a run in
+/// progress, as it does for `run`; pressed while waiting for changes, it ends
+/// the watch.
+fn watch(args: &Args) -> i32 {
+    interrupt::install();
+    loop {
+        eprintln!("=== [{}] {} ===", timestamp(), args.filename);
+        let mut interpreter = match interpreter(args) {
+            Ok(interpreter) => interpreter,
+            Err(code) => return code,
+        };
+        let started = Instant::now();
+        let code = run_script(args, &mut interpreter);
+        report(args, &interpreter, started.elapsed());
+        interrupt::reset();
+        eprintln!(
+            "=== [{}] exited with code {}, waiting for changes ===",
+            timestamp(),
+            code
+        );
+
+        let mut files = vec![PathBuf::from(&args.filename)];
+        files.extend(interpreter.imported_files().map(|path| path.to_path_buf()));
+        let last_modified = modified(&files);
+        while modified(&files) == last_modified {
+            if interrupt::requested() {
+                // As a shell reports a process ended by SIGINT.
+                return 130;
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+    }
+}
+
+fn modified(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| fs::metadata(file).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Wall-clock time of day as `HH:MM:SS` (UTC).
+fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Dispatches an AST node to the matching method of a visitor. Visitors are
+/// borrowed mutably so they can keep state (the interpreter, the resolver)
+/// without interior mutability.
 pub trait Walkable<V, T> {
     fn walk(&self, visitor: &mut V) -> T;
 }
@@ -0,0 +1,100 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{eval::Value, number::Number};
+
+/// How many results a function made by `memoize(fn)` keeps when not given a
+/// limit.
+pub const DEFAULT_LIMIT: usize = 10_000;
+
+/// The results of a function made by `memoize`, keyed on its arguments.
+/// Arguments equal under `==` share an entry: strings by contents, numbers by
+/// value and functions, modules and processes by identity.
+///
+/// Nothing is ever invalidated. A result stays cached even if the globals the
+/// function read to compute it change, and a call that hits the cache has no
+/// side effects, so only pure functions should be memoized. Calls that fail
+/// are not cached.
+#[derive(Clone, Debug)]
+pub struct Memo {
+    results: HashMap<Vec<Key>, Value>,
+    /// The most results kept. Once full, the cache is emptied and starts
+    /// over, which keeps a long-running script's memory bounded.
+    limit: usize,
+}
+
+impl Memo {
+    pub fn new(limit: usize) -> Self {
+        Memo {
+            results: HashMap::new(),
+            limit,
+        }
+    }
+
+    pub fn get(&self, args: &[Value]) -> Option<Value> {
+        self.results.get(&key(args)).cloned()
+    }
+
+    pub fn insert(&mut self, args: &[Value], result: Value) {
+        if self.results.len() >= self.limit {
+            self.results.clear();
+        }
+        self.results.insert(key(args), result);
+    }
+}
+
+/// An argument as part of a cache key.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Key {
+    Nil,
+    Boolean(bool),
+    /// A whole number, whether written as an integer or not, so `1` and
+    /// `1.0` share an entry as they are `==`.
+    Integer(i64),
+    /// The bits of any other number, with every `NaN` made the same.
+    Number(u64),
+    String(Rc<String>),
+    /// The address of a function, module or process. The value is kept
+    /// alongside so the address can't be reused while the entry exists.
+    Identity(*const (), Holder),
+}
+
+/// Keeps a function, module or process alive, without taking part in
+/// comparisons.
+#[derive(Clone, Debug)]
+struct Holder(#[allow(dead_code)] Value);
+
+impl PartialEq for Holder {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Holder {}
+
+impl std::hash::Hash for Holder {
+    fn hash<H: std::hash::Hasher>(&self, _: &mut H) {}
+}
+
+fn key(args: &[Value]) -> Vec<Key> {
+    args.iter().map(argument_key).collect()
+}
+
+fn argument_key(value: &Value) -> Key {
+    match value {
+        Value::Nil | Value::Uninitialized => Key::Nil,
+        Value::Boolean(b) => Key::Boolean(*b),
+        Value::Integer(n) => Key::Integer(*n),
+        Value::Number(Number(n)) if n.fract() == 0.0 && n.abs() < 2f64.powi(63) => {
+            Key::Integer(*n as i64)
+        }
+        Value::Number(Number(n)) if n.is_nan() => Key::Number(f64::NAN.to_bits()),
+        Value::Number(Number(n)) => Key::Number(n.to_bits()),
+        Value::String(s) => Key::String(s.clone()),
+        Value::Function(func) => Key::Identity(Rc::as_ptr(func).cast(), Holder(value.clone())),
+        Value::RustFunction(native) => {
+            Key::Identity(std::ptr::from_ref(*native).cast(), Holder(value.clone()))
+        }
+        Value::Module(module) => Key::Identity(Rc::as_ptr(module).cast(), Holder(value.clone())),
+        Value::Process(process) => Key::Identity(Rc::as_ptr(process).cast(), Holder(value.clone())),
+    }
+}
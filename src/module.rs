@@ -0,0 +1,78 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Error, Result};
+
+use crate::{
+    cache,
+    explain::{self, Coded},
+    lexer,
+    parser::Parser,
+    stmt::Stmt,
+};
+
+/// Resolves an import path relative to the directory of the importing file
+/// (or the working directory for scripts without a path) and canonicalizes it
+/// so the same module is only ever loaded once.
+pub fn resolve(importer: Option<&Path>, import: &str) -> Option<PathBuf> {
+    let base = importer
+        .and_then(|path| path.parent())
+        .unwrap_or(Path::new(""));
+    base.join(import).canonicalize().ok()
+}
+
+/// Reads, scans and parses a module file, with the extensions' syntax if
+/// `extensions`. With `ast_cache`, the statements come from the AST cache
+/// when it holds them, and are stored in it when not.
+pub fn load(path: &Path, ast_cache: bool, extensions: bool) -> Result<Vec<Stmt>, Error> {
+    let source = fs::read_to_string(path).map_err(|_| {
+        explain::error(
+            "R0016",
+            format!("Could not read module '{}'.", display_name(path)),
+        )
+    })?;
+    if let Some(stmts) = ast_cache
+        .then(|| cache::load(path, &source, extensions))
+        .flatten()
+    {
+        return Ok(stmts);
+    }
+    let (tokens, errors) = lexer::scan(&source, extensions);
+    if !errors.is_empty() {
+        return Err(compile_error(path, &errors));
+    }
+    let stmts = Parser::new(tokens)
+        .with_extensions(extensions)
+        .parse2()
+        .map_err(|errors| compile_error(path, &errors))?;
+    if ast_cache {
+        cache::store(path, &source, extensions, &stmts);
+    }
+    Ok(stmts)
+}
+
+/// An error listing the module's errors, with the code of the first.
+fn compile_error<E: Coded>(path: &Path, errors: &[E]) -> Error {
+    let message = format!(
+        "{}\nCould not compile module '{}'.",
+        errors
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        display_name(path)
+    );
+    match errors.first().and_then(Coded::code) {
+        Some(code) => explain::error(code, message),
+        None => Error::msg(message),
+    }
+}
+
+pub fn display_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,535 @@
+use std::{
+    cell::RefCell,
+    fmt::Debug,
+    process::{Command, Stdio},
+    rc::Rc,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Error, Result};
+
+use crate::{
+    eval::{Interpreter, LoxFunction, Process, Value},
+    explain, interrupt,
+    memo::{self, Memo},
+    number::Number,
+    random::Rng,
+    spawn::{self, Message},
+};
+
+/// A builtin function implemented in Rust.
+pub struct Native {
+    pub name: &'static str,
+    pub arity: usize,
+    /// Whether the native takes any number of arguments past `arity`.
+    pub variadic: bool,
+    /// Whether the native reaches outside the interpreter (files, environment,
+    /// processes) or can stall the host (sleeping). Those are unavailable in
+    /// sandbox mode.
+    pub ambient: bool,
+    pub func: fn(&mut Interpreter, &[Value]) -> Result<Value, Error>,
+}
+
+impl Debug for Native {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Native({})", self.name)
+    }
+}
+
+pub static NATIVES: &[Native] = &[
+    Native {
+        name: "channel",
+        arity: 0,
+        variadic: false,
+        ambient: false,
+        func: channel,
+    },
+    Native {
+        name: "clock",
+        arity: 0,
+        variadic: false,
+        ambient: false,
+        func: clock,
+    },
+    Native {
+        name: "elapsed",
+        arity: 1,
+        variadic: false,
+        ambient: false,
+        func: elapsed,
+    },
+    Native {
+        name: "exec",
+        arity: 1,
+        variadic: false,
+        ambient: true,
+        func: exec,
+    },
+    Native {
+        name: "execArgs",
+        arity: 1,
+        variadic: true,
+        ambient: true,
+        func: exec_args,
+    },
+    Native {
+        name: "format",
+        arity: 1,
+        variadic: true,
+        ambient: false,
+        func: format,
+    },
+    Native {
+        name: "formatTime",
+        arity: 2,
+        variadic: false,
+        ambient: false,
+        func: format_time,
+    },
+    Native {
+        name: "getenv",
+        arity: 1,
+        variadic: false,
+        ambient: true,
+        func: getenv,
+    },
+    Native {
+        name: "random",
+        arity: 0,
+        variadic: false,
+        ambient: false,
+        func: random,
+    },
+    Native {
+        name: "randomInt",
+        arity: 2,
+        variadic: false,
+        ambient: false,
+        func: random_int,
+    },
+    Native {
+        name: "recv",
+        arity: 1,
+        variadic: false,
+        ambient: false,
+        func: recv,
+    },
+    Native {
+        name: "seed",
+        arity: 1,
+        variadic: false,
+        ambient: false,
+        func: seed,
+    },
+    Native {
+        name: "send",
+        arity: 2,
+        variadic: false,
+        ambient: false,
+        func: send,
+    },
+    Native {
+        name: "setenv",
+        arity: 2,
+        variadic: false,
+        ambient: true,
+        func: setenv,
+    },
+    Native {
+        name: "join",
+        arity: 1,
+        variadic: false,
+        ambient: false,
+        func: join,
+    },
+    Native {
+        name: "memoize",
+        arity: 1,
+        variadic: true,
+        ambient: false,
+        func: memoize,
+    },
+    Native {
+        name: "now",
+        arity: 0,
+        variadic: false,
+        ambient: false,
+        func: now,
+    },
+    Native {
+        name: "println",
+        arity: 1,
+        variadic: false,
+        ambient: false,
+        func: println,
+    },
+    Native {
+        name: "write",
+        arity: 1,
+        variadic: false,
+        ambient: false,
+        func: write,
+    },
+    Native {
+        name: "sleep",
+        arity: 1,
+        variadic: false,
+        ambient: true,
+        func: sleep,
+    },
+    Native {
+        name: "spawn",
+        arity: 1,
+        variadic: true,
+        ambient: true,
+        func: spawn,
+    },
+];
+
+fn clock(interpreter: &mut Interpreter, _: &[Value]) -> Result<Value, Error> {
+    if let Some(now) = interpreter.tick() {
+        return Ok(Value::Number(Number(now)));
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    Ok(Value::Number(Number(now as f64)))
+}
+
+/// `now()`: milliseconds since the Unix epoch.
+fn now(interpreter: &mut Interpreter, _: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Number(Number(epoch_millis(interpreter))))
+}
+
+/// `elapsed(start)`: milliseconds since `start`, a value returned by `now`.
+fn elapsed(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, Error> {
+    let start = number_arg(&args[0])?;
+    Ok(Value::Number(Number(epoch_millis(interpreter) - start)))
+}
+
+/// `formatTime(epoch, fmt)`: the UTC time `epoch` milliseconds after the Unix
+/// epoch, formatted with the strftime-style directives `%Y`, `%m`, `%d`, `%H`,
+/// `%M`, `%S` and `%%`.
+fn format_time(_: &mut Interpreter, args: &[Value]) -> Result<Value, Error> {
+    let millis = number_arg(&args[0])?;
+    let fmt = string_arg(&args[1])?;
+    let seconds = (millis / 1000.0).floor() as i64;
+    let (days, time) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+    let (year, month, day) = civil_from_days(days);
+    let mut out = String::with_capacity(fmt.len());
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => out.push_str(&format!("{:04}", year)),
+            Some('m') => out.push_str(&format!("{:02}", month)),
+            Some('d') => out.push_str(&format!("{:02}", day)),
+            Some('H') => out.push_str(&format!("{:02}", time / 3600)),
+            Some('M') => out.push_str(&format!("{:02}", time / 60 % 60)),
+            Some('S') => out.push_str(&format!("{:02}", time % 60)),
+            Some('%') => out.push('%'),
+            Some(other) => {
+                return Err(explain::error(
+                    "R0025",
+                    format!("Unknown time directive '%{}'.", other),
+                ))
+            }
+            None => return Err(explain::error("R0026", "Time format ends with '%'.")),
+        }
+    }
+    Ok(Value::String(Rc::new(out)))
+}
+
+fn epoch_millis(interpreter: &mut Interpreter) -> f64 {
+    if let Some(now) = interpreter.tick() {
+        return now * 1000.0;
+    }
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as f64
+}
+
+/// The proleptic Gregorian (year, month, day) of a day count from
+/// 1970-01-01, after Howard Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// `println(value)`: the `print` statement as a function.
+fn println(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, Error> {
+    interpreter.print(&args[0])?;
+    Ok(Value::Nil)
+}
+
+/// `write(value)`: like `println`, without the trailing newline.
+fn write(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, Error> {
+    interpreter.write(&args[0])?;
+    Ok(Value::Nil)
+}
+
+/// `format(fmt, ...)`: `fmt` with each `{}` replaced by the next argument
+/// and each `{N}` by the argument at index `N`, printed as `print` would.
+/// `{{` and `}}` stand for literal braces.
+fn format(_: &mut Interpreter, args: &[Value]) -> Result<Value, Error> {
+    let fmt = string_arg(&args[0])?;
+    let values = &args[1..];
+    let mut out = String::with_capacity(fmt.len());
+    let mut next = 0;
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let rest = chars.as_str();
+                let close = rest
+                    .find('}')
+                    .ok_or_else(|| explain::error("R0021", "Unmatched '{' in format string."))?;
+                let index = match &rest[..close] {
+                    "" => {
+                        next += 1;
+                        next - 1
+                    }
+                    digits => digits.parse::<usize>().map_err(|_| {
+                        explain::error("R0022", format!("Invalid placeholder '{{{}}}'.", digits))
+                    })?,
+                };
+                let value = values.get(index).ok_or_else(|| {
+                    explain::error(
+                        "R0023",
+                        format!(
+                            "Placeholder refers to argument {} but only {} were given.",
+                            index,
+                            values.len()
+                        ),
+                    )
+                })?;
+                out.push_str(&value.to_string());
+                chars = rest[close + 1..].chars();
+            }
+            '}' => return Err(explain::error("R0021", "Unmatched '}' in format string.")),
+            c => out.push(c),
+        }
+    }
+    Ok(Value::String(Rc::new(out)))
+}
+
+/// `random()`: a number in `[0, 1)`.
+fn random(interpreter: &mut Interpreter, _: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Number(Number(interpreter.rng().next_f64())))
+}
+
+/// `randomInt(lo, hi)`: an integer in `[lo, hi]`.
+fn random_int(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, Error> {
+    let lo = integer_arg(&args[0])?;
+    let hi = integer_arg(&args[1])?;
+    if lo > hi {
+        return Err(explain::error(
+            "R0024",
+            "Lower bound must not exceed upper bound.",
+        ));
+    }
+    Ok(Value::Number(Number(
+        interpreter.rng().next_in(lo, hi) as f64
+    )))
+}
+
+/// `seed(n)`: restarts `random` and `randomInt` on the stream fixed by `n`.
+fn seed(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, Error> {
+    *interpreter.rng() = Rng::new(integer_arg(&args[0])? as u64);
+    Ok(Value::Nil)
+}
+
+/// `spawn(fn, ...)`: calls the top-level function `fn` with the remaining
+/// arguments on a new thread and returns a handle for `join`. See
+/// `Interpreter::spawn` for what the function can see.
+fn spawn(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, Error> {
+    let Value::Function(func) = &args[0] else {
+        return Err(explain::error("R0019", "Argument must be a function."));
+    };
+    let handle = interpreter.spawn(func, &args[1..])?;
+    Ok(Value::Number(Number(handle as f64)))
+}
+
+/// `memoize(fn)` or `memoize(fn, limit)`: a copy of `fn` that caches its
+/// results by argument, keeping at most `limit` of them. See `Memo` for when
+/// a result is reused.
+fn memoize(_: &mut Interpreter, args: &[Value]) -> Result<Value, Error> {
+    let Value::Function(func) = &args[0] else {
+        return Err(explain::error("R0019", "Argument must be a function."));
+    };
+    let limit = match args {
+        [_] => memo::DEFAULT_LIMIT,
+        [_, limit] => match integer_arg(limit)? {
+            limit @ 1.. => limit as usize,
+            _ => return Err(explain::error("R0048", "Memo limit must be positive.")),
+        },
+        _ => {
+            return Err(explain::error(
+                "R0006",
+                format!(
+                    "Expected at most 2 arguments but got {} in call to 'memoize'.",
+                    args.len()
+                ),
+            ))
+        }
+    };
+    Ok(Value::Function(Rc::new(LoxFunction {
+        memo: Some(RefCell::new(Memo::new(limit))),
+        ..(**func).clone()
+    })))
+}
+
+/// `join(handle)`: waits for a spawned function and returns its result.
+fn join(_: &mut Interpreter, args: &[Value]) -> Result<Value, Error> {
+    Ok(spawn::join(index_arg(&args[0])?)?.into_value())
+}
+
+/// `channel()`: a new channel for `send` and `recv`, usable from any thread.
+fn channel(_: &mut Interpreter, _: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Number(Number(spawn::channel() as f64)))
+}
+
+/// `send(channel, value)`: queues a nil, boolean, number or string.
+fn send(_: &mut Interpreter, args: &[Value]) -> Result<Value, Error> {
+    spawn::send(index_arg(&args[0])?, Message::from_value(&args[1])?)?;
+    Ok(Value::Nil)
+}
+
+/// `recv(channel)`: the oldest value sent on `channel`, waiting for one.
+fn recv(_: &mut Interpreter, args: &[Value]) -> Result<Value, Error> {
+    Ok(spawn::recv(index_arg(&args[0])?)?.into_value())
+}
+
+/// `exec(cmd)`: runs `cmd` with `sh -c` and waits for it, returning the
+/// finished `Process`. Its `code` is `nil` when it was killed by a signal.
+fn exec(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, Error> {
+    let cmd = string_arg(&args[0])?;
+    run_process(
+        Command::new("sh")
+            .arg("-c")
+            .arg(cmd)
+            .envs(interpreter.env_vars()),
+    )
+}
+
+/// `execArgs(program, ...)`: runs `program` directly with the remaining
+/// arguments, which must be strings, and returns a result like `exec`.
+fn exec_args(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, Error> {
+    let mut command = Command::new(string_arg(&args[0])?);
+    command.envs(interpreter.env_vars());
+    for arg in &args[1..] {
+        command.arg(string_arg(arg)?);
+    }
+    run_process(&mut command)
+}
+
+fn run_process(command: &mut Command) -> Result<Value, Error> {
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| explain::error("R0027", format!("Could not run process: {}.", e)))?;
+    let text = |bytes: &[u8]| Rc::new(String::from_utf8_lossy(bytes).into_owned());
+    Ok(Value::Process(Rc::new(Process {
+        stdout: text(&output.stdout),
+        stderr: text(&output.stderr),
+        code: output.status.code(),
+    })))
+}
+
+/// `getenv(name)`: the value of an environment variable, or `nil` if unset.
+fn getenv(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, Error> {
+    let name = string_arg(&args[0])?;
+    Ok(interpreter
+        .env_var(name)
+        .map_or(Value::Nil, |value| Value::String(Rc::new(value))))
+}
+
+/// `setenv(name, value)`: sets an environment variable for the rest of the
+/// script, the threads it spawns afterwards and the processes it runs.
+fn setenv(interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, Error> {
+    let name = string_arg(&args[0])?;
+    let value = string_arg(&args[1])?;
+    if name.is_empty() || name.contains(['=', '\0']) || value.contains('\0') {
+        return Err(explain::error("R0020", "Invalid environment variable."));
+    }
+    interpreter.set_env_var(name, value);
+    Ok(Value::Nil)
+}
+
+/// `sleep(seconds)`: blocks for a (possibly fractional) number of seconds.
+/// Ctrl-C cuts the sleep short with an "Interrupted." error. Counts as a
+/// single step against the step budget.
+fn sleep(_: &mut Interpreter, args: &[Value]) -> Result<Value, Error> {
+    let seconds = match &args[0] {
+        Value::Number(Number(n)) if *n >= 0.0 && n.is_finite() => *n,
+        Value::Integer(n) if *n >= 0 => *n as f64,
+        _ => {
+            return Err(explain::error(
+                "R0019",
+                "Argument must be a non-negative number.",
+            ))
+        }
+    };
+    let deadline = Instant::now() + Duration::from_secs_f64(seconds);
+    loop {
+        if interrupt::requested() {
+            return Err(explain::error("R0013", "Interrupted."));
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(Value::Nil);
+        }
+        thread::sleep(remaining.min(Duration::from_millis(50)));
+    }
+}
+
+fn number_arg(value: &Value) -> Result<f64, Error> {
+    match value {
+        Value::Number(Number(n)) if n.is_finite() => Ok(*n),
+        Value::Integer(n) => Ok(*n as f64),
+        _ => Err(explain::error("R0019", "Argument must be a number.")),
+    }
+}
+
+fn integer_arg(value: &Value) -> Result<i64, Error> {
+    match value {
+        Value::Integer(n) => Ok(*n),
+        Value::Number(Number(n)) if n.fract() == 0.0 && n.abs() < 2f64.powi(63) => Ok(*n as i64),
+        _ => Err(explain::error("R0019", "Argument must be an integer.")),
+    }
+}
+
+/// A thread handle or channel id.
+fn index_arg(value: &Value) -> Result<usize, Error> {
+    usize::try_from(integer_arg(value)?)
+        .map_err(|_| explain::error("R0019", "Argument must be a handle."))
+}
+
+fn string_arg(value: &Value) -> Result<&str, Error> {
+    match value {
+        Value::String(s) => Ok(s),
+        _ => Err(explain::error("R0019", "Argument must be a string.")),
+    }
+}
@@ -0,0 +1,74 @@
+use std::{
+    fmt,
+    num::ParseFloatError,
+    ops::{Add, Div, Mul, Neg, Sub},
+    str::FromStr,
+};
+
+/// A Lox number. Lox has a single numeric type, a double, and this is where
+/// its arithmetic and formatting live.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub struct Number(pub f64);
+
+impl Number {
+    /// The number as `tokenize` and `parse` print literals: integers keep a
+    /// `.0`, so `1` prints as `1.0`.
+    pub fn literal(self) -> String {
+        if self.is_integer() {
+            format!("{:.1}", self.0)
+        } else {
+            self.0.to_string()
+        }
+    }
+
+    fn is_integer(self) -> bool {
+        self.0 % 1.0 == 0.0
+    }
+}
+
+/// The number as `print` shows it: integers without a fraction, `-0` with its
+/// sign.
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for Number {
+    type Err = ParseFloatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Number)
+    }
+}
+
+impl From<f64> for Number {
+    fn from(n: f64) -> Self {
+        Number(n)
+    }
+}
+
+impl Neg for Number {
+    type Output = Number;
+
+    fn neg(self) -> Number {
+        Number(-self.0)
+    }
+}
+
+macro_rules! binary_op {
+    ($trait:ident, $method:ident, $op:tt) => {
+        impl $trait for Number {
+            type Output = Number;
+
+            fn $method(self, rhs: Number) -> Number {
+                Number(self.0 $op rhs.0)
+            }
+        }
+    };
+}
+
+binary_op!(Add, add, +);
+binary_op!(Sub, sub, -);
+binary_op!(Mul, mul, *);
+binary_op!(Div, div, /);
@@ -1,33 +1,124 @@
+use std::{cell::Cell, rc::Rc};
+
 use anyhow::{Error, Result};
 
 use crate::{
-    expr::{Assign, Binary, Call, Expr, Grouping, Literal, Unary, Variable},
-    stmt::{Block, Expression, For, Func, If, Print, Return, Stmt, Var, While},
-    token::{Token, TokenValue},
+    explain,
+    expr::{Assign, Binary, Call, Expr, Get, Grouping, Literal, LiteralValue, Unary, Variable},
+    lexer,
+    stmt::{Block, Expression, Func, If, Import, Print, Return, Stmt, Var, While},
+    token::{Span, Token, TokenValue},
 };
 
 pub struct Parser {
     tokens: Vec<Token>,
     errors: Vec<Error>,
+    /// Whether the first error was found at the end of the input, i.e. the
+    /// parser ran out of tokens rather than met a wrong one.
+    first_error_at_end: bool,
     current: usize,
+    /// How many statements and expressions enclose the one being parsed.
+    depth: usize,
+    /// The deepest level, on the scale of `depth`, of the operator chain
+    /// being parsed. Each operator added to a left-nested chain such as
+    /// `1 + 1 + 1` pushes everything already in it a level down.
+    deepest: usize,
+    /// Whether the extensions' syntax is parsed.
+    extensions: bool,
+}
+
+/// Deepest nesting of statements and expressions the parser accepts, counting
+/// each operator, call or property access in a chain as a level. Every pass
+/// over the AST recurses on the native stack, so anything deeper is rejected
+/// here rather than crashing one of them.
+pub const MAX_NESTING: usize = 1000;
+
+/// The types a parameter can be annotated with, as `Value::type_name` names
+/// them.
+pub const TYPES: [&str; 6] = [
+    "boolean", "number", "string", "function", "module", "process",
+];
+
+/// The outcome of parsing a program that may still be being typed.
+pub enum Partial {
+    Complete(Vec<Stmt>),
+    /// The program stops partway through a statement, block or string; more
+    /// input may complete it.
+    Incomplete,
+    /// The program has errors no further input can fix.
+    Invalid(Vec<Error>),
+}
+
+/// Scans and parses `source` as `Parser::parse_partial` does. An unterminated
+/// string is incomplete, any other lexical error invalid.
+pub fn parse_partial(source: &str, extensions: bool) -> Partial {
+    let (tokens, errors) = lexer::scan(source, extensions);
+    if errors
+        .iter()
+        .any(|error| error.code != Some(lexer::UNTERMINATED_STRING))
+    {
+        return Partial::Invalid(
+            errors
+                .iter()
+                .map(|error| match error.code {
+                    Some(code) => explain::error(code, error.to_string()),
+                    None => Error::msg(error.to_string()),
+                })
+                .collect(),
+        );
+    }
+    if !errors.is_empty() {
+        return Partial::Incomplete;
+    }
+    Parser::new(tokens)
+        .with_extensions(extensions)
+        .parse_partial()
 }
 
 impl Parser {
     pub fn parse2(&mut self) -> Result<Vec<Stmt>, Vec<Error>> {
+        let stmts = self.statements();
+        if !self.errors.is_empty() {
+            return Err(std::mem::take(&mut self.errors));
+        }
+        Ok(stmts)
+    }
+
+    /// Like `parse2`, but tells a program that merely stops early apart from
+    /// one that is wrong, for callers reading a program as it is typed.
+    pub fn parse_partial(&mut self) -> Partial {
+        let stmts = self.statements();
+        if self.errors.is_empty() {
+            Partial::Complete(stmts)
+        } else if self.first_error_at_end {
+            Partial::Incomplete
+        } else {
+            Partial::Invalid(std::mem::take(&mut self.errors))
+        }
+    }
+
+    fn statements(&mut self) -> Vec<Stmt> {
         let mut stmts = Vec::new();
         while !self.at_the_end() {
             match self.declaration() {
                 Ok(stmt) => stmts.push(stmt),
-                Err(e) => self.errors.push(e),
+                Err(e) => {
+                    if self.errors.is_empty() {
+                        self.first_error_at_end = self.at_the_end();
+                    }
+                    self.errors.push(e);
+                    self.synchronize();
+                }
             }
         }
-        if !self.errors.is_empty() {
-            return Err(std::mem::take(&mut self.errors));
-        }
-        Ok(stmts)
+        stmts
     }
 
     fn declaration(&mut self) -> Result<Stmt, Error> {
+        self.nested(Self::statement)
+    }
+
+    fn statement(&mut self) -> Result<Stmt, Error> {
         match self.peek().value {
             TokenValue::Print => self.print_stmt(),
             TokenValue::Var => self.var_stmt(),
@@ -37,117 +128,259 @@ impl Parser {
             TokenValue::For => self.for_stmt(),
             TokenValue::Fun => self.func_stmt(),
             TokenValue::Return => self.return_stmt(),
+            TokenValue::Identifier(_) if self.at_import() => self.import_stmt(),
             _ => self.expr_stmt(),
         }
     }
 
+    /// Whether an import statement starts here. `import` scans as an
+    /// identifier so programs can still use it as a name; it's the keyword
+    /// only when a string or a name follows, which can't continue an
+    /// expression statement.
+    fn at_import(&self) -> bool {
+        self.peek().lexeme == "import"
+            && matches!(
+                self.tokens[self.current + 1].value,
+                TokenValue::String(_) | TokenValue::Identifier(_)
+            )
+    }
+
     fn return_stmt(&mut self) -> Result<Stmt, Error> {
+        let start = self.peek().span();
         self.advance();
         let value = if self.peek().value != TokenValue::Semicolon {
             Some(self.expression()?)
         } else {
             None
         };
-        if self.peek().value != TokenValue::Semicolon {
-            return Err(Error::msg(format!(
-                "[line {}] Error at '{}': Expect ';' after value.",
-                self.peek().line,
-                self.peek().lexeme
-            )));
-        }
-        self.advance();
-        let stmt = Stmt::Return(Return { value });
+        self.consume(TokenValue::Semicolon, "P0002", "Expect ';' after value.")?;
+        let stmt = Stmt::Return(Return {
+            span: self.span_from(start),
+            value,
+        });
         Ok(stmt)
     }
 
+    fn import_stmt(&mut self) -> Result<Stmt, Error> {
+        let start = self.peek().span();
+        let mut keyword = self.advance().clone();
+        keyword.value = TokenValue::Import;
+        if !matches!(self.peek().value, TokenValue::String(_)) {
+            return Err(explain::error(
+                "P0024",
+                format!(
+                    "[line {}] Error at '{}': Expect module path after 'import'.",
+                    self.peek().line,
+                    self.peek().lexeme
+                ),
+            ));
+        }
+        let path = self.advance().clone();
+        let alias = if matches!(self.peek().value, TokenValue::Identifier(_))
+            && self.peek().lexeme == "as"
+        {
+            self.advance();
+            if !matches!(self.peek().value, TokenValue::Identifier(_)) {
+                return Err(explain::error(
+                    "P0025",
+                    format!(
+                        "[line {}] Error at '{}': Expect module name after 'as'.",
+                        self.peek().line,
+                        self.peek().lexeme
+                    ),
+                ));
+            }
+            Some(self.advance().clone())
+        } else {
+            None
+        };
+        self.consume(
+            TokenValue::Semicolon,
+            "P0026",
+            "Expect ';' after module path.",
+        )?;
+        Ok(Stmt::Import(Import {
+            span: self.span_from(start),
+            keyword,
+            path,
+            alias,
+            slot: Cell::default(),
+        }))
+    }
+
     fn func_stmt(&mut self) -> Result<Stmt, Error> {
+        let start = self.peek().span();
         self.advance();
-        let name = self.advance().clone();
-        assert_eq!(self.peek().value, TokenValue::LeftParen);
-        self.advance();
+        let name = self.consume_identifier("P0015", "Expect function name.")?;
+        self.consume(
+            TokenValue::LeftParen,
+            "P0016",
+            "Expect '(' after function name.",
+        )?;
         let mut params = Vec::new();
+        let mut types = Vec::new();
         while self.peek().value != TokenValue::RightParen && !self.at_the_end() {
             params.push(self.advance().clone());
+            types.push(self.annotation()?);
             match self.peek().value {
                 TokenValue::Comma => {
                     self.advance();
                 }
                 TokenValue::RightParen => break,
-                _ => {
-                    return Err(Error::msg(format!(
-                        "[line {}] Error at '{}': Expect ')' after paramters.",
-                        self.peek().line,
-                        self.peek().lexeme
-                    )))
-                }
+                _ => return Err(self.error("P0017", "Expect ')' after paramters.")),
             }
         }
         self.advance();
 
-        if self.peek().value != TokenValue::LeftBrace {
-            return Err(Error::msg(format!(
-                "[line {}] Error at '{}': Expect '{{' before function body.",
-                self.peek().line,
-                self.peek().lexeme
-            )));
-        }
-        self.advance();
+        self.consume(
+            TokenValue::LeftBrace,
+            "P0018",
+            "Expect '{' before function body.",
+        )?;
         let mut body = Vec::new();
         while self.peek().value != TokenValue::RightBrace && !self.at_the_end() {
             body.push(self.declaration()?);
         }
         if self.peek().value != TokenValue::RightBrace {
-            return Err(Error::msg(format!(
-                "[line {}] Error at end: Expect '}}' .",
-                self.peek().line
-            )));
+            return Err(explain::error(
+                "P0019",
+                format!("[line {}] Error at end: Expect '}}' .", self.peek().line),
+            ));
         }
         self.advance();
 
-        let stmt = Stmt::Func(Func { name, params, body });
+        let stmt = Stmt::Func(Func {
+            span: self.span_from(start),
+            name,
+            params,
+            types,
+            body: body.into(),
+            slot: Cell::default(),
+        });
         Ok(stmt)
     }
 
-    fn for_stmt(&mut self) -> Result<Stmt, Error> {
+    /// Parses the optional `: type` after a parameter, with extensions.
+    fn annotation(&mut self) -> Result<Option<Token>, Error> {
+        if !self.extensions || self.peek().value != TokenValue::Colon {
+            return Ok(None);
+        }
         self.advance();
+        let ty = self.consume_identifier("P0029", "Expect type after ':'.")?;
+        if !TYPES.contains(&ty.lexeme.as_str()) {
+            return Err(error_at(
+                &ty,
+                "P0030",
+                &format!("Unknown type '{}'.", ty.lexeme),
+            ));
+        }
+        Ok(Some(ty))
+    }
+
+    /// Parses a `for` loop and desugars it into a `while` loop, wrapped in a
+    /// block that scopes the initializer. Every clause is optional; a missing
+    /// condition loops forever.
+    ///
+    /// The initializer's variable is therefore declared once and shared by
+    /// every iteration, as in the reference implementations: closures made in
+    /// the body see its latest value, not the one of the iteration that made
+    /// them. Copying it into a variable declared in the body captures the
+    /// iteration's value instead.
+    fn for_stmt(&mut self) -> Result<Stmt, Error> {
+        let start = self.peek().span();
         self.advance();
-        let init = if self.matches(&[TokenValue::Semicolon]) {
-            None
-        } else {
-            Some(Box::new(self.declaration()?))
+        self.consume(TokenValue::LeftParen, "P0011", "Expect '(' after 'for'.")?;
+        let init = match self.peek().value {
+            TokenValue::Semicolon => {
+                self.advance();
+                None
+            }
+            TokenValue::Var => Some(self.var_stmt()?),
+            _ => Some(self.expr_stmt()?),
         };
 
-        let condition = Box::new(self.expression()?);
-        self.advance();
+        let condition = if self.peek().value != TokenValue::Semicolon {
+            self.expression()?
+        } else {
+            Expr::Literal(Literal {
+                value: LiteralValue::True,
+                span: self.peek().span(),
+            })
+        };
+        self.consume(
+            TokenValue::Semicolon,
+            "P0013",
+            "Expect ';' after loop condition.",
+        )?;
 
         let update = if self.peek().value != TokenValue::RightParen {
-            Some(Box::new(self.expression()?))
+            Some(self.expression()?)
         } else {
             None
         };
-        self.advance();
-
-        let body = Box::new(self.declaration()?);
-        let stmt = Stmt::For(For {
-            init,
-            condition,
-            update,
-            body,
+        self.consume(
+            TokenValue::RightParen,
+            "P0014",
+            "Expect ')' after for clauses.",
+        )?;
+
+        let mut body = self.declaration()?;
+        let span = self.span_from(start);
+        if let Some(update) = update {
+            body = Stmt::Block(Block {
+                span,
+                statements: vec![
+                    body,
+                    Stmt::Expression(Expression {
+                        span: update.span(),
+                        expr: Box::new(update),
+                    }),
+                ],
+            });
+        }
+        let mut stmt = Stmt::While(While {
+            span,
+            condition: Box::new(condition),
+            body: Box::new(body),
         });
+        if let Some(init) = init {
+            stmt = Stmt::Block(Block {
+                span,
+                statements: vec![init, stmt],
+            });
+        }
         Ok(stmt)
     }
 
     fn while_stmt(&mut self) -> Result<Stmt, Error> {
+        let start = self.peek().span();
         self.advance();
+        self.consume(TokenValue::LeftParen, "P0009", "Expect '(' after 'while'.")?;
         let condition = Box::new(self.expression()?);
+        self.consume(
+            TokenValue::RightParen,
+            "P0010",
+            "Expect ')' after condition.",
+        )?;
         let body = Box::new(self.declaration()?);
-        let stmt = Stmt::While(While { condition, body });
+        let stmt = Stmt::While(While {
+            span: self.span_from(start),
+            condition,
+            body,
+        });
         Ok(stmt)
     }
 
     fn if_stmt(&mut self) -> Result<Stmt, Error> {
+        let start = self.peek().span();
         self.advance();
+        self.consume(TokenValue::LeftParen, "P0007", "Expect '(' after 'if'.")?;
         let condition = Box::new(self.expression()?);
+        self.consume(
+            TokenValue::RightParen,
+            "P0008",
+            "Expect ')' after if condition.",
+        )?;
         let then_branch = Box::new(self.declaration()?);
         let else_branch = if self.matches(&[TokenValue::Else]) {
             Some(Box::new(self.declaration()?))
@@ -155,6 +388,7 @@ impl Parser {
             None
         };
         let stmt = Stmt::If(If {
+            span: self.span_from(start),
             condition,
             then_branch,
             else_branch,
@@ -163,34 +397,36 @@ impl Parser {
     }
 
     fn print_stmt(&mut self) -> Result<Stmt, Error> {
+        let start = self.peek().span();
         self.advance();
         let expr = self.expression()?;
-        if self.peek().value != TokenValue::Semicolon {
-            return Err(Error::msg(format!(
-                "[line {}] Error at '{}': Expect ';' after value.",
-                self.peek().line,
-                self.peek().lexeme
-            )));
-        }
-        self.advance();
+        self.consume(TokenValue::Semicolon, "P0002", "Expect ';' after value.")?;
         let stmt = Stmt::Print(Print {
+            span: self.span_from(start),
             expr: Box::new(expr),
         });
         Ok(stmt)
     }
 
     fn expr_stmt(&mut self) -> Result<Stmt, Error> {
+        let start = self.peek().span();
         let expr = self.expression()?;
-        self.advance();
+        self.consume(
+            TokenValue::Semicolon,
+            "P0003",
+            "Expect ';' after expression.",
+        )?;
         let stmt = Stmt::Expression(Expression {
+            span: self.span_from(start),
             expr: Box::new(expr),
         });
         Ok(stmt)
     }
 
     fn var_stmt(&mut self) -> Result<Stmt, Error> {
+        let start = self.peek().span();
         self.advance();
-        let name = self.advance().clone();
+        let name = self.consume_identifier("P0005", "Expect variable name.")?;
         let initializer = if self.peek().value == TokenValue::Equal {
             self.advance();
             let expr = self.expression()?;
@@ -198,25 +434,38 @@ impl Parser {
         } else {
             None
         };
-        self.advance();
-        let stmt = Stmt::Var(Var { name, initializer });
+        self.consume(
+            TokenValue::Semicolon,
+            "P0004",
+            "Expect ';' after variable declaration.",
+        )?;
+        let stmt = Stmt::Var(Var {
+            span: self.span_from(start),
+            name,
+            initializer,
+            slot: Cell::default(),
+        });
         Ok(stmt)
     }
 
     fn block(&mut self) -> Result<Stmt, Error> {
+        let start = self.peek().span();
         self.advance();
         let mut stmts = Vec::new();
         while self.peek().value != TokenValue::RightBrace && !self.at_the_end() {
             stmts.push(self.declaration()?);
         }
         if self.peek().value != TokenValue::RightBrace {
-            return Err(Error::msg(format!(
-                "[line {}] Error at end: Expect '}}' .",
-                self.peek().line
-            )));
+            return Err(explain::error(
+                "P0019",
+                format!("[line {}] Error at end: Expect '}}' .", self.peek().line),
+            ));
         }
         self.advance();
-        let stmt = Stmt::Block(Block { statements: stmts });
+        let stmt = Stmt::Block(Block {
+            span: self.span_from(start),
+            statements: stmts,
+        });
         Ok(stmt)
     }
 }
@@ -226,16 +475,28 @@ impl Parser {
         Parser {
             tokens,
             errors: vec![],
+            first_error_at_end: false,
             current: 0,
+            depth: 0,
+            deepest: 0,
+            extensions: false,
         }
     }
 
+    pub fn with_extensions(mut self, extensions: bool) -> Parser {
+        self.extensions = extensions;
+        self
+    }
+
     pub fn parse(&mut self) -> Result<Vec<Expr>, Vec<Error>> {
         let mut exprs = Vec::new();
         while !self.at_the_end() {
             match self.expression() {
                 Ok(expr) => exprs.push(expr),
-                Err(e) => self.errors.push(e),
+                Err(e) => {
+                    self.errors.push(e);
+                    self.synchronize();
+                }
             }
         }
         if !self.errors.is_empty() {
@@ -244,121 +505,189 @@ impl Parser {
         Ok(exprs)
     }
 
+    /// Parses exactly one expression; any tokens after it are an error.
+    pub fn parse_expression(&mut self) -> Result<Expr, Error> {
+        let expr = self.expression()?;
+        if !self.at_the_end() {
+            return Err(self.error("P0027", "Expect end of expression."));
+        }
+        Ok(expr)
+    }
+
     fn expression(&mut self) -> Result<Expr, Error> {
-        self.assign()
+        self.nested(Self::assign)
     }
 
     fn assign(&mut self) -> Result<Expr, Error> {
-        if self
-            .peek_next()
-            .is_some_and(|token| token.value == TokenValue::Equal)
-        {
-            let name = self.advance().clone();
-            self.advance();
-            let value = self.assign()?;
-            return Ok(Expr::Assign(Assign {
+        let expr = self.pipeline()?;
+        if !self.matches(&[TokenValue::Equal]) {
+            return Ok(expr);
+        }
+        let equals = self.previous().clone();
+        let value = self.nested(Self::assign)?;
+        match expr {
+            Expr::Variable(Variable { name, .. }) => Ok(Expr::Assign(Assign {
+                span: name.span().to(value.span()),
                 name,
                 value: Box::new(value),
-            }));
-        } else {
-            return self.logical_or();
+                local: Cell::default(),
+            })),
+            _ => Err(error_at(&equals, "P0006", "Invalid assignment target.")),
         }
     }
 
+    /// Parses `a ?? b`, left-associative. Only with extensions.
+    fn coalesce(&mut self) -> Result<Expr, Error> {
+        let outer = self.start_chain();
+        let mut left = self.logical_or()?;
+        while self.extensions && self.matches(&[TokenValue::QuestionQuestion]) {
+            self.link()?;
+            let operator = self.previous().clone();
+            let right = self.logical_or()?;
+            left = Expr::Binary(Binary {
+                span: left.span().to(right.span()),
+                left: Box::new(left),
+                operator,
+                right: Box::new(right),
+            });
+        }
+        self.deepest = self.deepest.max(outer);
+        Ok(left)
+    }
+
     fn logical_or(&mut self) -> Result<Expr, Error> {
+        let outer = self.start_chain();
         let mut left = self.logical_and()?;
         while self.matches(&[TokenValue::Or]) {
+            self.link()?;
             let operator = self.previous().clone();
             let right = self.logical_and()?;
             left = Expr::Binary(Binary {
+                span: left.span().to(right.span()),
                 left: Box::new(left),
                 operator,
                 right: Box::new(right),
             });
         }
+        self.deepest = self.deepest.max(outer);
         Ok(left)
     }
 
     fn logical_and(&mut self) -> Result<Expr, Error> {
+        let outer = self.start_chain();
         let mut left = self.equality()?;
         while self.matches(&[TokenValue::And]) {
+            self.link()?;
             let operator = self.previous().clone();
             let right = self.equality()?;
             left = Expr::Binary(Binary {
+                span: left.span().to(right.span()),
                 left: Box::new(left),
                 operator,
                 right: Box::new(right),
             });
         }
+        self.deepest = self.deepest.max(outer);
         Ok(left)
     }
 
     fn equality(&mut self) -> Result<Expr, Error> {
+        let outer = self.start_chain();
         let mut left = self.comparison()?;
         while self.matches(&[TokenValue::BangEqual, TokenValue::EqualEqual]) {
+            self.link()?;
             let operator = self.previous().clone();
             let right = self.comparison()?;
             left = Expr::Binary(Binary {
+                span: left.span().to(right.span()),
                 left: Box::new(left),
                 operator,
                 right: Box::new(right),
             });
         }
+        self.deepest = self.deepest.max(outer);
         Ok(left)
     }
 
     fn comparison(&mut self) -> Result<Expr, Error> {
+        let outer = self.start_chain();
         let mut left = self.term()?;
-        while self.matches(&[
-            TokenValue::Greater,
-            TokenValue::GreaterEqual,
-            TokenValue::Less,
-            TokenValue::LessEqual,
-        ]) {
-            let operator = self.previous().clone();
+        loop {
+            let operator = if self.matches(&[
+                TokenValue::Greater,
+                TokenValue::GreaterEqual,
+                TokenValue::Less,
+                TokenValue::LessEqual,
+            ]) {
+                self.previous().clone()
+            } else if self.extensions
+                && self.peek().lexeme == "in"
+                && matches!(self.peek().value, TokenValue::Identifier(_))
+            {
+                // Not a keyword, so `in` stays usable as a name: an
+                // identifier can't follow an operand anyway. Without
+                // extensions it is only ever a name.
+                let mut operator = self.advance().clone();
+                operator.value = TokenValue::In;
+                operator
+            } else {
+                break;
+            };
+            self.link()?;
             let right = self.term()?;
             left = Expr::Binary(Binary {
+                span: left.span().to(right.span()),
                 left: Box::new(left),
                 operator,
                 right: Box::new(right),
             });
         }
+        self.deepest = self.deepest.max(outer);
         Ok(left)
     }
 
     fn term(&mut self) -> Result<Expr, Error> {
+        let outer = self.start_chain();
         let mut left = self.factory()?;
         while self.matches(&[TokenValue::Plus, TokenValue::Minus]) {
+            self.link()?;
             let operator = self.previous().clone();
             let right = self.factory()?;
             left = Expr::Binary(Binary {
+                span: left.span().to(right.span()),
                 left: Box::new(left),
                 operator,
                 right: Box::new(right),
             });
         }
+        self.deepest = self.deepest.max(outer);
         Ok(left)
     }
 
     fn factory(&mut self) -> Result<Expr, Error> {
+        let outer = self.start_chain();
         let mut left = self.unary()?;
         while self.matches(&[TokenValue::Star, TokenValue::Slash]) {
+            self.link()?;
             let operator = self.previous().clone();
             let right = self.unary()?;
             left = Expr::Binary(Binary {
+                span: left.span().to(right.span()),
                 left: Box::new(left),
                 operator,
                 right: Box::new(right),
             });
         }
+        self.deepest = self.deepest.max(outer);
         Ok(left)
     }
 
     fn unary(&mut self) -> Result<Expr, Error> {
         if self.matches(&[TokenValue::Minus, TokenValue::Bang]) {
             let operator = self.previous().clone();
-            let right = self.unary()?;
+            let right = self.nested(Self::unary)?;
             Ok(Expr::Unary(Unary {
+                span: operator.span().to(right.span()),
                 operator,
                 right: Box::new(right),
             }))
@@ -368,84 +697,176 @@ impl Parser {
     }
 
     fn call(&mut self) -> Result<Expr, Error> {
+        let outer = self.start_chain();
         let mut expr = self.primary()?;
 
-        while self.matches(&[TokenValue::LeftParen]) {
-            expr = self.finish_call(expr)?
+        loop {
+            if self.matches(&[TokenValue::LeftParen]) {
+                self.link()?;
+                expr = self.finish_call(expr)?
+            } else if self.matches(&[TokenValue::Dot])
+                || (self.extensions && self.matches(&[TokenValue::QuestionDot]))
+            {
+                self.link()?;
+                let optional = self.previous().value == TokenValue::QuestionDot;
+                if !matches!(self.peek().value, TokenValue::Identifier(_)) {
+                    return Err(explain::error(
+                        "P0022",
+                        format!(
+                            "[line {}] Error at '{}': Expect property name after '.'.",
+                            self.peek().line,
+                            self.peek().lexeme
+                        ),
+                    ));
+                }
+                let name = self.advance().clone();
+                expr = Expr::Get(Get {
+                    span: expr.span().to(name.span()),
+                    object: Box::new(expr),
+                    name,
+                    optional,
+                });
+            } else {
+                break;
+            }
         }
 
+        self.deepest = self.deepest.max(outer);
+        Ok(expr)
+    }
+
+    /// Parses `x |> f |> g`, left-associative, into the calls `g(f(x))`.
+    /// Each call keeps the `|>` as its closing parenthesis. Only with
+    /// extensions.
+    fn pipeline(&mut self) -> Result<Expr, Error> {
+        let outer = self.start_chain();
+        let mut expr = self.coalesce()?;
+        while self.extensions && self.matches(&[TokenValue::PipeGreater]) {
+            self.link()?;
+            let pipe = self.previous().clone();
+            let callee = self.coalesce()?;
+            expr = Expr::Call(Call {
+                span: expr.span().to(callee.span()),
+                callee: Box::new(callee),
+                args: vec![expr],
+                names: vec![None],
+                paren: pipe,
+            });
+        }
+        self.deepest = self.deepest.max(outer);
         Ok(expr)
     }
 
     fn finish_call(&mut self, callee: Expr) -> Result<Expr, Error> {
         let mut args = vec![];
+        let mut names = vec![];
         if !self.matches(&[TokenValue::RightParen]) {
-            args.push(self.expression()?);
+            args.push(self.argument(&mut names)?);
             while self.matches(&[TokenValue::Comma]) {
                 if args.len() >= 255 {
-                    return Err(Error::msg(format!(
-                        "[line {}] Error: Can't have more than 255 arguments.",
-                        self.peek().line
-                    )));
+                    return Err(self.error("P0021", "Can't have more than 255 arguments."));
                 }
-                args.push(self.expression()?);
+                args.push(self.argument(&mut names)?);
             }
         } else {
             return Ok(Expr::Call(Call {
+                span: callee.span().to(self.previous().span()),
                 callee: Box::new(callee),
                 args,
+                names,
                 paren: self.previous().clone(),
             }));
         }
         if !self.matches(&[TokenValue::RightParen]) {
-            return Err(Error::msg(format!(
-                "[line {}] Error: Expect ')' after arguments.",
-                self.peek().line
-            )));
+            return Err(explain::error(
+                "P0020",
+                format!(
+                    "[line {}] Error: Expect ')' after arguments.",
+                    self.peek().line
+                ),
+            ));
         }
 
         Ok(Expr::Call(Call {
+            span: callee.span().to(self.previous().span()),
             callee: Box::new(callee),
             args,
+            names,
             paren: self.previous().clone(),
         }))
     }
 
+    /// Parses an argument, pushing the parameter name it is passed to, if it
+    /// is written `name: value` with extensions, onto `names`.
+    fn argument(&mut self, names: &mut Vec<Option<Token>>) -> Result<Expr, Error> {
+        let named = self.extensions
+            && matches!(self.peek().value, TokenValue::Identifier(_))
+            && self.tokens[self.current + 1].value == TokenValue::Colon;
+        if named {
+            names.push(Some(self.advance().clone()));
+            self.advance();
+        } else if names.last().is_some_and(Option::is_some) {
+            return Err(self.error("P0031", "Expect named argument after named ones."));
+        } else {
+            names.push(None);
+        }
+        self.expression()
+    }
+
     fn primary(&mut self) -> Result<Expr, Error> {
-        self.advance();
-        match &self.previous().value {
-            TokenValue::Number(n) => Ok(Expr::Literal(Literal::Number(*n))),
-            TokenValue::String(s) => Ok(Expr::Literal(Literal::String(s.clone()))),
-            TokenValue::True => Ok(Expr::Literal(Literal::True)),
-            TokenValue::False => Ok(Expr::Literal(Literal::False)),
-            TokenValue::Nil => Ok(Expr::Literal(Literal::Nil)),
+        let start = self.peek().span();
+        let value = match &self.peek().value {
+            TokenValue::Number(n) => match self.peek().lexeme.parse() {
+                Ok(n) => LiteralValue::Integer(n),
+                Err(_) => LiteralValue::Number(*n),
+            },
+            TokenValue::String(s) => LiteralValue::String(Rc::new(s.clone())),
+            TokenValue::True => LiteralValue::True,
+            TokenValue::False => LiteralValue::False,
+            TokenValue::Nil => LiteralValue::Nil,
 
             TokenValue::LeftParen => {
+                self.advance();
                 let expr = self.expression()?;
-                if self.matches(&[TokenValue::RightParen]) {
+                return if self.matches(&[TokenValue::RightParen]) {
                     Ok(Expr::Grouping(Grouping {
                         expr: Box::new(expr),
+                        span: self.span_from(start),
                     }))
                 } else {
-                    Err(Error::msg(format!(
-                        "[line {}] Error: Expect ')' after expression.",
-                        self.peek().line
-                    )))
-                }
+                    Err(explain::error(
+                        "P0012",
+                        format!(
+                            "[line {}] Error: Expect ')' after expression.",
+                            self.peek().line
+                        ),
+                    ))
+                };
             }
 
-            TokenValue::Identifier => {
-                let name = self.previous().clone();
+            TokenValue::Identifier(_) => {
+                let name = self.advance().clone();
+                return Ok(Expr::Variable(Variable {
+                    span: name.span(),
+                    name,
+                    local: Cell::default(),
+                }));
+            }
 
-                Ok(Expr::Variable(Variable { name }))
+            // There are no classes, so `this` and `super` are always outside
+            // of one.
+            TokenValue::This => {
+                return Err(self.error("P0023", "Can't use 'this' outside of a class."))
+            }
+            TokenValue::Super => {
+                return Err(self.error("P0023", "Can't use 'super' outside of a class."))
             }
 
-            _ => Err(Error::msg(format!(
-                "[line {}] Error at '{}': Expect expression.",
-                self.previous().line,
-                self.previous().lexeme
-            ))),
-        }
+            // Left unconsumed, so the error points at it.
+            _ => return Err(self.error("P0001", "Expect expression.")),
+        };
+        self.advance();
+        Ok(Expr::Literal(Literal { value, span: start }))
     }
 
     fn matches(&mut self, expected: &[TokenValue]) -> bool {
@@ -461,12 +882,92 @@ impl Parser {
         true
     }
 
-    fn peek(&self) -> &Token {
-        &self.tokens[self.current]
+    /// Parses with `parse` one level deeper, failing past `MAX_NESTING`.
+    fn nested<T>(&mut self, parse: fn(&mut Self) -> Result<T, Error>) -> Result<T, Error> {
+        if self.depth == MAX_NESTING {
+            return Err(self.error("P0028", "Too much nesting."));
+        }
+        self.depth += 1;
+        self.deepest = self.deepest.max(self.depth);
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Starts measuring a chain of operators at the current depth, returning
+    /// the deepest level of any chain it is part of, to restore once it ends.
+    fn start_chain(&mut self) -> usize {
+        std::mem::replace(&mut self.deepest, self.depth)
+    }
+
+    /// Adds the operator just consumed to the chain being parsed, one level
+    /// above all of it, failing past `MAX_NESTING`.
+    fn link(&mut self) -> Result<(), Error> {
+        if self.deepest >= MAX_NESTING {
+            return Err(error_at(self.previous(), "P0028", "Too much nesting."));
+        }
+        self.deepest += 1;
+        Ok(())
+    }
+
+    /// Skips to the start of the next statement after an error, so one
+    /// mistake doesn't cascade into a string of unrelated errors.
+    fn synchronize(&mut self) {
+        self.advance();
+        while !self.at_the_end() {
+            if self.previous().value == TokenValue::Semicolon {
+                return;
+            }
+            match self.peek().value {
+                TokenValue::Class
+                | TokenValue::Fun
+                | TokenValue::Var
+                | TokenValue::For
+                | TokenValue::If
+                | TokenValue::While
+                | TokenValue::Print
+                | TokenValue::Return => return,
+                TokenValue::Identifier(_) if self.at_import() => return,
+                _ => {
+                    self.advance();
+                }
+            }
+        }
+    }
+
+    /// Consumes the next token if it is `expected`, or reports `message` at
+    /// it.
+    fn consume(
+        &mut self,
+        expected: TokenValue,
+        code: &'static str,
+        message: &str,
+    ) -> Result<&Token, Error> {
+        if self.peek().value == expected {
+            return Ok(self.advance());
+        }
+        Err(self.error(code, message))
+    }
+
+    fn consume_identifier(&mut self, code: &'static str, message: &str) -> Result<Token, Error> {
+        if matches!(self.peek().value, TokenValue::Identifier(_)) {
+            return Ok(self.advance().clone());
+        }
+        Err(self.error(code, message))
+    }
+
+    /// A parse error of the kind `code` at the next token.
+    fn error(&self, code: &'static str, message: &str) -> Error {
+        error_at(self.peek(), code, message)
+    }
+
+    /// The span from `start` to the end of the last consumed token.
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.previous().span())
     }
 
-    fn peek_next(&self) -> Option<&Token> {
-        self.tokens.get(self.current + 1)
+    fn peek(&self) -> &Token {
+        &self.tokens[self.current]
     }
 
     fn previous(&self) -> &Token {
@@ -484,3 +985,20 @@ impl Parser {
         self.previous()
     }
 }
+
+fn error_at(token: &Token, code: &'static str, message: &str) -> Error {
+    if token.value == TokenValue::Eof {
+        explain::error(
+            code,
+            format!("[line {}] Error at end: {}", token.line, message),
+        )
+    } else {
+        explain::error(
+            code,
+            format!(
+                "[line {}] Error at '{}': {}",
+                token.line, token.lexeme, message
+            ),
+        )
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, IsTerminal},
+};
+
+use crate::{
+    editor::Editor,
+    eval::Value,
+    interrupt, lexer,
+    parser::{parse_partial, Partial},
+    symbol::Symbol,
+    Args,
+};
+
+/// Reads Lox from stdin, or from the file given, and runs each entry in the
+/// same interpreter, so definitions carry over between entries. An entry
+/// that stops partway is continued on the next line. On a terminal, lines
+/// are read with the `Editor`, which keeps the history; from a file or pipe
+/// they are read as they are, without prompts.
+///
+/// The value of an entry that ends in an expression is printed and kept in
+/// `_`, so the next entry can build on it.
+///
+/// `:env` lists the globals defined so far, `:undo` takes back the last
+/// entry's changes to them, and `:reset` those of every entry. Ctrl-C
+/// interrupts the entry running, as it does a script, and the REPL carries on.
+pub fn run(args: &Args) -> i32 {
+    interrupt::install();
+    let mut interpreter = match crate::interpreter(args) {
+        Ok(interpreter) => interpreter,
+        Err(code) => return code,
+    };
+    let extensions = args.has_flag("extensions");
+    let start = interpreter.snapshot();
+    // The state before each entry, latest last.
+    let mut undo = Vec::new();
+    let mut lines = match Input::open(&args.filename) {
+        Some(lines) => lines,
+        None => {
+            eprintln!("Failed to read file {}", args.filename);
+            return 0;
+        }
+    };
+    let mut input = String::new();
+    // `> ` starts an entry, `. ` continues one.
+    while let Some(line) = lines.read_line(if input.is_empty() { "> " } else { ". " }) {
+        // Ctrl-C stops the entry it interrupts, not the session.
+        interrupt::reset();
+        if input.is_empty() {
+            match line.trim() {
+                ":env" => {
+                    for (name, value) in interpreter.globals() {
+                        // The natives are always there; listing them is noise.
+                        if !matches!(value, Value::RustFunction(_)) {
+                            println!("{} = {}", name, value);
+                        }
+                    }
+                    continue;
+                }
+                ":reset" => {
+                    interpreter.restore(&start);
+                    undo.clear();
+                    continue;
+                }
+                ":undo" => {
+                    match undo.pop() {
+                        Some(snapshot) => interpreter.restore(&snapshot),
+                        None => eprintln!("Nothing to undo."),
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        input.push_str(&line);
+        if is_incomplete(&input, extensions) {
+            continue;
+        }
+        let entry = std::mem::take(&mut input);
+        if entry.trim().is_empty() {
+            continue;
+        }
+        let (tokens, errors) = lexer::scan(&entry, extensions);
+        if !errors.is_empty() {
+            for error in errors {
+                crate::explain::report(error);
+            }
+            continue;
+        }
+        undo.push(interpreter.snapshot());
+        if let Ok(Some(value)) = crate::evaluate_program(args, &mut interpreter, tokens) {
+            interpreter.define(Symbol::intern("_"), value);
+        }
+    }
+    if matches!(lines, Input::Terminal(_)) {
+        println!();
+    }
+    0
+}
+
+/// Where the REPL's lines come from.
+enum Input {
+    Terminal(Editor),
+    Lines(Box<dyn BufRead>),
+}
+
+impl Input {
+    /// The file `filename`, or stdin when it is empty. `None` if the file
+    /// can't be opened.
+    fn open(filename: &str) -> Option<Input> {
+        if !filename.is_empty() {
+            let file = File::open(filename).ok()?;
+            return Some(Input::Lines(Box::new(BufReader::new(file))));
+        }
+        if io::stdin().is_terminal() {
+            return Some(Input::Terminal(Editor::open()));
+        }
+        Some(Input::Lines(Box::new(io::stdin().lock())))
+    }
+
+    /// Reads a line, with its newline, prompting with `prompt` on a
+    /// terminal. `None` at the end of input.
+    fn read_line(&mut self, prompt: &str) -> Option<String> {
+        match self {
+            Input::Terminal(editor) => editor.read_line(prompt),
+            Input::Lines(reader) => {
+                let mut line = String::new();
+                match reader.read_line(&mut line) {
+                    Ok(0) | Err(_) => None,
+                    Ok(_) => Some(line),
+                }
+            }
+        }
+    }
+}
+
+/// Whether `input` stops partway through a statement, block or string. An
+/// expression missing only its final `;` is complete, as in `evaluate`.
+fn is_incomplete(input: &str, extensions: bool) -> bool {
+    matches!(parse_partial(input, extensions), Partial::Incomplete)
+        && !matches!(
+            parse_partial(&format!("{input};"), extensions),
+            Partial::Complete(_)
+        )
+}
@@ -0,0 +1,332 @@
+use std::{cell::Cell, collections::HashMap, rc::Rc};
+
+use crate::{
+    explain::Coded,
+    expr::{
+        Assign, Binary, Call, Expr, ExprVisitor, Get, Grouping, Literal, LiteralValue, Unary,
+        Variable,
+    },
+    stmt::{Block, Expression, Func, If, Import, Print, Return, Stmt, StmtVisitor, Var, While},
+    symbol::Symbol,
+    token::TokenValue,
+    Walkable,
+};
+
+/// Where a local variable lives at runtime: `depth` environments up from the
+/// current one, at index `slot` of that environment's locals.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Local {
+    pub depth: usize,
+    pub slot: usize,
+}
+
+/// Something suspicious found while resolving. Warnings are reported but
+/// don't stop the program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub line: usize,
+    /// See `explain::DIAGNOSTICS`.
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[line {}] Warning: {}", self.line, self.message)
+    }
+}
+
+impl Coded for Warning {
+    fn code(&self) -> Option<&'static str> {
+        Some(self.code)
+    }
+}
+
+#[derive(Default)]
+struct Scope {
+    names: HashMap<Symbol, usize>,
+    slots: usize,
+}
+
+/// Statically binds every local variable access to a `(depth, slot)` pair so
+/// the interpreter can skip name lookups. Accesses that don't resolve to a
+/// local are left alone and looked up by name among the globals.
+///
+/// The scopes modelled here must match the environments the interpreter
+/// creates: one per block and one per function call (shared by the
+/// parameters and the body).
+#[derive(Default)]
+pub struct Resolver {
+    scopes: Vec<Scope>,
+    warnings: Vec<Warning>,
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver::default()
+    }
+
+    pub fn resolve(&mut self, stmts: &[Stmt]) {
+        for (i, stmt) in stmts.iter().enumerate() {
+            stmt.walk(self);
+            if let Some(next) = stmts.get(i + 1).filter(|_| always_returns(stmt)) {
+                self.warn(next.line(), "W0002", "Unreachable code.");
+            }
+        }
+    }
+
+    /// The warnings collected by `resolve`, in the order they were found.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    fn resolve_expr(&mut self, expr: &Expr) {
+        expr.walk(self);
+    }
+
+    fn warn(&mut self, line: usize, code: &'static str, message: impl Into<String>) {
+        self.warnings.push(Warning {
+            line,
+            code,
+            message: message.into(),
+        });
+    }
+
+    /// Resolves the condition of an `if` or `while`, warning when it is an
+    /// assignment: almost always a typo for `==`. An assignment meant as
+    /// the condition can be wrapped in another pair of parentheses.
+    ///
+    /// Also warns when the condition is a constant, except for the literal
+    /// `true` of a loop meant to run until it returns.
+    fn resolve_condition(&mut self, condition: &Expr, is_loop: bool) {
+        if let Expr::Assign(assign) = condition {
+            self.warn(
+                assign.name.line,
+                "W0001",
+                "Assignment used as a condition. Did you mean '=='? Wrap it in parentheses if not.",
+            );
+        }
+        let forever = matches!(
+            condition,
+            Expr::Literal(Literal {
+                value: LiteralValue::True,
+                ..
+            })
+        );
+        if let Some(constant) = fold(condition).filter(|_| !(is_loop && forever)) {
+            let message = if constant.is_truthy() {
+                "Condition is always true."
+            } else {
+                "Condition is always false."
+            };
+            self.warn(condition.span().line, "W0003", message);
+        }
+        self.resolve_expr(condition);
+    }
+
+    /// Gives `name` the next slot in the innermost scope, or `None` at the
+    /// top level where variables are globals.
+    fn declare(&mut self, name: Symbol) -> Option<usize> {
+        let scope = self.scopes.last_mut()?;
+        let slot = scope.slots;
+        scope.slots += 1;
+        scope.names.insert(name, slot);
+        Some(slot)
+    }
+
+    fn resolve_local(&self, name: Symbol, local: &Cell<Option<Local>>) {
+        let found = self
+            .scopes
+            .iter()
+            .rev()
+            .enumerate()
+            .find_map(|(depth, scope)| {
+                let slot = *scope.names.get(&name)?;
+                Some(Local { depth, slot })
+            });
+        local.set(found);
+    }
+}
+
+/// The value of an expression that depends on no variables or calls, as far
+/// as it can be worked out without running it.
+#[derive(Debug, Clone, PartialEq)]
+enum Constant {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(Rc<String>),
+}
+
+impl Constant {
+    fn is_truthy(&self) -> bool {
+        !matches!(self, Constant::Nil | Constant::Boolean(false))
+    }
+}
+
+/// Folds `expr` to a constant. Operations that would fail at runtime, and
+/// `and`/`or` on anything but booleans, are left alone.
+fn fold(expr: &Expr) -> Option<Constant> {
+    match expr {
+        Expr::Literal(literal) => Some(match &literal.value {
+            LiteralValue::String(s) => Constant::String(s.clone()),
+            LiteralValue::Number(n) => Constant::Number(n.0),
+            LiteralValue::Integer(n) => Constant::Number(*n as f64),
+            LiteralValue::True => Constant::Boolean(true),
+            LiteralValue::False => Constant::Boolean(false),
+            LiteralValue::Nil => Constant::Nil,
+        }),
+        Expr::Grouping(grouping) => fold(&grouping.expr),
+        Expr::Unary(unary) => match (&unary.operator.value, fold(&unary.right)?) {
+            (TokenValue::Bang, right) => Some(Constant::Boolean(!right.is_truthy())),
+            (TokenValue::Minus, Constant::Number(n)) => Some(Constant::Number(-n)),
+            _ => None,
+        },
+        Expr::Binary(binary) => {
+            let left = fold(&binary.left)?;
+            let right = fold(&binary.right)?;
+            let value = match (&binary.operator.value, left, right) {
+                (TokenValue::EqualEqual, l, r) => Constant::Boolean(l == r),
+                (TokenValue::BangEqual, l, r) => Constant::Boolean(l != r),
+                (TokenValue::And, Constant::Boolean(l), Constant::Boolean(r)) => {
+                    Constant::Boolean(l && r)
+                }
+                (TokenValue::Or, Constant::Boolean(l), Constant::Boolean(r)) => {
+                    Constant::Boolean(l || r)
+                }
+                (TokenValue::Plus, Constant::String(l), Constant::String(r)) => {
+                    Constant::String(Rc::new(format!("{}{}", l, r)))
+                }
+                (operator, Constant::Number(l), Constant::Number(r)) => match operator {
+                    TokenValue::Plus => Constant::Number(l + r),
+                    TokenValue::Minus => Constant::Number(l - r),
+                    TokenValue::Star => Constant::Number(l * r),
+                    TokenValue::Slash => Constant::Number(l / r),
+                    TokenValue::Greater => Constant::Boolean(l > r),
+                    TokenValue::GreaterEqual => Constant::Boolean(l >= r),
+                    TokenValue::Less => Constant::Boolean(l < r),
+                    TokenValue::LessEqual => Constant::Boolean(l <= r),
+                    _ => return None,
+                },
+                _ => return None,
+            };
+            Some(value)
+        }
+        _ => None,
+    }
+}
+
+/// Whether running `stmt` returns on every path, so nothing after it in the
+/// same block can run.
+fn always_returns(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Return(_) => true,
+        Stmt::Block(block) => block.statements.iter().any(always_returns),
+        Stmt::If(If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        }) => always_returns(then_branch) && always_returns(else_branch),
+        _ => false,
+    }
+}
+
+impl ExprVisitor<()> for Resolver {
+    fn visit_literal(&mut self, _expr: &Literal) {}
+
+    fn visit_grouping(&mut self, expr: &Grouping) {
+        self.resolve_expr(&expr.expr);
+    }
+
+    fn visit_unary(&mut self, expr: &Unary) {
+        self.resolve_expr(&expr.right);
+    }
+
+    fn visit_binary(&mut self, expr: &Binary) {
+        self.resolve_expr(&expr.left);
+        self.resolve_expr(&expr.right);
+    }
+
+    fn visit_assign(&mut self, expr: &Assign) {
+        self.resolve_expr(&expr.value);
+        self.resolve_local(expr.name.symbol(), &expr.local);
+    }
+
+    fn visit_variable(&mut self, expr: &Variable) {
+        self.resolve_local(expr.name.symbol(), &expr.local);
+    }
+
+    fn visit_call(&mut self, expr: &Call) {
+        self.resolve_expr(&expr.callee);
+        for arg in &expr.args {
+            self.resolve_expr(arg);
+        }
+    }
+
+    fn visit_get(&mut self, expr: &Get) {
+        self.resolve_expr(&expr.object);
+    }
+}
+
+impl StmtVisitor<()> for Resolver {
+    fn visit_print(&mut self, stmt: &Print) {
+        self.resolve_expr(&stmt.expr);
+    }
+
+    fn visit_expression(&mut self, stmt: &Expression) {
+        self.resolve_expr(&stmt.expr);
+    }
+
+    fn visit_var(&mut self, stmt: &Var) {
+        // The initializer is resolved first so `var a = a;` reads the outer
+        // `a`, just as it does at runtime.
+        if let Some(initializer) = &stmt.initializer {
+            self.resolve_expr(initializer);
+        }
+        stmt.slot.set(self.declare(stmt.name.symbol()));
+    }
+
+    fn visit_block(&mut self, stmt: &Block) {
+        self.scopes.push(Scope::default());
+        self.resolve(&stmt.statements);
+        self.scopes.pop();
+    }
+
+    fn visit_if(&mut self, stmt: &If) {
+        self.resolve_condition(&stmt.condition, false);
+        stmt.then_branch.walk(self);
+        if let Some(else_branch) = &stmt.else_branch {
+            else_branch.walk(self);
+        }
+    }
+
+    fn visit_while(&mut self, stmt: &While) {
+        self.resolve_condition(&stmt.condition, true);
+        stmt.body.walk(self);
+    }
+
+    fn visit_func(&mut self, stmt: &Func) {
+        // Declared before the body so the function can call itself.
+        stmt.slot.set(self.declare(stmt.name.symbol()));
+        self.scopes.push(Scope::default());
+        for param in &stmt.params {
+            self.declare(param.symbol());
+        }
+        self.resolve(&stmt.body);
+        self.scopes.pop();
+    }
+
+    fn visit_return(&mut self, stmt: &Return) {
+        if let Some(value) = &stmt.value {
+            self.resolve_expr(value);
+        }
+    }
+
+    fn visit_import(&mut self, stmt: &Import) {
+        // Unaliased imports define whatever the module exports, which isn't
+        // known until runtime, so those names stay dynamic.
+        if let Some(alias) = &stmt.alias {
+            stmt.slot.set(self.declare(alias.symbol()));
+        }
+    }
+}
@@ -0,0 +1,668 @@
+use std::{cell::Cell, rc::Rc};
+
+use crate::{
+    expr::{Assign, Binary, Call, Expr, Get, Grouping, Literal, LiteralValue, Unary, Variable},
+    format::format,
+    lexer,
+    number::Number,
+    parser::{Parser, TYPES},
+    random::Rng,
+    stmt::{Block, Expression, Func, If, Import, Print, Return, Stmt, Var, While},
+    symbol::Symbol,
+    token::{Span, Token, TokenValue},
+};
+
+/// `roundtrip`: formats `cases` random programs, parses each back and checks
+/// it is the program that was formatted, spans aside. Prints the first
+/// program that doesn't survive the trip, shrunk to the statements and
+/// expressions the failure needs, with the seed to reproduce it.
+pub fn run(cases: usize, seed: u64) -> i32 {
+    let mut generator = Generator {
+        rng: Rng::new(seed),
+        depth: 0,
+    };
+    for case in 0..cases {
+        let stmts = generator.program();
+        if check(&stmts).is_ok() {
+            continue;
+        }
+        let stmts = shrink(stmts);
+        let Err(failure) = check(&stmts) else {
+            unreachable!("shrinking keeps the program failing");
+        };
+        println!("FAIL case {} of seed {}", case, seed);
+        println!("formatted:\n{}", format(&stmts));
+        println!("generated:");
+        for stmt in &stmts {
+            println!("{}", stmt);
+        }
+        println!("{}", failure);
+        return 1;
+    }
+    println!("{} programs round-tripped", cases);
+    0
+}
+
+/// Formats `stmts` and parses them back, describing how the result differs
+/// if it isn't the same program.
+fn check(stmts: &[Stmt]) -> Result<(), String> {
+    let source = format(stmts);
+    let (tokens, errors) = lexer::scan(&source, true);
+    let reparsed = if errors.is_empty() {
+        Parser::new(tokens)
+            .with_extensions(true)
+            .parse2()
+            .map_err(|errors| errors.iter().map(|e| e.to_string()).collect::<Vec<_>>())
+    } else {
+        Err(errors.iter().map(|e| e.to_string()).collect())
+    };
+    match reparsed {
+        Ok(reparsed) if same_stmts(stmts, &reparsed) => Ok(()),
+        Ok(reparsed) => Err(format!(
+            "parsed back as a different program:\n{}",
+            reparsed
+                .iter()
+                .map(|stmt| stmt.to_string())
+                .collect::<Vec<_>>()
+                .join("\n")
+        )),
+        Err(errors) => Err(format!("failed to parse back:\n{}", errors.join("\n"))),
+    }
+}
+
+/// Shrinks a program that fails `check` by applying, over and over, the
+/// first simplification it still fails under, until there is none. Each one
+/// makes the program smaller, so this ends.
+fn shrink(mut stmts: Vec<Stmt>) -> Vec<Stmt> {
+    let mut site = 0;
+    loop {
+        let mut candidate = stmts.clone();
+        if !simplify_stmts(&mut candidate, &mut site.clone()) {
+            return stmts;
+        }
+        if check(&candidate).is_err() {
+            stmts = candidate;
+            site = 0;
+        } else {
+            site += 1;
+        }
+    }
+}
+
+/// Applies the `site`-th simplification, counting in source order, to a
+/// list of statements: dropping one, putting the statements nested in one in
+/// its place, or simplifying inside one. Returns whether there was that
+/// many; `site` is left counting down the rest.
+fn simplify_stmts(stmts: &mut Vec<Stmt>, site: &mut usize) -> bool {
+    for i in 0..stmts.len() {
+        if take(site) {
+            stmts.remove(i);
+            return true;
+        }
+        for nested in nested(&stmts[i]) {
+            if take(site) {
+                stmts.splice(i..=i, nested);
+                return true;
+            }
+        }
+        if simplify_stmt(&mut stmts[i], site) {
+            return true;
+        }
+    }
+    false
+}
+
+/// The statements nested in a statement, one list for each body or branch.
+fn nested(stmt: &Stmt) -> Vec<Vec<Stmt>> {
+    let body = |stmt: &Stmt| match stmt {
+        Stmt::Block(block) => block.statements.clone(),
+        stmt => vec![stmt.clone()],
+    };
+    match stmt {
+        Stmt::Block(block) => vec![block.statements.clone()],
+        Stmt::If(stmt) => std::iter::once(&*stmt.then_branch)
+            .chain(stmt.else_branch.as_deref())
+            .map(body)
+            .collect(),
+        Stmt::While(stmt) => vec![body(&stmt.body)],
+        Stmt::Func(func) => vec![func.body.to_vec()],
+        _ => Vec::new(),
+    }
+}
+
+/// Whether the simplification to apply is this one, counting it off if not.
+fn take(site: &mut usize) -> bool {
+    if *site == 0 {
+        return true;
+    }
+    *site -= 1;
+    false
+}
+
+fn simplify_stmt(stmt: &mut Stmt, site: &mut usize) -> bool {
+    match stmt {
+        Stmt::Print(Print { expr, .. }) | Stmt::Expression(Expression { expr, .. }) => {
+            simplify_expr(expr, ASSIGNMENT, site)
+        }
+        Stmt::Var(var) => match &mut var.initializer {
+            Some(_) if take(site) => {
+                var.initializer = None;
+                true
+            }
+            Some(initializer) => simplify_expr(initializer, ASSIGNMENT, site),
+            None => false,
+        },
+        Stmt::Return(ret) => match &mut ret.value {
+            Some(_) if take(site) => {
+                ret.value = None;
+                true
+            }
+            Some(value) => simplify_expr(value, ASSIGNMENT, site),
+            None => false,
+        },
+        Stmt::Block(block) => simplify_stmts(&mut block.statements, site),
+        Stmt::If(stmt) => {
+            if simplify_expr(&mut stmt.condition, ASSIGNMENT, site)
+                || simplify_branch(&mut stmt.then_branch, site)
+            {
+                return true;
+            }
+            match &mut stmt.else_branch {
+                Some(_) if take(site) => {
+                    stmt.else_branch = None;
+                    true
+                }
+                Some(else_branch) => simplify_branch(else_branch, site),
+                None => false,
+            }
+        }
+        Stmt::While(stmt) => {
+            simplify_expr(&mut stmt.condition, ASSIGNMENT, site)
+                || simplify_branch(&mut stmt.body, site)
+        }
+        Stmt::Func(func) => {
+            let mut body = func.body.to_vec();
+            let simplified = simplify_stmts(&mut body, site);
+            func.body = Rc::from(body);
+            simplified
+        }
+        Stmt::Import(import) => {
+            let simplified = import.alias.is_some() && take(site);
+            if simplified {
+                import.alias = None;
+            }
+            simplified
+        }
+    }
+}
+
+/// Simplifies the body of an `if` or `while`, which can also be emptied.
+/// An empty block is what is left, as an `if` with an `else` needs a block.
+fn simplify_branch(stmt: &mut Stmt, site: &mut usize) -> bool {
+    let empty = matches!(stmt, Stmt::Block(block) if block.statements.is_empty());
+    if !empty && take(site) {
+        *stmt = Stmt::Block(Block {
+            span: Span::default(),
+            statements: Vec::new(),
+        });
+        return true;
+    }
+    simplify_stmt(stmt, site)
+}
+
+/// Simplifies an expression in a position that needs one binding at least
+/// as tightly as `precedence`: replacing it with `nil`, which fits anywhere,
+/// or with one of its operands, dropping an argument, or simplifying inside
+/// it.
+fn simplify_expr(expr: &mut Expr, precedence: usize, site: &mut usize) -> bool {
+    let nil = matches!(
+        expr,
+        Expr::Literal(Literal {
+            value: LiteralValue::Nil,
+            ..
+        })
+    );
+    if !nil && take(site) {
+        *expr = Expr::Literal(Literal {
+            value: LiteralValue::Nil,
+            span: Span::default(),
+        });
+        return true;
+    }
+    for operand in operands(expr, precedence) {
+        if take(site) {
+            // An operand binding too loosely for the position is grouped.
+            *expr = if level(&operand) >= precedence {
+                operand
+            } else {
+                Expr::Grouping(Grouping {
+                    expr: Box::new(operand),
+                    span: Span::default(),
+                })
+            };
+            return true;
+        }
+    }
+    match expr {
+        Expr::Literal(_) | Expr::Variable(_) => false,
+        Expr::Grouping(grouping) => simplify_expr(&mut grouping.expr, ASSIGNMENT, site),
+        Expr::Unary(unary) => simplify_expr(&mut unary.right, UNARY, site),
+        Expr::Binary(binary) => {
+            let level = binary_level(&binary.operator.value);
+            simplify_expr(&mut binary.left, level, site)
+                || simplify_expr(&mut binary.right, level + 1, site)
+        }
+        Expr::Assign(assign) => simplify_expr(&mut assign.value, ASSIGNMENT, site),
+        Expr::Get(get) => simplify_expr(&mut get.object, CALL, site),
+        Expr::Call(call) => {
+            if simplify_expr(&mut call.callee, CALL, site) {
+                return true;
+            }
+            for i in 0..call.args.len() {
+                if take(site) {
+                    call.args.remove(i);
+                    call.names.remove(i);
+                    return true;
+                }
+                if simplify_expr(&mut call.args[i], ASSIGNMENT, site) {
+                    return true;
+                }
+            }
+            false
+        }
+    }
+}
+
+/// The operands an expression in a position needing `precedence` could be
+/// replaced with when shrinking. A grouping's operand is one only if it can
+/// do without the grouping.
+fn operands(expr: &Expr, precedence: usize) -> Vec<Expr> {
+    match expr {
+        Expr::Literal(_) | Expr::Variable(_) => Vec::new(),
+        Expr::Grouping(grouping) if level(&grouping.expr) >= precedence => {
+            vec![(*grouping.expr).clone()]
+        }
+        Expr::Grouping(_) => Vec::new(),
+        Expr::Unary(unary) => vec![(*unary.right).clone()],
+        Expr::Binary(binary) => vec![(*binary.left).clone(), (*binary.right).clone()],
+        Expr::Assign(assign) => vec![(*assign.value).clone()],
+        Expr::Get(get) => vec![(*get.object).clone()],
+        Expr::Call(call) => std::iter::once(&*call.callee)
+            .chain(&call.args)
+            .cloned()
+            .collect(),
+    }
+}
+
+/// The precedence level an expression binds at, as the generator numbers
+/// them.
+fn level(expr: &Expr) -> usize {
+    match expr {
+        Expr::Assign(_) => ASSIGNMENT,
+        Expr::Binary(binary) => binary_level(&binary.operator.value),
+        Expr::Unary(_) => UNARY,
+        Expr::Call(_) | Expr::Get(_) => CALL,
+        Expr::Literal(_) | Expr::Variable(_) | Expr::Grouping(_) => PRIMARY,
+    }
+}
+
+/// The precedence level of a binary operator.
+fn binary_level(operator: &TokenValue) -> usize {
+    OPERATORS
+        .iter()
+        .position(|operators| operators.iter().any(|(value, _)| value == operator))
+        .map_or(PRIMARY, |i| i + 1)
+}
+
+/// Names for generated variables, functions and properties.
+const NAMES: [&str; 6] = ["a", "b", "count", "f", "_tmp", "x2"];
+
+/// Binary operators, loosest-binding first, grouped by precedence level.
+const OPERATORS: [&[(TokenValue, &str)]; 7] = [
+    &[(TokenValue::QuestionQuestion, "??")],
+    &[(TokenValue::Or, "or")],
+    &[(TokenValue::And, "and")],
+    &[
+        (TokenValue::EqualEqual, "=="),
+        (TokenValue::BangEqual, "!="),
+    ],
+    &[
+        (TokenValue::Greater, ">"),
+        (TokenValue::GreaterEqual, ">="),
+        (TokenValue::Less, "<"),
+        (TokenValue::LessEqual, "<="),
+        (TokenValue::In, "in"),
+    ],
+    &[(TokenValue::Plus, "+"), (TokenValue::Minus, "-")],
+    &[(TokenValue::Star, "*"), (TokenValue::Slash, "/")],
+];
+
+// Precedence levels besides the binary operators', which are 1 to 7.
+const ASSIGNMENT: usize = 0;
+const UNARY: usize = 8;
+const CALL: usize = 9;
+const PRIMARY: usize = 10;
+
+/// Beyond this depth only leaves are generated, so programs stay small.
+const MAX_DEPTH: usize = 5;
+
+/// Generates random programs that the parser could have produced, with
+/// default spans. Every operand binds at least as tightly as its position
+/// needs, wrapped in a grouping where it doesn't, as the parser would have
+/// left it.
+struct Generator {
+    rng: Rng,
+    depth: usize,
+}
+
+impl Generator {
+    fn program(&mut self) -> Vec<Stmt> {
+        let count = self.below(5) + 1;
+        (0..count).map(|_| self.stmt()).collect()
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        self.rng.next_in(0, n as i64 - 1) as usize
+    }
+
+    fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    fn token(&self, value: TokenValue, lexeme: &str) -> Token {
+        Token::new(value, lexeme.to_string(), 0, 0)
+    }
+
+    fn name(&mut self) -> Token {
+        let name = NAMES[self.below(NAMES.len())];
+        self.token(TokenValue::Identifier(Symbol::intern(name)), name)
+    }
+
+    /// A parameter's type, or none, as often.
+    fn annotation(&mut self) -> Option<Token> {
+        let ty = TYPES[self.below(TYPES.len())];
+        self.chance(50)
+            .then(|| self.token(TokenValue::Identifier(Symbol::intern(ty)), ty))
+    }
+
+    fn stmt(&mut self) -> Stmt {
+        self.depth += 1;
+        let leaf = self.depth > MAX_DEPTH;
+        let span = Span::default();
+        let stmt = match self.below(if leaf { 4 } else { 10 }) {
+            0 => Stmt::Print(Print {
+                span,
+                expr: Box::new(self.expr(ASSIGNMENT)),
+            }),
+            1 => Stmt::Expression(Expression {
+                span,
+                expr: Box::new(self.expr(ASSIGNMENT)),
+            }),
+            2 => Stmt::Var(Var {
+                span,
+                name: self.name(),
+                initializer: self.chance(70).then(|| Box::new(self.expr(ASSIGNMENT))),
+                slot: Cell::default(),
+            }),
+            3 => Stmt::Return(Return {
+                span,
+                value: self.chance(70).then(|| self.expr(ASSIGNMENT)),
+            }),
+            4 => Stmt::Block(self.block()),
+            5 => {
+                let condition = Box::new(self.expr(ASSIGNMENT));
+                let else_branch = self.chance(50).then(|| Box::new(self.stmt()));
+                // With an `else`, a bare `if` at the end of the `then` branch
+                // would take the `else` as its own, so the branch is a block.
+                let then_branch = match else_branch {
+                    Some(_) => Stmt::Block(self.block()),
+                    None => self.stmt(),
+                };
+                Stmt::If(If {
+                    span,
+                    condition,
+                    then_branch: Box::new(then_branch),
+                    else_branch,
+                })
+            }
+            6 => Stmt::While(While {
+                span,
+                condition: Box::new(self.expr(ASSIGNMENT)),
+                body: Box::new(self.stmt()),
+            }),
+            7 | 8 => {
+                let count = self.below(4);
+                Stmt::Func(Func {
+                    span,
+                    name: self.name(),
+                    params: (0..count).map(|_| self.name()).collect(),
+                    types: (0..count).map(|_| self.annotation()).collect(),
+                    body: Rc::from(self.block().statements),
+                    slot: Cell::default(),
+                })
+            }
+            _ => Stmt::Import(Import {
+                span,
+                keyword: self.token(TokenValue::Import, "import"),
+                path: self.token(TokenValue::String("lib.lox".to_string()), "\"lib.lox\""),
+                alias: self.chance(50).then(|| self.name()),
+                slot: Cell::default(),
+            }),
+        };
+        self.depth -= 1;
+        stmt
+    }
+
+    fn block(&mut self) -> Block {
+        let count = self.below(4);
+        Block {
+            span: Span::default(),
+            statements: (0..count).map(|_| self.stmt()).collect(),
+        }
+    }
+
+    /// An expression that binds at least as tightly as `precedence`.
+    fn expr(&mut self, precedence: usize) -> Expr {
+        self.depth += 1;
+        let level = if self.depth > MAX_DEPTH {
+            PRIMARY
+        } else {
+            self.below(PRIMARY + 1)
+        };
+        let expr = if level < precedence {
+            Expr::Grouping(Grouping {
+                expr: Box::new(self.expr_at(level)),
+                span: Span::default(),
+            })
+        } else {
+            self.expr_at(level)
+        };
+        self.depth -= 1;
+        expr
+    }
+
+    /// An expression whose outermost operator binds at `level`.
+    fn expr_at(&mut self, level: usize) -> Expr {
+        let span = Span::default();
+        match level {
+            ASSIGNMENT => Expr::Assign(Assign {
+                name: self.name(),
+                value: Box::new(self.expr(ASSIGNMENT)),
+                local: Cell::default(),
+                span,
+            }),
+            UNARY => {
+                let (value, lexeme) = if self.chance(50) {
+                    (TokenValue::Minus, "-")
+                } else {
+                    (TokenValue::Bang, "!")
+                };
+                Expr::Unary(Unary {
+                    operator: self.token(value, lexeme),
+                    right: Box::new(self.expr(UNARY)),
+                    span,
+                })
+            }
+            CALL if self.chance(60) => {
+                let count = self.below(4);
+                let positional = self.below(count + 1);
+                Expr::Call(Call {
+                    callee: Box::new(self.expr(CALL)),
+                    args: (0..count).map(|_| self.expr(ASSIGNMENT)).collect(),
+                    names: (0..count)
+                        .map(|i| (i >= positional).then(|| self.name()))
+                        .collect(),
+                    paren: self.token(TokenValue::RightParen, ")"),
+                    span,
+                })
+            }
+            CALL => Expr::Get(Get {
+                object: Box::new(self.expr(CALL)),
+                name: self.name(),
+                optional: self.chance(30),
+                span,
+            }),
+            PRIMARY => self.primary(),
+            _ => {
+                let operators = OPERATORS[level - 1];
+                let (value, lexeme) = &operators[self.below(operators.len())];
+                // Left-associative: only the right operand needs to bind
+                // more tightly.
+                Expr::Binary(Binary {
+                    left: Box::new(self.expr(level)),
+                    operator: self.token(value.clone(), lexeme),
+                    right: Box::new(self.expr(level + 1)),
+                    span,
+                })
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Expr {
+        let value = match self.below(7) {
+            0 => LiteralValue::Integer(self.rng.next_in(0, 1000)),
+            1 => LiteralValue::Number(Number(self.rng.next_in(0, 4000) as f64 / 8.0)),
+            2 => LiteralValue::String(Rc::new(NAMES[self.below(NAMES.len())].repeat(2))),
+            3 => LiteralValue::True,
+            4 => LiteralValue::False,
+            5 => LiteralValue::Nil,
+            _ => {
+                return Expr::Variable(Variable {
+                    name: self.name(),
+                    local: Cell::default(),
+                    span: Span::default(),
+                })
+            }
+        };
+        // `1.0` is parsed as a number and `1` as an integer, so whole numbers
+        // are generated as integers.
+        let value = match value {
+            LiteralValue::Number(n) if n.0.fract() == 0.0 => LiteralValue::Integer(n.0 as i64),
+            value => value,
+        };
+        Expr::Literal(Literal {
+            value,
+            span: Span::default(),
+        })
+    }
+}
+
+fn same_stmts(a: &[Stmt], b: &[Stmt]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same_stmt(a, b))
+}
+
+fn same_token(a: &Token, b: &Token) -> bool {
+    a.value == b.value && a.lexeme == b.lexeme
+}
+
+fn same_option<T>(a: Option<&T>, b: Option<&T>, same: impl Fn(&T, &T) -> bool) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => same(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Structural equality, ignoring spans and token positions.
+fn same_stmt(a: &Stmt, b: &Stmt) -> bool {
+    match (a, b) {
+        (Stmt::Print(a), Stmt::Print(b)) => same_expr(&a.expr, &b.expr),
+        (Stmt::Expression(a), Stmt::Expression(b)) => same_expr(&a.expr, &b.expr),
+        (Stmt::Var(a), Stmt::Var(b)) => {
+            same_token(&a.name, &b.name)
+                && same_option(
+                    a.initializer.as_deref(),
+                    b.initializer.as_deref(),
+                    same_expr,
+                )
+        }
+        (Stmt::Block(a), Stmt::Block(b)) => same_stmts(&a.statements, &b.statements),
+        (Stmt::If(a), Stmt::If(b)) => {
+            same_expr(&a.condition, &b.condition)
+                && same_stmt(&a.then_branch, &b.then_branch)
+                && same_option(
+                    a.else_branch.as_deref(),
+                    b.else_branch.as_deref(),
+                    same_stmt,
+                )
+        }
+        (Stmt::While(a), Stmt::While(b)) => {
+            same_expr(&a.condition, &b.condition) && same_stmt(&a.body, &b.body)
+        }
+        (Stmt::Func(a), Stmt::Func(b)) => {
+            same_token(&a.name, &b.name)
+                && a.params.len() == b.params.len()
+                && a.params
+                    .iter()
+                    .zip(&b.params)
+                    .all(|(a, b)| same_token(a, b))
+                && a.types
+                    .iter()
+                    .zip(&b.types)
+                    .all(|(a, b)| same_option(a.as_ref(), b.as_ref(), same_token))
+                && same_stmts(&a.body, &b.body)
+        }
+        (Stmt::Return(a), Stmt::Return(b)) => {
+            same_option(a.value.as_ref(), b.value.as_ref(), same_expr)
+        }
+        (Stmt::Import(a), Stmt::Import(b)) => {
+            same_token(&a.path, &b.path)
+                && same_option(a.alias.as_ref(), b.alias.as_ref(), same_token)
+        }
+        _ => false,
+    }
+}
+
+fn same_expr(a: &Expr, b: &Expr) -> bool {
+    match (a, b) {
+        (Expr::Literal(a), Expr::Literal(b)) => a.value == b.value,
+        (Expr::Grouping(a), Expr::Grouping(b)) => same_expr(&a.expr, &b.expr),
+        (Expr::Unary(a), Expr::Unary(b)) => {
+            same_token(&a.operator, &b.operator) && same_expr(&a.right, &b.right)
+        }
+        (Expr::Binary(a), Expr::Binary(b)) => {
+            same_expr(&a.left, &b.left)
+                && same_token(&a.operator, &b.operator)
+                && same_expr(&a.right, &b.right)
+        }
+        (Expr::Assign(a), Expr::Assign(b)) => {
+            same_token(&a.name, &b.name) && same_expr(&a.value, &b.value)
+        }
+        (Expr::Variable(a), Expr::Variable(b)) => same_token(&a.name, &b.name),
+        (Expr::Call(a), Expr::Call(b)) => {
+            same_expr(&a.callee, &b.callee)
+                && a.args.len() == b.args.len()
+                && a.args.iter().zip(&b.args).all(|(a, b)| same_expr(a, b))
+                && a.names
+                    .iter()
+                    .zip(&b.names)
+                    .all(|(a, b)| same_option(a.as_ref(), b.as_ref(), same_token))
+        }
+        (Expr::Get(a), Expr::Get(b)) => {
+            same_expr(&a.object, &b.object)
+                && same_token(&a.name, &b.name)
+                && a.optional == b.optional
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::VecDeque,
+    rc::Rc,
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::{Error, Result};
+
+use crate::{eval::Value, explain, interrupt, number::Number};
+
+/// A value that can cross threads. Functions and modules hold `Rc`s into
+/// their interpreter, so only plain data can be sent.
+#[derive(Debug)]
+pub enum Message {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    Integer(i64),
+    String(String),
+}
+
+impl Message {
+    pub fn from_value(value: &Value) -> Result<Message, Error> {
+        match value {
+            Value::Nil | Value::Uninitialized => Ok(Message::Nil),
+            Value::Boolean(b) => Ok(Message::Boolean(*b)),
+            Value::Number(Number(n)) => Ok(Message::Number(*n)),
+            Value::Integer(n) => Ok(Message::Integer(*n)),
+            Value::String(s) => Ok(Message::String(s.to_string())),
+            value => Err(explain::error(
+                "R0029",
+                format!("Can't send a {} between threads.", value.type_name()),
+            )),
+        }
+    }
+
+    pub fn into_value(self) -> Value {
+        match self {
+            Message::Nil => Value::Nil,
+            Message::Boolean(b) => Value::Boolean(b),
+            Message::Number(n) => Value::Number(Number(n)),
+            Message::Integer(n) => Value::Integer(n),
+            Message::String(s) => Value::String(Rc::new(s)),
+        }
+    }
+}
+
+/// What a spawned function returned, or the first line of its error.
+type Outcome = Result<Message, String>;
+
+/// Threads started by `spawn`, indexed by handle. A slot is emptied once the
+/// thread is joined.
+static THREADS: Mutex<Vec<Option<JoinHandle<Outcome>>>> = Mutex::new(Vec::new());
+
+/// Channels created by `channel`, indexed by id. They are shared by every
+/// interpreter in the process, so their ids can be handed to spawned
+/// functions.
+static CHANNELS: Mutex<Vec<Arc<Channel>>> = Mutex::new(Vec::new());
+
+#[derive(Default)]
+struct Channel {
+    queue: Mutex<VecDeque<Message>>,
+    ready: Condvar,
+}
+
+/// Runs `run` on a new thread and returns its handle.
+pub fn start(run: impl FnOnce() -> Outcome + Send + 'static) -> Result<usize, Error> {
+    let handle = thread::Builder::new()
+        .stack_size(crate::STACK_SIZE)
+        .spawn(run)
+        .map_err(|e| Error::msg(format!("Could not start thread: {}.", e)))?;
+    let mut threads = THREADS.lock().unwrap();
+    threads.push(Some(handle));
+    Ok(threads.len() - 1)
+}
+
+/// Waits for the thread behind `handle` to finish and returns what its
+/// function returned. Ctrl-C stops waiting with an "Interrupted." error.
+pub fn join(handle: usize) -> Result<Message, Error> {
+    let thread = THREADS
+        .lock()
+        .unwrap()
+        .get_mut(handle)
+        .ok_or_else(|| explain::error("R0032", "Invalid thread handle."))?
+        .take()
+        .ok_or_else(|| explain::error("R0033", "Thread has already been joined."))?;
+    while !thread.is_finished() {
+        if interrupt::requested() {
+            return Err(explain::error("R0013", "Interrupted."));
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    match thread.join() {
+        Ok(Ok(message)) => Ok(message),
+        Ok(Err(error)) => Err(explain::error(
+            "R0030",
+            format!("Spawned thread failed: {}", error),
+        )),
+        Err(_) => Err(Error::msg("Spawned thread panicked.")),
+    }
+}
+
+/// Creates a channel and returns its id.
+pub fn channel() -> usize {
+    let mut channels = CHANNELS.lock().unwrap();
+    channels.push(Arc::default());
+    channels.len() - 1
+}
+
+pub fn send(id: usize, message: Message) -> Result<(), Error> {
+    let channel = lookup(id)?;
+    channel.queue.lock().unwrap().push_back(message);
+    channel.ready.notify_one();
+    Ok(())
+}
+
+/// Takes the oldest message off a channel, waiting for one if it is empty.
+/// Ctrl-C stops waiting with an "Interrupted." error.
+pub fn recv(id: usize) -> Result<Message, Error> {
+    let channel = lookup(id)?;
+    let mut queue = channel.queue.lock().unwrap();
+    loop {
+        if let Some(message) = queue.pop_front() {
+            return Ok(message);
+        }
+        if interrupt::requested() {
+            return Err(explain::error("R0013", "Interrupted."));
+        }
+        queue = channel
+            .ready
+            .wait_timeout(queue, Duration::from_millis(50))
+            .unwrap()
+            .0;
+    }
+}
+
+fn lookup(id: usize) -> Result<Arc<Channel>, Error> {
+    CHANNELS
+        .lock()
+        .unwrap()
+        .get(id)
+        .cloned()
+        .ok_or_else(|| explain::error("R0031", "Invalid channel."))
+}
@@ -0,0 +1,93 @@
+use crate::{
+    explain, lexer,
+    parser::Parser,
+    stmt::Stmt,
+    walk::{walk_children, Node, NodeCount, Visitor},
+};
+
+/// Size and shape of a program.
+#[derive(Debug, Default)]
+struct Metrics {
+    statements: usize,
+    expressions: usize,
+    /// How deeply statements nest in blocks, bodies and branches; top-level
+    /// statements are at depth 1.
+    max_depth: usize,
+    depth: usize,
+    functions: usize,
+    /// The function with the most AST nodes, and how many.
+    largest: Option<(String, usize)>,
+}
+
+impl Visitor for Metrics {
+    fn visit(&mut self, node: Node) {
+        let Node::Stmt(stmt) = node else {
+            self.expressions += 1;
+            walk_children(self, node);
+            return;
+        };
+        self.statements += 1;
+        if let Stmt::Func(func) = stmt {
+            self.functions += 1;
+            let mut count = NodeCount::default();
+            count.visit_all(&func.body);
+            let size = count.counts.values().sum();
+            if self
+                .largest
+                .as_ref()
+                .is_none_or(|(_, largest)| size > *largest)
+            {
+                self.largest = Some((func.name.lexeme.clone(), size));
+            }
+        }
+        self.depth += 1;
+        self.max_depth = self.max_depth.max(self.depth);
+        walk_children(self, node);
+        self.depth -= 1;
+    }
+}
+
+/// `stats <file>`: prints metrics of the program, then how many AST nodes of
+/// each kind it has, most frequent first.
+pub fn run(source: &str, extensions: bool) -> i32 {
+    let (tokens, errors) = lexer::scan(source, extensions);
+    if !errors.is_empty() {
+        for error in errors {
+            explain::report(error);
+        }
+        return 65;
+    }
+    // Not counting the end of file.
+    let token_count = tokens.len() - 1;
+    let stmts = match Parser::new(tokens).with_extensions(extensions).parse2() {
+        Ok(stmts) => stmts,
+        Err(errors) => {
+            for error in errors {
+                explain::report(error);
+            }
+            return 65;
+        }
+    };
+
+    let mut metrics = Metrics::default();
+    metrics.visit_all(&stmts);
+    println!("{:<18} {}", "tokens", token_count);
+    println!("{:<18} {}", "statements", metrics.statements);
+    println!("{:<18} {}", "expressions", metrics.expressions);
+    println!("{:<18} {}", "max nesting depth", metrics.max_depth);
+    println!("{:<18} {}", "functions", metrics.functions);
+    if let Some((name, size)) = &metrics.largest {
+        println!("{:<18} {} ({} nodes)", "largest function", name, size);
+    }
+    println!();
+
+    let mut count = NodeCount::default();
+    count.visit_all(&stmts);
+    let mut counts = count.counts.into_iter().collect::<Vec<_>>();
+    counts.sort_by(|(a_kind, a), (b_kind, b)| b.cmp(a).then(a_kind.cmp(b_kind)));
+    println!("{:<12} {:>6}", "node", "count");
+    for (kind, count) in counts {
+        println!("{:<12} {:>6}", kind, count);
+    }
+    0
+}
@@ -1,5 +1,10 @@
+use std::{cell::Cell, rc::Rc};
 
-use crate::{expr::Expr, token::Token, Walkable};
+use crate::{
+    expr::Expr,
+    token::{Span, Token},
+    Walkable,
+};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Stmt {
@@ -9,34 +14,41 @@ pub enum Stmt {
     Block(Block),
     If(If),
     While(While),
-    For(For),
     Func(Func),
     Return(Return),
+    Import(Import),
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Print {
+    pub span: Span,
     pub expr: Box<Expr>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Expression {
+    pub span: Span,
     pub expr: Box<Expr>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Var {
+    pub span: Span,
     pub name: Token,
     pub initializer: Option<Box<Expr>>,
+    /// The local slot assigned by the resolver; `None` for globals.
+    pub slot: Cell<Option<usize>>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Block {
+    pub span: Span,
     pub statements: Vec<Stmt>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct If {
+    pub span: Span,
     pub condition: Box<Expr>,
     pub then_branch: Box<Stmt>,
     pub else_branch: Option<Box<Stmt>>,
@@ -44,30 +56,60 @@ pub struct If {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct While {
+    pub span: Span,
     pub condition: Box<Expr>,
     pub body: Box<Stmt>,
 }
 
-#[derive(Debug, PartialEq, Clone)]
-pub struct For {
-    pub init: Option<Box<Stmt>>,
-    pub condition: Box<Expr>,
-    pub update: Option<Box<Expr>>,
-    pub body: Box<Stmt>,
-}
-
 #[derive(Debug, PartialEq, Clone)]
 pub struct Func {
+    pub span: Span,
     pub name: Token,
     pub params: Vec<Token>,
-    pub body: Vec<Stmt>,
+    /// The type each parameter is annotated with, as in `fun f(a: number)`;
+    /// checked on calls in extension mode.
+    pub types: Vec<Option<Token>>,
+    pub body: Rc<[Stmt]>,
+    pub slot: Cell<Option<usize>>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Return {
+    pub span: Span,
     pub value: Option<Expr>,
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub struct Import {
+    pub span: Span,
+    pub keyword: Token,
+    pub path: Token,
+    pub alias: Option<Token>,
+    pub slot: Cell<Option<usize>>,
+}
+
+impl Stmt {
+    /// The source region the statement was parsed from.
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Print(print) => print.span,
+            Stmt::Expression(expression) => expression.span,
+            Stmt::Var(var) => var.span,
+            Stmt::Block(block) => block.span,
+            Stmt::If(if_stmt) => if_stmt.span,
+            Stmt::While(while_stmt) => while_stmt.span,
+            Stmt::Func(func) => func.span,
+            Stmt::Return(ret) => ret.span,
+            Stmt::Import(import) => import.span,
+        }
+    }
+
+    /// The line the statement starts on.
+    pub fn line(&self) -> usize {
+        self.span().line
+    }
+}
+
 impl<V: StmtVisitor<T>, T> Walkable<V, T> for Stmt {
     fn walk(&self, visitor: &mut V) -> T {
         match self {
@@ -77,9 +119,9 @@ impl<V: StmtVisitor<T>, T> Walkable<V, T> for Stmt {
             Stmt::Block(block) => visitor.visit_block(block),
             Stmt::If(if_stmt) => visitor.visit_if(if_stmt),
             Stmt::While(while_stmt) => visitor.visit_while(while_stmt),
-            Stmt::For(for_stmt) => visitor.visit_for(for_stmt),
             Stmt::Func(func) => visitor.visit_func(func),
             Stmt::Return(ret) => visitor.visit_return(ret),
+            Stmt::Import(import) => visitor.visit_import(import),
         }
     }
 }
@@ -97,9 +139,74 @@ pub trait StmtVisitor<T> {
 
     fn visit_while(&mut self, stmt: &While) -> T;
 
-    fn visit_for(&mut self, stmt: &For) -> T;
-
     fn visit_func(&mut self, stmt: &Func) -> T;
 
     fn visit_return(&mut self, stmt: &Return) -> T;
+
+    fn visit_import(&mut self, stmt: &Import) -> T;
+}
+
+/// Prints statements as S-expressions, in the same style as `Expr`.
+impl std::fmt::Display for Stmt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stmt::Print(Print { expr, .. }) => write!(f, "(print {})", expr),
+            Stmt::Expression(Expression { expr, .. }) => write!(f, "(; {})", expr),
+            Stmt::Var(Var {
+                name, initializer, ..
+            }) => match initializer {
+                Some(initializer) => write!(f, "(var {} {})", name.lexeme, initializer),
+                None => write!(f, "(var {})", name.lexeme),
+            },
+            Stmt::Block(Block { statements, .. }) => write!(f, "(block{})", list(statements)),
+            Stmt::If(If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            }) => match else_branch {
+                Some(else_branch) => {
+                    write!(f, "(if {} {} {})", condition, then_branch, else_branch)
+                }
+                None => write!(f, "(if {} {})", condition, then_branch),
+            },
+            Stmt::While(While {
+                condition, body, ..
+            }) => write!(f, "(while {} {})", condition, body),
+            Stmt::Func(Func {
+                name,
+                params,
+                types,
+                body,
+                ..
+            }) => write!(
+                f,
+                "(fun {} ({}){})",
+                name.lexeme,
+                params
+                    .iter()
+                    .zip(types)
+                    .map(|(param, ty)| match ty {
+                        Some(ty) => format!("{}:{}", param.lexeme, ty.lexeme),
+                        None => param.lexeme.clone(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                list(body)
+            ),
+            Stmt::Return(Return { value, .. }) => match value {
+                Some(value) => write!(f, "(return {})", value),
+                None => f.write_str("(return)"),
+            },
+            Stmt::Import(Import { path, alias, .. }) => match alias {
+                Some(alias) => write!(f, "(import {} as {})", path.lexeme, alias.lexeme),
+                None => write!(f, "(import {})", path.lexeme),
+            },
+        }
+    }
+}
+
+/// Statements each preceded by a space, for the tail of an S-expression.
+fn list(stmts: &[Stmt]) -> String {
+    stmts.iter().map(|stmt| format!(" {}", stmt)).collect()
 }
@@ -0,0 +1,63 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{Mutex, OnceLock},
+};
+
+/// An interned identifier. Comparing and hashing symbols is as cheap as for a
+/// `u32`, and the text is only looked up when a name has to be displayed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+type Slot = OnceLock<&'static str>;
+
+/// The names by symbol, in chunks that double in size: chunk `k` holds the
+/// `2^k` symbols from `2^k - 1` on. Chunks and names are only ever added, so
+/// `as_str` can read them without taking the lock.
+static CHUNKS: [OnceLock<&'static [Slot]>; 32] = [const { OnceLock::new() }; 32];
+
+/// The chunk and index within it of a symbol's name.
+fn position(symbol: Symbol) -> (usize, usize) {
+    let n = symbol.0 as usize + 1;
+    let chunk = n.ilog2() as usize;
+    (chunk, n - (1 << chunk))
+}
+
+/// Symbols by name, which `intern` looks up and appends to under the lock.
+fn ids() -> &'static Mutex<HashMap<&'static str, Symbol>> {
+    static IDS: OnceLock<Mutex<HashMap<&'static str, Symbol>>> = OnceLock::new();
+    IDS.get_or_init(Default::default)
+}
+
+impl Symbol {
+    pub fn intern(name: &str) -> Symbol {
+        let mut ids = ids().lock().unwrap();
+        if let Some(symbol) = ids.get(name) {
+            return *symbol;
+        }
+        // Identifiers live for the whole run, so leaking them is fine and
+        // lets `as_str` hand out plain `&'static str`s.
+        let name: &'static str = Box::leak(name.into());
+        let symbol = Symbol(ids.len() as u32);
+        let (chunk, index) = position(symbol);
+        let slots =
+            CHUNKS[chunk].get_or_init(|| Box::leak((0..1 << chunk).map(|_| Slot::new()).collect()));
+        slots[index].set(name).unwrap();
+        ids.insert(name, symbol);
+        symbol
+    }
+
+    pub fn as_str(self) -> &'static str {
+        let (chunk, index) = position(self);
+        CHUNKS[chunk]
+            .get()
+            .and_then(|slots| slots[index].get())
+            .unwrap()
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
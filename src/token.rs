@@ -1,13 +1,34 @@
 use std::fmt::Display;
 
+use crate::{number::Number, symbol::Symbol};
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Token {
     pub value: TokenValue,
     pub lexeme: String,
     pub line: usize,
+    /// Byte offset of the lexeme in the source.
+    pub start: usize,
+}
+
+/// A region of the source: the bytes `start..end`, beginning on `line`.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
 }
 
-pub type Number = f64;
+impl Span {
+    /// The span covering both `self` and `other`, which comes later.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+            line: self.line,
+        }
+    }
+}
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum TokenValue {
@@ -35,9 +56,15 @@ pub enum TokenValue {
     GreaterEqual,
     Less,
     LessEqual,
+    PipeGreater,
+    QuestionDot,
+    QuestionQuestion,
+    /// `in`, which the lexer scans as an identifier and the parser turns
+    /// into an operator where one is expected.
+    In,
 
     // Literals.
-    Identifier,
+    Identifier(Symbol),
     String(String),
     Number(Number),
 
@@ -50,6 +77,9 @@ pub enum TokenValue {
     Fun,
     For,
     If,
+    /// `import`, which the lexer scans as an identifier and the parser
+    /// turns into a keyword where a statement starts with it.
+    Import,
     Nil,
     Or,
     Print,
@@ -108,8 +138,12 @@ impl Display for TokenValue {
             TokenValue::GreaterEqual => write!(f, "GREATER_EQUAL"),
             TokenValue::Less => write!(f, "LESS"),
             TokenValue::LessEqual => write!(f, "LESS_EQUAL"),
+            TokenValue::PipeGreater => write!(f, "PIPE_GREATER"),
+            TokenValue::QuestionDot => write!(f, "QUESTION_DOT"),
+            TokenValue::QuestionQuestion => write!(f, "QUESTION_QUESTION"),
+            TokenValue::In => write!(f, "IN"),
 
-            TokenValue::Identifier => write!(f, "IDENTIFIER"),
+            TokenValue::Identifier(_) => write!(f, "IDENTIFIER"),
             TokenValue::String(_) => write!(f, "STRING"),
             TokenValue::Number(_) => write!(f, "NUMBER"),
 
@@ -121,6 +155,7 @@ impl Display for TokenValue {
             TokenValue::Fun => write!(f, "FUN"),
             TokenValue::For => write!(f, "FOR"),
             TokenValue::If => write!(f, "IF"),
+            TokenValue::Import => write!(f, "IMPORT"),
             TokenValue::Nil => write!(f, "NIL"),
             TokenValue::Or => write!(f, "OR"),
             TokenValue::Print => write!(f, "PRINT"),
@@ -137,11 +172,31 @@ impl Display for TokenValue {
 }
 
 impl Token {
-    pub fn new(value: TokenValue, lexeme: String, line: usize) -> Token {
+    pub fn new(value: TokenValue, lexeme: String, line: usize, start: usize) -> Token {
         Token {
             value,
             lexeme,
             line,
+            start,
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        Span {
+            start: self.start,
+            end: self.start + self.lexeme.len(),
+            line: self.line,
+        }
+    }
+}
+
+impl Token {
+    /// The interned name of an identifier (or keyword used as a name, like
+    /// `this`).
+    pub fn symbol(&self) -> Symbol {
+        match self.value {
+            TokenValue::Identifier(symbol) => symbol,
+            _ => Symbol::intern(&self.lexeme),
         }
     }
 }
@@ -150,13 +205,7 @@ impl std::fmt::Display for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self.value {
             TokenValue::String(s) => write!(f, "{} {} {}", self.value, self.lexeme, s),
-            TokenValue::Number(n) => {
-                if n % 1.0 == 0.0 {
-                    write!(f, "{} {} {:.1}", self.value, self.lexeme, n)
-                } else {
-                    write!(f, "{} {} {}", self.value, self.lexeme, n)
-                }
-            }
+            TokenValue::Number(n) => write!(f, "{} {} {}", self.value, self.lexeme, n.literal()),
             _ => write!(f, "{} {} null", self.value, self.lexeme),
         }
     }
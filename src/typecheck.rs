@@ -0,0 +1,463 @@
+use std::collections::HashMap;
+
+use crate::{
+    explain::Coded,
+    expr::{Expr, LiteralValue},
+    native::NATIVES,
+    stmt::Stmt,
+    symbol::Symbol,
+    token::TokenValue,
+};
+
+/// What a variable or expression is known to hold. Inference is
+/// flow-insensitive: a variable's type covers every value ever assigned to
+/// it, anywhere in the program.
+#[derive(Debug, Clone, PartialEq)]
+enum Type {
+    /// Nothing assigned yet; only seen while inference is still running.
+    Never,
+    Nil,
+    Boolean,
+    Number,
+    String,
+    /// A function, with its name and arity when every function the value can
+    /// be takes the same fixed number of arguments.
+    Function(Option<(Symbol, usize)>),
+    Module,
+    /// Could be anything.
+    Any,
+}
+
+impl Type {
+    /// The type covering values of both `self` and `other`.
+    fn join(&self, other: &Type) -> Type {
+        match (self, other) {
+            (Type::Never, t) | (t, Type::Never) => t.clone(),
+            (a, b) if a == b => a.clone(),
+            (Type::Function(_), Type::Function(_)) => Type::Function(None),
+            _ => Type::Any,
+        }
+    }
+
+    /// Whether every value of the type is a number, or none is. `Never` and
+    /// `Any` are neither.
+    fn is_number(&self) -> Option<bool> {
+        match self {
+            Type::Never | Type::Any => None,
+            t => Some(*t == Type::Number),
+        }
+    }
+
+    fn is_string(&self) -> Option<bool> {
+        match self {
+            Type::Never | Type::Any => None,
+            t => Some(*t == Type::String),
+        }
+    }
+
+    fn is_function(&self) -> Option<bool> {
+        match self {
+            Type::Never | Type::Any => None,
+            t => Some(matches!(t, Type::Function(_))),
+        }
+    }
+
+    /// The name the runtime uses for values of the type.
+    fn name(&self) -> &'static str {
+        match self {
+            Type::Nil => "nil",
+            Type::Boolean => "boolean",
+            Type::Number => "number",
+            Type::String => "string",
+            Type::Function(_) => "function",
+            Type::Module => "module",
+            Type::Never | Type::Any => "value",
+        }
+    }
+}
+
+/// The type a parameter annotation names.
+fn annotated(name: &str) -> Type {
+    match name {
+        "boolean" => Type::Boolean,
+        "number" => Type::Number,
+        "string" => Type::String,
+        "function" => Type::Function(None),
+        "module" => Type::Module,
+        _ => Type::Any,
+    }
+}
+
+/// A type error found before running: the runtime error the program would
+/// likely raise, and where.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    pub line: usize,
+    /// The code of the runtime error, see `explain::DIAGNOSTICS`.
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl std::fmt::Display for TypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[line {}] Type error: {}", self.line, self.message)
+    }
+}
+
+impl Coded for TypeError {
+    fn code(&self) -> Option<&'static str> {
+        Some(self.code)
+    }
+}
+
+/// Infers the types of the program's variables, then reports operations
+/// whose operands can only have types they reject. Anything not known for
+/// sure, such as parameters and call results, is assumed fine, so what is
+/// reported is almost always an error the program hits if it runs that code.
+///
+/// `extensions` accepts what extension mode does, such as adding a string to
+/// anything.
+pub fn check(stmts: &[Stmt], extensions: bool) -> Vec<TypeError> {
+    let mut checker = Checker {
+        extensions,
+        ..Checker::default()
+    };
+    for native in NATIVES {
+        let name = Symbol::intern(native.name);
+        let arity = (!native.variadic).then_some((name, native.arity));
+        checker.globals.insert(name, Type::Function(arity));
+    }
+    // Variable types only grow, and can grow at most three times (from
+    // `Never` to a type, to a function of unknown arity, to `Any`), so this
+    // ends.
+    loop {
+        checker.changed = false;
+        checker.stmts(stmts);
+        if !checker.changed {
+            break;
+        }
+    }
+    checker.reporting = true;
+    checker.stmts(stmts);
+    checker.errors
+}
+
+enum Var {
+    Global(Symbol),
+    Local(usize),
+}
+
+#[derive(Default)]
+struct Checker {
+    extensions: bool,
+    globals: HashMap<Symbol, Type>,
+    /// The type of every local, by the order it is declared in. Passes walk
+    /// the program in the same order, so a local gets the same index in each.
+    locals: Vec<Type>,
+    /// How many locals have been declared in this pass.
+    declared: usize,
+    scopes: Vec<HashMap<Symbol, usize>>,
+    changed: bool,
+    /// Whether this is the final pass, which reports errors.
+    reporting: bool,
+    errors: Vec<TypeError>,
+}
+
+impl Checker {
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        if self.scopes.is_empty() {
+            self.declared = 0;
+        }
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Print(print) => {
+                self.expr(&print.expr);
+            }
+            Stmt::Expression(expression) => {
+                self.expr(&expression.expr);
+            }
+            Stmt::Var(var) => {
+                let t = match &var.initializer {
+                    Some(initializer) => self.expr(initializer),
+                    None => Type::Nil,
+                };
+                let id = self.declare(var.name.symbol());
+                self.assign(id, &t);
+            }
+            Stmt::Block(block) => {
+                self.scopes.push(HashMap::new());
+                self.stmts(&block.statements);
+                self.scopes.pop();
+            }
+            Stmt::If(if_stmt) => {
+                self.expr(&if_stmt.condition);
+                self.stmt(&if_stmt.then_branch);
+                if let Some(else_branch) = &if_stmt.else_branch {
+                    self.stmt(else_branch);
+                }
+            }
+            Stmt::While(while_stmt) => {
+                self.expr(&while_stmt.condition);
+                self.stmt(&while_stmt.body);
+            }
+            Stmt::Func(func) => {
+                let name = func.name.symbol();
+                let id = self.declare(name);
+                let signature = (name, func.params.len());
+                self.assign(id, &Type::Function(Some(signature)));
+                self.scopes.push(HashMap::new());
+                for (param, ty) in func.params.iter().zip(&func.types) {
+                    // Annotations are only enforced in extension mode.
+                    let t = match ty {
+                        Some(ty) if self.extensions => annotated(&ty.lexeme),
+                        _ => Type::Any,
+                    };
+                    let id = self.declare(param.symbol());
+                    self.assign(id, &t);
+                }
+                self.stmts(&func.body);
+                self.scopes.pop();
+            }
+            Stmt::Return(ret) => {
+                if let Some(value) = &ret.value {
+                    self.expr(value);
+                }
+            }
+            Stmt::Import(import) => {
+                if let Some(alias) = &import.alias {
+                    let id = self.declare(alias.symbol());
+                    self.assign(id, &Type::Module);
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Literal(literal) => match &literal.value {
+                LiteralValue::String(_) => Type::String,
+                LiteralValue::Number(_) | LiteralValue::Integer(_) => Type::Number,
+                LiteralValue::True | LiteralValue::False => Type::Boolean,
+                LiteralValue::Nil => Type::Nil,
+            },
+            Expr::Grouping(grouping) => self.expr(&grouping.expr),
+            Expr::Unary(unary) => {
+                let right = self.expr(&unary.right);
+                if unary.operator.value == TokenValue::Bang {
+                    return Type::Boolean;
+                }
+                if right.is_number() == Some(false) {
+                    self.error(unary.operator.line, "R0002", "Operand must be a number.");
+                }
+                Type::Number
+            }
+            Expr::Binary(binary) if binary.operator.value == TokenValue::QuestionQuestion => {
+                let left = self.expr(&binary.left);
+                let right = self.expr(&binary.right);
+                match left {
+                    Type::Nil => right,
+                    Type::Never | Type::Any => Type::Any,
+                    left => left,
+                }
+            }
+            Expr::Binary(binary) => {
+                let left = self.expr(&binary.left);
+                let right = self.expr(&binary.right);
+                let lines = (binary.left.span().line, binary.right.span().line);
+                self.binary(&binary.operator.value, left, right, lines)
+            }
+            Expr::Assign(assign) => {
+                let t = self.expr(&assign.value);
+                let id = self.lookup(assign.name.symbol());
+                self.assign(id, &t);
+                t
+            }
+            Expr::Variable(variable) => match self.lookup(variable.name.symbol()) {
+                Var::Global(name) => self.globals.get(&name).cloned().unwrap_or(Type::Never),
+                Var::Local(index) => self.locals[index].clone(),
+            },
+            Expr::Call(call) => {
+                let callee = self.expr(&call.callee);
+                for arg in &call.args {
+                    self.expr(arg);
+                }
+                match callee {
+                    // Named arguments are matched up at runtime.
+                    Type::Function(Some(_)) if call.names.iter().any(Option::is_some) => {}
+                    Type::Function(Some((name, arity))) if arity != call.args.len() => {
+                        let message = format!(
+                            "Expected {} arguments but got {} in call to '{}'.",
+                            arity,
+                            call.args.len(),
+                            name
+                        );
+                        self.error(call.paren.line, "R0006", &message);
+                    }
+                    callee if callee.is_function() == Some(false) => {
+                        let message = format!(
+                            "Can only call functions and classes, got {}.",
+                            callee.name()
+                        );
+                        self.error(call.callee.span().line, "R0005", &message);
+                    }
+                    _ => {}
+                }
+                Type::Any
+            }
+            Expr::Get(get) => {
+                let object = self.expr(&get.object);
+                if get.optional && object == Type::Nil {
+                    return Type::Nil;
+                }
+                if !matches!(object, Type::Module | Type::Never | Type::Any) {
+                    self.error(get.name.line, "R0008", "Only instances have properties.");
+                }
+                Type::Any
+            }
+        }
+    }
+
+    /// The type of a binary operation, reporting operands it rejects. Like
+    /// at runtime, the error points at the left operand unless it has a type
+    /// the operator accepts.
+    fn binary(
+        &mut self,
+        operator: &TokenValue,
+        left: Type,
+        right: Type,
+        (left_line, right_line): (usize, usize),
+    ) -> Type {
+        let strings = |t: &Type| match (t.is_number(), t.is_string()) {
+            (Some(number), Some(string)) => Some(number || string),
+            _ => None,
+        };
+        let (left_ok, right_ok, (code, message), result) = match operator {
+            TokenValue::Plus => {
+                let result = match (&left, &right) {
+                    (Type::Number, Type::Number) => Type::Number,
+                    (Type::String, _) | (_, Type::String) => Type::String,
+                    _ => Type::Any,
+                };
+                let (left_ok, right_ok) =
+                    if self.extensions && (left == Type::String || right == Type::String) {
+                        (Some(true), Some(true))
+                    } else if left == Type::Number || left == Type::String {
+                        // The right operand has to match the left one.
+                        (
+                            Some(true),
+                            (right != Type::Never && right != Type::Any).then(|| right == left),
+                        )
+                    } else {
+                        (strings(&left), strings(&right))
+                    };
+                let error = ("R0004", "Operands must be two numbers or two strings.");
+                (left_ok, right_ok, error, result)
+            }
+            TokenValue::Star
+                if self.extensions
+                    && matches!(
+                        (&left, &right),
+                        (Type::String, Type::Number) | (Type::Number, Type::String)
+                    ) =>
+            {
+                return Type::String;
+            }
+            TokenValue::Minus | TokenValue::Star | TokenValue::Slash => (
+                left.is_number(),
+                right.is_number(),
+                ("R0003", "Operands must be numbers."),
+                Type::Number,
+            ),
+            TokenValue::Greater
+            | TokenValue::GreaterEqual
+            | TokenValue::Less
+            | TokenValue::LessEqual => {
+                if self.extensions {
+                    (
+                        strings(&left),
+                        strings(&right),
+                        ("R0004", "Operands must be two numbers or two strings."),
+                        Type::Boolean,
+                    )
+                } else {
+                    (
+                        left.is_number(),
+                        right.is_number(),
+                        ("R0003", "Operands must be numbers."),
+                        Type::Boolean,
+                    )
+                }
+            }
+            TokenValue::In => (
+                left.is_string(),
+                right.is_string(),
+                ("R0047", "Operands of 'in' must be two strings."),
+                Type::Boolean,
+            ),
+            TokenValue::EqualEqual | TokenValue::BangEqual => return Type::Boolean,
+            _ => {
+                return if left == Type::Boolean && right == Type::Boolean {
+                    Type::Boolean
+                } else {
+                    Type::Any
+                }
+            }
+        };
+        if left_ok == Some(false) {
+            self.error(left_line, code, message);
+        } else if right_ok == Some(false) {
+            self.error(right_line, code, message);
+        }
+        result
+    }
+
+    fn declare(&mut self, name: Symbol) -> Var {
+        let Some(scope) = self.scopes.last_mut() else {
+            return Var::Global(name);
+        };
+        let index = self.declared;
+        self.declared += 1;
+        if index == self.locals.len() {
+            self.locals.push(Type::Never);
+        }
+        scope.insert(name, index);
+        Var::Local(index)
+    }
+
+    /// A variable by name: the innermost local, or else the global, declared
+    /// or not.
+    fn lookup(&self, name: Symbol) -> Var {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name).copied())
+            .map_or(Var::Global(name), Var::Local)
+    }
+
+    fn assign(&mut self, var: Var, t: &Type) {
+        let current = match var {
+            Var::Global(name) => self.globals.entry(name).or_insert(Type::Never),
+            Var::Local(index) => &mut self.locals[index],
+        };
+        let joined = current.join(t);
+        if joined != *current {
+            *current = joined;
+            self.changed = true;
+        }
+    }
+
+    fn error(&mut self, line: usize, code: &'static str, message: &str) {
+        if self.reporting {
+            self.errors.push(TypeError {
+                line,
+                code,
+                message: message.to_string(),
+            });
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+
+use crate::{expr::Expr, stmt::Stmt};
+
+/// A statement or expression, for passes that treat every node alike.
+#[derive(Clone, Copy, Debug)]
+pub enum Node<'a> {
+    Stmt(&'a Stmt),
+    Expr(&'a Expr),
+}
+
+impl Node<'_> {
+    /// The name of the node's variant, e.g. `"while"` or `"binary"`.
+    pub fn kind(self) -> &'static str {
+        match self {
+            Node::Stmt(stmt) => match stmt {
+                Stmt::Print(_) => "print",
+                Stmt::Expression(_) => "expression",
+                Stmt::Var(_) => "var",
+                Stmt::Block(_) => "block",
+                Stmt::If(_) => "if",
+                Stmt::While(_) => "while",
+                Stmt::Func(_) => "fun",
+                Stmt::Return(_) => "return",
+                Stmt::Import(_) => "import",
+            },
+            Node::Expr(expr) => match expr {
+                Expr::Literal(_) => "literal",
+                Expr::Grouping(_) => "grouping",
+                Expr::Unary(_) => "unary",
+                Expr::Binary(_) => "binary",
+                Expr::Assign(_) => "assign",
+                Expr::Variable(_) => "variable",
+                Expr::Call(_) => "call",
+                Expr::Get(_) => "get",
+            },
+        }
+    }
+}
+
+/// A read-only pass over the AST. `visit` defaults to visiting the node's
+/// children, so a pass only overrides it to look at nodes, calling
+/// `walk_children` to keep descending. Unlike `StmtVisitor` and
+/// `ExprVisitor`, no method has to be written per node type.
+pub trait Visitor {
+    fn visit(&mut self, node: Node) {
+        walk_children(self, node);
+    }
+
+    fn visit_all(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.visit(Node::Stmt(stmt));
+        }
+    }
+}
+
+/// Visits the direct children of `node`, in source order.
+pub fn walk_children<V: Visitor + ?Sized>(visitor: &mut V, node: Node) {
+    match node {
+        Node::Stmt(stmt) => match stmt {
+            Stmt::Print(print) => visitor.visit(Node::Expr(&print.expr)),
+            Stmt::Expression(expression) => visitor.visit(Node::Expr(&expression.expr)),
+            Stmt::Var(var) => {
+                if let Some(initializer) = &var.initializer {
+                    visitor.visit(Node::Expr(initializer));
+                }
+            }
+            Stmt::Block(block) => visitor.visit_all(&block.statements),
+            Stmt::If(if_stmt) => {
+                visitor.visit(Node::Expr(&if_stmt.condition));
+                visitor.visit(Node::Stmt(&if_stmt.then_branch));
+                if let Some(else_branch) = &if_stmt.else_branch {
+                    visitor.visit(Node::Stmt(else_branch));
+                }
+            }
+            Stmt::While(while_stmt) => {
+                visitor.visit(Node::Expr(&while_stmt.condition));
+                visitor.visit(Node::Stmt(&while_stmt.body));
+            }
+            Stmt::Func(func) => visitor.visit_all(&func.body),
+            Stmt::Return(ret) => {
+                if let Some(value) = &ret.value {
+                    visitor.visit(Node::Expr(value));
+                }
+            }
+            Stmt::Import(_) => {}
+        },
+        Node::Expr(expr) => match expr {
+            Expr::Literal(_) | Expr::Variable(_) => {}
+            Expr::Grouping(grouping) => visitor.visit(Node::Expr(&grouping.expr)),
+            Expr::Unary(unary) => visitor.visit(Node::Expr(&unary.right)),
+            Expr::Binary(binary) => {
+                visitor.visit(Node::Expr(&binary.left));
+                visitor.visit(Node::Expr(&binary.right));
+            }
+            Expr::Assign(assign) => visitor.visit(Node::Expr(&assign.value)),
+            Expr::Call(call) => {
+                visitor.visit(Node::Expr(&call.callee));
+                for arg in &call.args {
+                    visitor.visit(Node::Expr(arg));
+                }
+            }
+            Expr::Get(get) => visitor.visit(Node::Expr(&get.object)),
+        },
+    }
+}
+
+/// Counts the nodes of each kind in a program.
+#[derive(Debug, Default)]
+pub struct NodeCount {
+    pub counts: HashMap<&'static str, usize>,
+}
+
+impl Visitor for NodeCount {
+    fn visit(&mut self, node: Node) {
+        *self.counts.entry(node.kind()).or_default() += 1;
+        walk_children(self, node);
+    }
+}